@@ -1,4 +0,0 @@
-struct SimVal<T> {
-    value: T,
-    units: 
-}
\ No newline at end of file
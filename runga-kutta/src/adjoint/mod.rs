@@ -0,0 +1,196 @@
+use crate::error::SolverError;
+use crate::integrator::Stats;
+use crate::jacobian::gradient;
+use crate::output::SaveAt;
+use crate::runge_kutta;
+use crate::solver::SolverOptions;
+use crate::state::{to_dvector, OdeState};
+use nalgebra::DVector;
+
+type TerminalCost<'a, Ty, Tp> = Box<dyn Fn(&Ty, &Tp) -> f64 + 'a>;
+type RunningCost<'a, Ty, Tp> = Box<dyn Fn(f64, &Ty, &Tp) -> f64 + 'a>;
+
+/// Scalar cost `g(y(t_end), p) + integral of r(t, y, p) dt` of a trajectory
+pub struct Objective<'a, Ty, Tp> {
+    terminal: Option<TerminalCost<'a, Ty, Tp>>,
+    running: Option<RunningCost<'a, Ty, Tp>>,
+}
+
+impl<'a, Ty, Tp> Default for Objective<'a, Ty, Tp> {
+    fn default() -> Self {
+        Self {
+            terminal: None,
+            running: None,
+        }
+    }
+}
+
+impl<'a, Ty, Tp> Objective<'a, Ty, Tp> {
+    /// Cost of the final state
+    pub fn terminal(g: impl Fn(&Ty, &Tp) -> f64 + 'a) -> Self {
+        Self::default().with_terminal(g)
+    }
+
+    /// Cost integrated along the trajectory
+    pub fn running(r: impl Fn(f64, &Ty, &Tp) -> f64 + 'a) -> Self {
+        Self::default().with_running(r)
+    }
+
+    pub fn with_terminal(mut self, g: impl Fn(&Ty, &Tp) -> f64 + 'a) -> Self {
+        self.terminal = Some(Box::new(g));
+        self
+    }
+
+    pub fn with_running(mut self, r: impl Fn(f64, &Ty, &Tp) -> f64 + 'a) -> Self {
+        self.running = Some(Box::new(r));
+        self
+    }
+}
+
+/// Value and gradient of an `Objective`
+#[derive(Debug, Clone)]
+pub struct AdjointGradient {
+    pub cost: f64,
+    /// Gradient with respect to the initial state
+    pub dy0: DVector<f64>,
+    /// Gradient with respect to the parameters
+    pub dp: DVector<f64>,
+    pub forward: Stats,
+    pub backward: Stats,
+}
+
+/// Gradient of `objective` with respect to `y0` and `p` by the continuous
+/// adjoint method. The forward solution is stored at every step and
+/// interpolated during the backward solve of
+/// `dlambda = -J_y' lambda - dr/dy` and `dmu = -J_p' lambda - dr/dp`, from
+/// `lambda = dg/dy` and `mu = dg/dp` at `t_end`. The vector-Jacobian products
+/// are taken by central differences. Both solves use `options`, apart from
+/// `save_at`.
+pub fn adjoint_gradient<Ty: OdeState, Tp: OdeState>(
+    f: impl Fn(&mut Ty, &Ty, f64, &Tp),
+    y0: Ty,
+    t0: f64,
+    p: Tp,
+    t_end: f64,
+    objective: &Objective<Ty, Tp>,
+    options: SolverOptions,
+) -> Result<AdjointGradient, SolverError> {
+    let (n, m) = (y0.dim(), p.dim());
+    let forward = runge_kutta(
+        &f,
+        y0,
+        t0,
+        p.clone(),
+        t_end,
+        options.clone().save_at(SaveAt::EveryStep),
+    )?;
+    let (_, y_end) = forward.last().expect("solution has at least one point");
+
+    // backward state [lambda, mu, accumulated running cost]
+    let mut z_end = DVector::zeros(n + m + 1);
+    let mut cost = 0.0;
+    if let Some(g) = &objective.terminal {
+        cost = g(y_end, &p);
+        z_end
+            .rows_mut(0, n)
+            .copy_from(&gradient(|y: &Ty| g(y, &p), y_end));
+        z_end
+            .rows_mut(n, m)
+            .copy_from(&gradient(|p: &Tp| g(y_end, p), &p));
+    }
+
+    // stage times can round just outside the forward interval
+    let (lo, hi) = (t0.min(t_end), t0.max(t_end));
+    let adjoint = |dz: &mut DVector<f64>, z: &DVector<f64>, t: f64, p: &Tp| {
+        let y = forward
+            .interpolate(t.clamp(lo, hi))
+            .expect("clamped times lie within the forward solution");
+        let lambda = z.rows(0, n);
+        // lambda' f(y, t, p), whose gradients are the vector-Jacobian products
+        let lambda_f = |y: &Ty, p: &Tp| {
+            let mut dy = y.zeros_like();
+            f(&mut dy, y, t, p);
+            lambda.dot(&to_dvector(&dy))
+        };
+        let mut dlambda = -gradient(|y: &Ty| lambda_f(y, p), &y);
+        let mut dmu = -gradient(|p: &Tp| lambda_f(&y, p), p);
+        let mut dq = 0.0;
+        if let Some(r) = &objective.running {
+            dlambda -= gradient(|y: &Ty| r(t, y, p), &y);
+            dmu -= gradient(|p: &Tp| r(t, &y, p), p);
+            dq = -r(t, &y, p);
+        }
+        dz.rows_mut(0, n).copy_from(&dlambda);
+        dz.rows_mut(n, m).copy_from(&dmu);
+        dz[n + m] = dq;
+    };
+    let backward = runge_kutta(adjoint, z_end, t_end, p, t0, options.save_at(SaveAt::Final))?;
+    let (_, z0) = backward.last().expect("solution has at least one point");
+
+    Ok(AdjointGradient {
+        cost: cost + z0[n + m],
+        dy0: z0.rows(0, n).into_owned(),
+        dp: z0.rows(n, m).into_owned(),
+        forward: forward.stats,
+        backward: backward.stats,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::butcher::Tableau;
+    use crate::sensitivity::forward_sensitivity;
+    use nalgebra::Vector2;
+
+    fn decay(dy: &mut f64, y: &f64, _t: f64, k: &f64) {
+        *dy = -k * y;
+    }
+
+    #[test]
+    fn test_terminal_cost() {
+        // J = y(T)^2 / 2 with y(T) = y0 exp(-k T)
+        let (y0, k, t1) = (2.0, 0.7, 3.0);
+        let objective = Objective::terminal(|y: &f64, _k: &f64| 0.5 * y * y);
+        let options = SolverOptions::default().tolerance(1e-10, 1e-12);
+        let grad = adjoint_gradient(decay, y0, 0.0, k, t1, &objective, options).unwrap();
+        let y1 = y0 * (-k * t1).exp();
+        assert!((grad.cost - 0.5 * y1 * y1).abs() < 1e-9);
+        assert!((grad.dy0[0] - y1 * (-k * t1).exp()).abs() < 1e-7);
+        assert!((grad.dp[0] + t1 * y1 * y1).abs() < 1e-7);
+    }
+
+    #[test]
+    fn test_running_cost() {
+        // J = integral of y from 0 to T
+        let (y0, k, t1) = (2.0, 0.7, 3.0);
+        let objective = Objective::running(|_t, y: &f64, _k: &f64| *y);
+        let options = SolverOptions::new(Tableau::Tsit5).tolerance(1e-10, 1e-12);
+        let grad = adjoint_gradient(decay, y0, 0.0, k, t1, &objective, options).unwrap();
+        let e = (-k * t1).exp();
+        assert!((grad.cost - y0 * (1.0 - e) / k).abs() < 1e-7);
+        assert!((grad.dy0[0] - (1.0 - e) / k).abs() < 1e-7);
+        assert!((grad.dp[0] - y0 * (t1 * e / k - (1.0 - e) / (k * k))).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_matches_forward_sensitivity() {
+        // damped oscillator with p = (stiffness, damping)
+        let f = |dy: &mut Vector2<f64>, y: &Vector2<f64>, _t: f64, p: &Vector2<f64>| {
+            *dy = Vector2::new(y[1], -p[0] * y[0] - p[1] * y[1]);
+        };
+        let y0 = Vector2::new(1.0, 0.5);
+        let p = Vector2::new(4.0, 0.3);
+        let t1 = 2.0;
+        let options = SolverOptions::default().tolerance(1e-10, 1e-12);
+        let objective = Objective::terminal(|y: &Vector2<f64>, _p: &Vector2<f64>| y[0]);
+        let grad = adjoint_gradient(f, y0, 0.0, p, t1, &objective, options.clone()).unwrap();
+        let sens = forward_sensitivity(f, y0, 0.0, p, t1, options).unwrap();
+        let stm = sens.stm.last().unwrap();
+        let s = sens.sensitivity.last().unwrap();
+        for j in 0..2 {
+            assert!((grad.dy0[j] - stm[(0, j)]).abs() < 1e-6);
+            assert!((grad.dp[j] - s[(0, j)]).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,484 @@
+use crate::error::SolverError;
+use crate::runge_kutta;
+use crate::signal::Signal;
+use crate::solution::Solution;
+use crate::solver::SolverOptions;
+use nalgebra::DVector;
+
+/// `g(out, x, u, t)` writes the outputs of a block; `h(dx, x, u, t)` its
+/// state derivatives
+type BlockFunction<'a> = Box<dyn Fn(&mut [f64], &[f64], &[f64], f64) + 'a>;
+
+/// A subsystem with named inputs and outputs and internal states
+pub struct Block<'a> {
+    name: String,
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+    x0: Vec<f64>,
+    output: BlockFunction<'a>,
+    derivative: BlockFunction<'a>,
+    feedthrough: bool,
+}
+
+impl<'a> Block<'a> {
+    /// A block without ports or states; add them with the builder methods
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            inputs: vec![],
+            outputs: vec![],
+            x0: vec![],
+            output: Box::new(|_, _, _, _| {}),
+            derivative: Box::new(|_, _, _, _| {}),
+            feedthrough: true,
+        }
+    }
+
+    pub fn inputs(mut self, names: &[&str]) -> Self {
+        self.inputs = names.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    pub fn outputs(mut self, names: &[&str]) -> Self {
+        self.outputs = names.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    /// Internal states with their initial values
+    pub fn states(mut self, x0: Vec<f64>) -> Self {
+        self.x0 = x0;
+        self
+    }
+
+    /// `g(out, x, u, t)` computing the outputs from the states and inputs
+    pub fn output(mut self, g: impl Fn(&mut [f64], &[f64], &[f64], f64) + 'a) -> Self {
+        self.output = Box::new(g);
+        self
+    }
+
+    /// `h(dx, x, u, t)` computing the derivatives of the states
+    pub fn derivative(mut self, h: impl Fn(&mut [f64], &[f64], &[f64], f64) + 'a) -> Self {
+        self.derivative = Box::new(h);
+        self
+    }
+
+    /// Whether the outputs depend on the inputs directly, rather than only
+    /// through the states. Blocks have direct feedthrough unless told
+    /// otherwise; only loops through such blocks are algebraic.
+    pub fn feedthrough(mut self, feedthrough: bool) -> Self {
+        self.feedthrough = feedthrough;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Where a block input takes its value from
+enum Source {
+    Output { block: usize, port: usize },
+    External(Signal),
+}
+
+/// Blocks and the connections between their ports. Ports are named
+/// `"block.port"`.
+#[derive(Default)]
+pub struct Diagram<'a> {
+    blocks: Vec<Block<'a>>,
+    connections: Vec<(String, String)>,
+    external: Vec<(String, Signal)>,
+}
+
+impl<'a> Diagram<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn block(mut self, block: Block<'a>) -> Self {
+        self.blocks.push(block);
+        self
+    }
+
+    /// Feeds the output `from` into the input `to`
+    pub fn connect(mut self, from: &str, to: &str) -> Self {
+        self.connections.push((from.to_string(), to.to_string()));
+        self
+    }
+
+    /// Drives the input `to` with an external signal
+    pub fn input(mut self, to: &str, signal: Signal) -> Self {
+        self.external.push((to.to_string(), signal));
+        self
+    }
+
+    /// Resolves the connections into a single system with the states of all
+    /// blocks, in the order they were added. Fails on unknown or unconnected
+    /// ports, inputs driven twice and algebraic loops.
+    pub fn build(self) -> Result<System<'a>, SolverError> {
+        fn invalid<T>(msg: String) -> Result<T, SolverError> {
+            Err(SolverError::InvalidInput(msg))
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            if self.blocks[..i].iter().any(|b| b.name == block.name) {
+                return invalid(format!("duplicate block {}", block.name));
+            }
+        }
+        let find = |port: &str, outputs: bool| -> Option<(usize, usize)> {
+            let (block, name) = port.split_once('.')?;
+            let b = self.blocks.iter().position(|b| b.name == block)?;
+            let ports = if outputs {
+                &self.blocks[b].outputs
+            } else {
+                &self.blocks[b].inputs
+            };
+            Some((b, ports.iter().position(|p| p == name)?))
+        };
+
+        let mut sources: Vec<Vec<Option<Source>>> = self
+            .blocks
+            .iter()
+            .map(|b| b.inputs.iter().map(|_| None).collect())
+            .collect();
+        let mut assign = |to: &str, source: Source| -> Result<(), SolverError> {
+            let Some((b, i)) = find(to, false) else {
+                return invalid(format!("unknown input {}", to));
+            };
+            if sources[b][i].replace(source).is_some() {
+                return invalid(format!("input {} is driven twice", to));
+            }
+            Ok(())
+        };
+        for (from, to) in &self.connections {
+            let Some((block, port)) = find(from, true) else {
+                return invalid(format!("unknown output {}", from));
+            };
+            assign(to, Source::Output { block, port })?;
+        }
+        for (to, signal) in self.external {
+            assign(&to, Source::External(signal))?;
+        }
+        let mut resolved = vec![];
+        for (b, block_sources) in sources.into_iter().enumerate() {
+            let mut inputs = vec![];
+            for (i, source) in block_sources.into_iter().enumerate() {
+                match source {
+                    Some(source) => inputs.push(source),
+                    None => {
+                        let b = &self.blocks[b];
+                        return invalid(format!(
+                            "input {}.{} is not connected",
+                            b.name, b.inputs[i]
+                        ));
+                    }
+                }
+            }
+            resolved.push(inputs);
+        }
+
+        let order = evaluation_order(&self.blocks, &resolved)?;
+        let mut offsets = vec![0];
+        for block in &self.blocks {
+            offsets.push(offsets.last().unwrap() + block.x0.len());
+        }
+        Ok(System {
+            blocks: self.blocks,
+            sources: resolved,
+            order,
+            offsets,
+        })
+    }
+}
+
+/// Orders the blocks so that each comes after the blocks feeding its direct
+/// feedthrough inputs, failing if they form a cycle
+fn evaluation_order(blocks: &[Block], sources: &[Vec<Source>]) -> Result<Vec<usize>, SolverError> {
+    let n = blocks.len();
+    let depends = |b: usize| -> Vec<usize> {
+        if !blocks[b].feedthrough {
+            return vec![];
+        }
+        sources[b]
+            .iter()
+            .filter_map(|s| match s {
+                Source::Output { block, .. } => Some(*block),
+                Source::External(_) => None,
+            })
+            .collect()
+    };
+    let mut order = vec![];
+    let mut placed = vec![false; n];
+    while order.len() < n {
+        let ready = (0..n).find(|&b| !placed[b] && depends(b).iter().all(|&d| placed[d]));
+        match ready {
+            Some(b) => {
+                placed[b] = true;
+                order.push(b);
+            }
+            None => {
+                // every unplaced block waits on another, so following those
+                // waits from any of them ends up going around a cycle
+                let mut path = vec![(0..n).find(|&b| !placed[b]).unwrap()];
+                let start = loop {
+                    let b = *path.last().unwrap();
+                    let next = depends(b).into_iter().find(|&d| !placed[d]).unwrap();
+                    if let Some(i) = path.iter().position(|&p| p == next) {
+                        break i;
+                    }
+                    path.push(next);
+                };
+                let mut cycle = path.split_off(start);
+                cycle.sort_unstable();
+                let names: Vec<&str> = cycle.iter().map(|&b| blocks[b].name.as_str()).collect();
+                return Err(SolverError::InvalidInput(format!(
+                    "algebraic loop among {}",
+                    names.join(", ")
+                )));
+            }
+        }
+    }
+    Ok(order)
+}
+
+/// A diagram flattened into one ODE over the stacked states of its blocks
+pub struct System<'a> {
+    blocks: Vec<Block<'a>>,
+    sources: Vec<Vec<Source>>,
+    order: Vec<usize>,
+    /// Start of each block's states, with the total at the end
+    offsets: Vec<usize>,
+}
+
+impl System<'_> {
+    /// The initial states of all blocks
+    pub fn x0(&self) -> DVector<f64> {
+        DVector::from_iterator(
+            self.offsets[self.blocks.len()],
+            self.blocks.iter().flat_map(|b| b.x0.iter().copied()),
+        )
+    }
+
+    /// Names `"block.port"` of all outputs, in the order of `outputs`
+    pub fn output_names(&self) -> Vec<String> {
+        self.blocks
+            .iter()
+            .flat_map(|b| b.outputs.iter().map(move |o| format!("{}.{}", b.name, o)))
+            .collect()
+    }
+
+    fn states<'x>(&self, x: &'x DVector<f64>, b: usize) -> &'x [f64] {
+        &x.as_slice()[self.offsets[b]..self.offsets[b + 1]]
+    }
+
+    /// Outputs and inputs of every block at `(x, t)`
+    fn evaluate(&self, x: &DVector<f64>, t: f64) -> (Vec<Vec<f64>>, Vec<Vec<f64>>) {
+        let mut outputs: Vec<Vec<f64>> = self
+            .blocks
+            .iter()
+            .map(|b| vec![0.0; b.outputs.len()])
+            .collect();
+        let gather = |b: usize, outputs: &[Vec<f64>]| -> Vec<f64> {
+            self.sources[b]
+                .iter()
+                .map(|s| match s {
+                    Source::Output { block, port } => outputs[*block][*port],
+                    Source::External(signal) => signal.value(t),
+                })
+                .collect()
+        };
+        for &b in &self.order {
+            let u = gather(b, &outputs);
+            (self.blocks[b].output)(&mut outputs[b], self.states(x, b), &u, t);
+        }
+        let inputs = (0..self.blocks.len())
+            .map(|b| gather(b, &outputs))
+            .collect();
+        (outputs, inputs)
+    }
+
+    /// Derivative of the stacked states
+    pub fn derivative(&self, dx: &mut DVector<f64>, x: &DVector<f64>, t: f64) {
+        let (_, inputs) = self.evaluate(x, t);
+        for (b, block) in self.blocks.iter().enumerate() {
+            let range = self.offsets[b]..self.offsets[b + 1];
+            (block.derivative)(
+                &mut dx.as_mut_slice()[range],
+                self.states(x, b),
+                &inputs[b],
+                t,
+            );
+        }
+    }
+
+    /// All outputs at `(x, t)`, in the order of `output_names`
+    pub fn outputs(&self, x: &DVector<f64>, t: f64) -> DVector<f64> {
+        let (outputs, _) = self.evaluate(x, t);
+        DVector::from_vec(outputs.concat())
+    }
+
+    /// Integrates the system from the initial states of its blocks and logs
+    /// every output at the saved times. Steps stop at the breakpoints of the
+    /// external inputs.
+    pub fn solve(
+        &self,
+        t0: f64,
+        t_end: f64,
+        options: SolverOptions,
+    ) -> Result<SystemSolution, SolverError> {
+        let breakpoints = self.sources.iter().flatten().flat_map(|s| match s {
+            Source::External(signal) => signal.breakpoints(),
+            Source::Output { .. } => vec![],
+        });
+        let options = options.add_tstops(breakpoints);
+        let f = |dx: &mut DVector<f64>, x: &DVector<f64>, t: f64, _: &()| self.derivative(dx, x, t);
+        let solution = runge_kutta(f, self.x0(), t0, (), t_end, options)?;
+        let outputs = solution
+            .t
+            .iter()
+            .zip(&solution.y)
+            .map(|(&t, x)| self.outputs(x, t))
+            .collect();
+        Ok(SystemSolution {
+            solution,
+            names: self.output_names(),
+            outputs,
+        })
+    }
+}
+
+/// The stacked states of a system over time with the outputs of its blocks
+#[derive(Debug, Clone)]
+pub struct SystemSolution {
+    pub solution: Solution<DVector<f64>>,
+    pub names: Vec<String>,
+    /// All outputs at each saved time, in the order of `names`
+    pub outputs: Vec<DVector<f64>>,
+}
+
+impl SystemSolution {
+    /// The history of the output `"block.port"`
+    pub fn output(&self, name: &str) -> Option<Vec<f64>> {
+        let i = self.names.iter().position(|n| n == name)?;
+        Some(self.outputs.iter().map(|o| o[i]).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::SaveAt;
+    use nalgebra::Vector2;
+
+    fn plant() -> Block<'static> {
+        // unit mass on a spring driven by a force
+        Block::new("plant")
+            .inputs(&["force"])
+            .outputs(&["position", "velocity"])
+            .states(vec![1.0, 0.0])
+            .output(|y, x, _u, _t| y.copy_from_slice(x))
+            .derivative(|dx, x, u, _t| {
+                dx[0] = x[1];
+                dx[1] = -x[0] + u[0];
+            })
+            .feedthrough(false)
+    }
+
+    fn gain(name: &str, k: f64) -> Block<'static> {
+        Block::new(name)
+            .inputs(&["in"])
+            .outputs(&["out"])
+            .output(move |y, _x, u, _t| y[0] = k * u[0])
+    }
+
+    #[test]
+    fn test_closed_loop() {
+        // PD control towards a reference step
+        let controller = Block::new("pd")
+            .inputs(&["reference", "position", "velocity"])
+            .outputs(&["force"])
+            .output(|y, _x, u, _t| y[0] = 4.0 * (u[0] - u[1]) - 2.0 * u[2]);
+        let system = Diagram::new()
+            .block(plant())
+            .block(controller)
+            .connect("plant.position", "pd.position")
+            .connect("plant.velocity", "pd.velocity")
+            .connect("pd.force", "plant.force")
+            .input("pd.reference", Signal::Constant(0.5))
+            .build()
+            .unwrap();
+        let options = SolverOptions::default().tolerance(1e-10, 1e-12);
+        let sol = system.solve(0.0, 5.0, options.clone()).unwrap();
+
+        let direct = |dy: &mut Vector2<f64>, y: &Vector2<f64>, _t: f64, _p: &()| {
+            *dy = Vector2::new(y[1], -y[0] + 4.0 * (0.5 - y[0]) - 2.0 * y[1]);
+        };
+        let reference = runge_kutta(direct, Vector2::new(1.0, 0.0), 0.0, (), 5.0, options).unwrap();
+        let x1 = sol.solution.last().unwrap().1;
+        assert!((x1[0] - reference.last().unwrap().1[0]).abs() < 1e-8);
+
+        let force = sol.output("pd.force").unwrap();
+        assert_eq!(force.len(), sol.solution.len());
+        assert_eq!(force[0], -2.0);
+        assert_eq!(
+            sol.names,
+            vec!["plant.position", "plant.velocity", "pd.force"]
+        );
+    }
+
+    #[test]
+    fn test_algebraic_loop_detected() {
+        // c only waits on the loop and is not part of it
+        let looped = Diagram::new()
+            .block(gain("c", 1.0))
+            .block(gain("a", 2.0))
+            .block(gain("b", 0.5))
+            .connect("a.out", "c.in")
+            .connect("a.out", "b.in")
+            .connect("b.out", "a.in")
+            .build();
+        let Err(SolverError::InvalidInput(msg)) = looped else {
+            panic!("expected an algebraic loop");
+        };
+        assert_eq!(msg, "algebraic loop among a, b");
+
+        // the same loop through a block without feedthrough is fine
+        let system = Diagram::new()
+            .block(gain("k", -1.0))
+            .block(plant())
+            .connect("plant.position", "k.in")
+            .connect("k.out", "plant.force")
+            .build()
+            .unwrap();
+        assert_eq!(system.x0().len(), 2);
+    }
+
+    #[test]
+    fn test_external_breakpoints() {
+        // a force stepping on at 1.3 is stepped onto exactly
+        let system = Diagram::new()
+            .block(plant())
+            .input("plant.force", Signal::step(1.3))
+            .build()
+            .unwrap();
+        let options = SolverOptions::default().save_at(SaveAt::EveryStep);
+        let sol = system.solve(0.0, 3.0, options).unwrap();
+        assert!(sol.solution.t.contains(&1.3));
+    }
+
+    #[test]
+    fn test_bad_connections() {
+        let unconnected = Diagram::new().block(plant()).build();
+        assert!(unconnected.is_err());
+        let unknown = Diagram::new()
+            .block(plant())
+            .connect("plant.acceleration", "plant.force")
+            .build();
+        assert!(unknown.is_err());
+        let twice = Diagram::new()
+            .block(plant())
+            .connect("plant.position", "plant.force")
+            .input("plant.force", Signal::Constant(1.0))
+            .build();
+        assert!(twice.is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use nalgebra::{DVector, Matrix2, Matrix2x4, Matrix4, Vector2, Vector4};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rand_distr::StandardNormal;
+
+/// A sampled path of a multidimensional Wiener process. Besides the increments
+/// `dW` it carries `dZ`, the time integral of `W - W(start)` over each
+/// interval, which order 1.5 schemes need. Times that fall inside an already
+/// sampled interval are filled in from the Brownian bridge, so the same path
+/// can be queried on any grid: rejected steps stay unbiased and solutions with
+/// different step sizes can be compared on one path.
+#[derive(Debug, Clone)]
+pub struct BrownianPath {
+    rng: ChaCha8Rng,
+    /// Interval boundaries, starting at the initial time
+    times: Vec<f64>,
+    dw: Vec<DVector<f64>>,
+    dz: Vec<DVector<f64>>,
+    dim: usize,
+}
+
+/// Covariance of `(dW, dZ)` over an interval of length `h`
+fn covariance(h: f64) -> Matrix2<f64> {
+    Matrix2::new(h, h * h / 2.0, h * h / 2.0, h * h * h / 3.0)
+}
+
+impl BrownianPath {
+    pub fn new(dim: usize, t0: f64, seed: u64) -> Self {
+        Self {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            times: vec![t0],
+            dw: vec![],
+            dz: vec![],
+            dim,
+        }
+    }
+
+    /// Number of independent Wiener processes
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn start(&self) -> f64 {
+        self.times[0]
+    }
+
+    /// A fresh `(dW, dZ)` pair for one component over length `h`
+    fn sample(&mut self, h: f64) -> (f64, f64) {
+        let xi1: f64 = self.rng.sample(StandardNormal);
+        let xi2: f64 = self.rng.sample(StandardNormal);
+        let dw = xi1 * h.sqrt();
+        let dz = 0.5 * h.powf(1.5) * (xi1 + xi2 / 3.0_f64.sqrt());
+        (dw, dz)
+    }
+
+    /// Index of the boundary at `t`, sampling or splitting intervals as needed
+    fn boundary(&mut self, t: f64) -> usize {
+        assert!(
+            t >= self.start(),
+            "time {} precedes the start of the Brownian path",
+            t
+        );
+        let last = *self.times.last().unwrap();
+        if t > last {
+            let (dw, dz): (Vec<f64>, Vec<f64>) =
+                (0..self.dim).map(|_| self.sample(t - last)).unzip();
+            self.dw.push(DVector::from_vec(dw));
+            self.dz.push(DVector::from_vec(dz));
+            self.times.push(t);
+            return self.times.len() - 1;
+        }
+        let k = self.times.partition_point(|&s| s < t);
+        if self.times[k] == t {
+            return k;
+        }
+        // split interval k - 1 at t, conditioned on its sampled increments
+        let (a, b) = (self.times[k - 1], self.times[k]);
+        let (s, h) = (t - a, b - a);
+        let mut sigma = Matrix4::zeros();
+        sigma.fixed_view_mut::<2, 2>(0, 0).copy_from(&covariance(s));
+        sigma
+            .fixed_view_mut::<2, 2>(2, 2)
+            .copy_from(&covariance(h - s));
+        let constraint = Matrix2x4::new(1.0, 0.0, 1.0, 0.0, h - s, 1.0, 0.0, 1.0);
+        let gain = sigma
+            * constraint.transpose()
+            * (constraint * sigma * constraint.transpose())
+                .try_inverse()
+                .expect("interval covariance is positive definite");
+        let mut first = (DVector::zeros(self.dim), DVector::zeros(self.dim));
+        let mut second = (DVector::zeros(self.dim), DVector::zeros(self.dim));
+        for i in 0..self.dim {
+            let (w1, z1) = self.sample(s);
+            let (w2, z2) = self.sample(h - s);
+            let x = Vector4::new(w1, z1, w2, z2);
+            let target = Vector2::new(self.dw[k - 1][i], self.dz[k - 1][i]);
+            let x = x + gain * (target - constraint * x);
+            first.0[i] = x[0];
+            first.1[i] = x[1];
+            second.0[i] = x[2];
+            second.1[i] = x[3];
+        }
+        self.dw[k - 1] = first.0;
+        self.dz[k - 1] = first.1;
+        self.dw.insert(k, second.0);
+        self.dz.insert(k, second.1);
+        self.times.insert(k, t);
+        k
+    }
+
+    /// Increments `(dW, dZ)` over `[t0, t1]`
+    ///
+    /// Panics if `t0` precedes the start of the path or `t1 < t0`.
+    pub fn increment(&mut self, t0: f64, t1: f64) -> (DVector<f64>, DVector<f64>) {
+        assert!(t1 >= t0, "increment requires t1 >= t0");
+        let ka = self.boundary(t0);
+        let kb = self.boundary(t1);
+        let mut dw = DVector::zeros(self.dim);
+        let mut dz = DVector::zeros(self.dim);
+        for k in ka..kb {
+            let len = self.times[k + 1] - self.times[k];
+            dz += &self.dz[k] + &dw * len;
+            dw += &self.dw[k];
+        }
+        (dw, dz)
+    }
+
+    /// `W(t) - W(start)`
+    pub fn value(&mut self, t: f64) -> DVector<f64> {
+        let t0 = self.start();
+        self.increment(t0, t).0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refinement_preserves_increments() {
+        let mut path = BrownianPath::new(2, 0.0, 3);
+        let (w, z) = path.increment(0.0, 1.0);
+        let (w1, z1) = path.increment(0.0, 0.3);
+        let (w2, z2) = path.increment(0.3, 1.0);
+        assert!((&w1 + &w2 - &w).amax() < 1e-14);
+        assert!((&z1 + &z2 + &w1 * 0.7 - &z).amax() < 1e-14);
+
+        // a second query returns the same values
+        let (w_again, z_again) = path.increment(0.0, 1.0);
+        assert!((w_again - w).amax() < 1e-14);
+        assert!((z_again - z).amax() < 1e-14);
+    }
+
+    #[test]
+    fn test_bridge_statistics() {
+        // W(1/2) given W(1) has mean W(1) / 2 and variance 1 / 4
+        let n = 4000;
+        let (mut sum, mut sum_sq) = (0.0, 0.0);
+        for seed in 0..n {
+            let mut path = BrownianPath::new(1, 0.0, seed);
+            let w1 = path.value(1.0)[0];
+            let r = path.value(0.5)[0] - 0.5 * w1;
+            sum += r;
+            sum_sq += r * r;
+        }
+        let mean = sum / n as f64;
+        let var = sum_sq / n as f64 - mean * mean;
+        assert!(mean.abs() < 0.03);
+        assert!((var - 0.25).abs() < 0.03);
+    }
+}
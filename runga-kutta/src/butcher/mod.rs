@@ -1,28 +1,241 @@
-// Define a struct to represent a Butcher Tableau
-#[derive(Debug, Clone, Copy)]
-pub struct ButcherTableau {
-    a: [[f64; 8]; 8],
-    b: [f64; 8],
-    c: [f64; 8],
-}
-
-impl ButcherTableau {
-    fn new(a: [[f64; 8]; 8], b: [f64; 8], c: [f64; 8]) -> Self {
-        Self { a, b, c }
-    }
-
-    fn len(&self) -> usize {
-        self.b.len()
-    }
-}
-
-pub enum Tableau {    
-    DoPri45,
-    Tsit5,
-}
-
-impl Default for Tableau {
-    fn default() -> Self {
-        Tableau::DoPri45 // Specify Variant1 as the default value
-    }
-}
\ No newline at end of file
+use crate::{dopri45, tsit5};
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of stages a tableau can hold
+pub const MAX_STAGES: usize = 8;
+
+// Define a struct to represent a Butcher Tableau
+#[derive(Debug, Clone, Copy)]
+pub struct ButcherTableau {
+    pub a: [[f64; MAX_STAGES]; MAX_STAGES],
+    pub b: [f64; MAX_STAGES],
+    pub c: [f64; MAX_STAGES],
+    /// Error weights `b - b_hat` of the embedded method, if there is one
+    pub btilde: Option<[f64; MAX_STAGES]>,
+    pub stages: usize,
+    pub order: usize,
+    /// Order of the embedded method used for error estimation
+    pub embedded_order: usize,
+}
+
+impl ButcherTableau {
+    pub fn new(
+        a: [[f64; MAX_STAGES]; MAX_STAGES],
+        b: [f64; MAX_STAGES],
+        c: [f64; MAX_STAGES],
+        stages: usize,
+        order: usize,
+    ) -> Self {
+        Self {
+            a,
+            b,
+            c,
+            btilde: None,
+            stages,
+            order,
+            embedded_order: 0,
+        }
+    }
+
+    /// Adds an embedded method with weights `b_hat` of the given order
+    pub fn with_embedded(mut self, b_hat: [f64; MAX_STAGES], embedded_order: usize) -> Self {
+        let mut btilde = [0.0; MAX_STAGES];
+        for i in 0..MAX_STAGES {
+            btilde[i] = self.b[i] - b_hat[i];
+        }
+        self.btilde = Some(btilde);
+        self.embedded_order = embedded_order;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.stages
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages == 0
+    }
+
+    pub fn is_adaptive(&self) -> bool {
+        self.btilde.is_some()
+    }
+
+    /// True if the last stage is evaluated at the new solution point, so its
+    /// derivative can be reused as the first stage of the next step
+    pub fn is_fsal(&self) -> bool {
+        let s = self.stages - 1;
+        s > 0 && self.c[s] == 1.0 && (0..s).all(|j| self.a[s][j] == self.b[j]) && self.b[s] == 0.0
+    }
+
+    pub fn euler() -> Self {
+        let mut b = [0.0; MAX_STAGES];
+        b[0] = 1.0;
+        Self::new([[0.0; MAX_STAGES]; MAX_STAGES], b, [0.0; MAX_STAGES], 1, 1)
+    }
+
+    pub fn midpoint() -> Self {
+        let mut a = [[0.0; MAX_STAGES]; MAX_STAGES];
+        let mut b = [0.0; MAX_STAGES];
+        let mut c = [0.0; MAX_STAGES];
+        a[1][0] = 0.5;
+        b[1] = 1.0;
+        c[1] = 0.5;
+        Self::new(a, b, c, 2, 2)
+    }
+
+    pub fn rk4() -> Self {
+        let mut a = [[0.0; MAX_STAGES]; MAX_STAGES];
+        a[1][0] = 0.5;
+        a[2][1] = 0.5;
+        a[3][2] = 1.0;
+        let mut b = [0.0; MAX_STAGES];
+        b[..4].copy_from_slice(&[1.0 / 6.0, 1.0 / 3.0, 1.0 / 3.0, 1.0 / 6.0]);
+        let mut c = [0.0; MAX_STAGES];
+        c[..4].copy_from_slice(&[0.0, 0.5, 0.5, 1.0]);
+        Self::new(a, b, c, 4, 4)
+    }
+
+    /// Bogacki-Shampine 3(2) pair
+    pub fn bs3() -> Self {
+        let mut a = [[0.0; MAX_STAGES]; MAX_STAGES];
+        a[1][0] = 0.5;
+        a[2][1] = 0.75;
+        a[3][..3].copy_from_slice(&[2.0 / 9.0, 1.0 / 3.0, 4.0 / 9.0]);
+        let mut b = [0.0; MAX_STAGES];
+        b[..3].copy_from_slice(&[2.0 / 9.0, 1.0 / 3.0, 4.0 / 9.0]);
+        let mut b_hat = [0.0; MAX_STAGES];
+        b_hat[..4].copy_from_slice(&[7.0 / 24.0, 0.25, 1.0 / 3.0, 0.125]);
+        let mut c = [0.0; MAX_STAGES];
+        c[..4].copy_from_slice(&[0.0, 0.5, 0.75, 1.0]);
+        Self::new(a, b, c, 4, 3).with_embedded(b_hat, 2)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Tableau {
+    Euler,
+    Midpoint,
+    RK4,
+    BS3,
+    #[default]
+    DoPri45,
+    Tsit5,
+}
+
+impl Tableau {
+    pub fn tableau(&self) -> ButcherTableau {
+        match self {
+            Tableau::Euler => ButcherTableau::euler(),
+            Tableau::Midpoint => ButcherTableau::midpoint(),
+            Tableau::RK4 => ButcherTableau::rk4(),
+            Tableau::BS3 => ButcherTableau::bs3(),
+            Tableau::DoPri45 => dopri45::tableau(),
+            Tableau::Tsit5 => tsit5::tableau(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dot(x: &[f64], y: &[f64]) -> f64 {
+        x.iter().zip(y).map(|(a, b)| a * b).sum()
+    }
+
+    fn mul(x: &[f64], y: &[f64]) -> Vec<f64> {
+        x.iter().zip(y).map(|(a, b)| a * b).collect()
+    }
+
+    fn amul(bt: &ButcherTableau, x: &[f64]) -> Vec<f64> {
+        (0..bt.stages)
+            .map(|i| (0..bt.stages).map(|j| bt.a[i][j] * x[j]).sum())
+            .collect()
+    }
+
+    /// Residuals of the order conditions for the weights `b`, grouped by order
+    fn order_conditions(bt: &ButcherTableau, b: &[f64]) -> Vec<Vec<f64>> {
+        let s = bt.stages;
+        let b = &b[..s];
+        let e = vec![1.0; s];
+        let c = bt.c[..s].to_vec();
+        let c2 = mul(&c, &c);
+        let c3 = mul(&c2, &c);
+        let ac = amul(bt, &c);
+        let ac2 = amul(bt, &c2);
+        let aac = amul(bt, &ac);
+        vec![
+            vec![dot(b, &e) - 1.0],
+            vec![dot(b, &c) - 1.0 / 2.0],
+            vec![dot(b, &c2) - 1.0 / 3.0, dot(b, &ac) - 1.0 / 6.0],
+            vec![
+                dot(b, &c3) - 1.0 / 4.0,
+                dot(b, &mul(&c, &ac)) - 1.0 / 8.0,
+                dot(b, &ac2) - 1.0 / 12.0,
+                dot(b, &aac) - 1.0 / 24.0,
+            ],
+            vec![
+                dot(b, &mul(&c3, &c)) - 1.0 / 5.0,
+                dot(b, &mul(&c2, &ac)) - 1.0 / 10.0,
+                dot(b, &mul(&c, &ac2)) - 1.0 / 15.0,
+                dot(b, &mul(&c, &aac)) - 1.0 / 30.0,
+                dot(b, &mul(&ac, &ac)) - 1.0 / 20.0,
+                dot(b, &amul(bt, &c3)) - 1.0 / 20.0,
+                dot(b, &amul(bt, &mul(&c, &ac))) - 1.0 / 40.0,
+                dot(b, &amul(bt, &ac2)) - 1.0 / 60.0,
+                dot(b, &amul(bt, &aac)) - 1.0 / 120.0,
+            ],
+        ]
+    }
+
+    fn check_order(tableau: Tableau) {
+        let bt = tableau.tableau();
+        for i in 0..bt.stages {
+            let row: f64 = bt.a[i].iter().sum();
+            assert!((row - bt.c[i]).abs() < 1e-12, "{:?} row sum {}", tableau, i);
+        }
+        let conditions = order_conditions(&bt, &bt.b);
+        for (k, residuals) in conditions.iter().take(bt.order).enumerate() {
+            for r in residuals {
+                assert!(
+                    r.abs() < 1e-12,
+                    "{:?} order {} residual {}",
+                    tableau,
+                    k + 1,
+                    r
+                );
+            }
+        }
+        if let Some(btilde) = bt.btilde {
+            let b_hat: Vec<f64> = (0..MAX_STAGES).map(|i| bt.b[i] - btilde[i]).collect();
+            let conditions = order_conditions(&bt, &b_hat);
+            for residuals in conditions.iter().take(bt.embedded_order) {
+                for r in residuals {
+                    assert!(r.abs() < 1e-12, "{:?} embedded residual {}", tableau, r);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_order_conditions() {
+        for tableau in [
+            Tableau::Euler,
+            Tableau::Midpoint,
+            Tableau::RK4,
+            Tableau::BS3,
+            Tableau::DoPri45,
+            Tableau::Tsit5,
+        ] {
+            check_order(tableau);
+        }
+    }
+
+    #[test]
+    fn test_fsal() {
+        assert!(Tableau::DoPri45.tableau().is_fsal());
+        assert!(Tableau::Tsit5.tableau().is_fsal());
+        assert!(Tableau::BS3.tableau().is_fsal());
+        assert!(!Tableau::RK4.tableau().is_fsal());
+    }
+}
@@ -0,0 +1,259 @@
+use crate::error::SolverError;
+use crate::jacobian::central_difference;
+use crate::least_squares::{levenberg_marquardt, LeastSquaresOptions};
+use crate::output::SaveAt;
+use crate::runge_kutta;
+use crate::sensitivity::state_transition;
+use crate::solution::Solution;
+use crate::solver::SolverOptions;
+use crate::state::{from_slice, to_dvector, OdeState};
+use nalgebra::{DMatrix, DVector};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BvpOptions {
+    pub solver: SolverOptions,
+    pub least_squares: LeastSquaresOptions,
+    /// Number of shooting segments; one is single shooting
+    pub segments: usize,
+    /// Largest boundary or continuity residual accepted as converged
+    pub tol: f64,
+}
+
+impl Default for BvpOptions {
+    fn default() -> Self {
+        Self {
+            solver: SolverOptions::default().tolerance(1e-10, 1e-12),
+            least_squares: LeastSquaresOptions::default(),
+            segments: 1,
+            tol: 1e-8,
+        }
+    }
+}
+
+impl BvpOptions {
+    pub fn solver(mut self, solver: SolverOptions) -> Self {
+        self.solver = solver;
+        self
+    }
+
+    pub fn least_squares(mut self, least_squares: LeastSquaresOptions) -> Self {
+        self.least_squares = least_squares;
+        self
+    }
+
+    /// Multiple shooting over `segments` equal segments, which keeps sensitive
+    /// problems from amplifying errors in the initial guess over the whole
+    /// interval
+    pub fn segments(mut self, segments: usize) -> Self {
+        self.segments = segments;
+        self
+    }
+
+    pub fn tolerance(mut self, tol: f64) -> Self {
+        self.tol = tol;
+        self
+    }
+}
+
+/// Trajectory of a boundary value problem over the whole interval, with
+/// dense output through `Solution::interpolate`. Every step is saved, whatever
+/// the `SaveAt` of the solver options. Each segment starts from its fitted
+/// node state, so at an interior node the trajectory can jump by up to the
+/// continuity residual there, at most `tol` when converged.
+#[derive(Debug, Clone)]
+pub struct BvpSolution<Ty> {
+    pub solution: Solution<Ty>,
+    /// Start times of the shooting segments
+    pub nodes: Vec<f64>,
+    /// Continuity residuals between segments, then the boundary residuals
+    pub residuals: DVector<f64>,
+    pub iterations: usize,
+    pub converged: bool,
+}
+
+/// Solves `dy = f(y, t, p)` on `[t0, t1]` subject to the `n` boundary
+/// conditions `bc(ya, yb, p) = 0`, for a state with `n` components, by
+/// shooting. The states at the segment starts are the unknowns, initially
+/// taken from `guess`; their Jacobian comes from the state transition
+/// matrices of the segments.
+pub fn solve_bvp<Ty: OdeState, Tp>(
+    f: impl Fn(&mut Ty, &Ty, f64, &Tp),
+    bc: impl Fn(&mut DVector<f64>, &Ty, &Ty, &Tp),
+    guess: impl Fn(f64) -> Ty,
+    t0: f64,
+    t1: f64,
+    p: Tp,
+    options: &BvpOptions,
+) -> Result<BvpSolution<Ty>, SolverError> {
+    let segments = options.segments;
+    let invalid = |msg: &str| Err(SolverError::InvalidInput(msg.to_string()));
+    if segments == 0 {
+        return invalid("shooting needs at least one segment");
+    }
+    if !(t1 - t0).is_normal() {
+        return invalid("the boundaries must be distinct and finite");
+    }
+    let nodes: Vec<f64> = (0..=segments)
+        .map(|k| t0 + (t1 - t0) * k as f64 / segments as f64)
+        .collect();
+    let template = guess(t0);
+    let n = template.dim();
+    let state =
+        |z: &DVector<f64>, k: usize| from_slice(&template, &z.as_slice()[k * n..(k + 1) * n]);
+
+    let rhs = |dy: &mut Ty, y: &Ty, t: f64, _: &()| f(dy, y, t, &p);
+    let mut solver = options.solver.clone();
+    solver.save_at = SaveAt::Final;
+    let boundary = |ya: &Ty, yb: &Ty| {
+        let mut r = DVector::zeros(n);
+        bc(&mut r, ya, yb, &p);
+        r
+    };
+    let problem = |z: &DVector<f64>| {
+        let rows = n * segments;
+        let mut r = DVector::zeros(rows);
+        let mut jac = DMatrix::zeros(rows, rows);
+        let mut end = template.clone();
+        let mut end_stm = DMatrix::zeros(n, n);
+        for k in 0..segments {
+            let sol =
+                state_transition(rhs, state(z, k), nodes[k], (), nodes[k + 1], solver.clone())?;
+            let (yk, stm) = (sol.solution.y.last().unwrap(), sol.stm.last().unwrap());
+            if k + 1 < segments {
+                let gap = to_dvector(yk) - z.rows(n * (k + 1), n);
+                r.rows_mut(n * k, n).copy_from(&gap);
+                jac.view_mut((n * k, n * k), (n, n)).copy_from(stm);
+                jac.view_mut((n * k, n * (k + 1)), (n, n))
+                    .copy_from(&-DMatrix::<f64>::identity(n, n));
+            } else {
+                end = yk.clone();
+                end_stm = stm.clone();
+            }
+        }
+        let ya = state(z, 0);
+        let bc_rows = n * (segments - 1);
+        r.rows_mut(bc_rows, n).copy_from(&boundary(&ya, &end));
+        let ba = central_difference(&ya, n, |ya| boundary(ya, &end));
+        let bb = central_difference(&end, n, |yb| boundary(&ya, yb));
+        let mut first = jac.view_mut((bc_rows, 0), (n, n));
+        first += ba;
+        let mut last = jac.view_mut((bc_rows, n * (segments - 1)), (n, n));
+        last += bb * end_stm;
+        Ok((r, jac))
+    };
+    let z0 = DVector::from_iterator(
+        n * segments,
+        nodes[..segments]
+            .iter()
+            .flat_map(|&t| to_dvector(&guess(t)).iter().copied().collect::<Vec<_>>()),
+    );
+    let fit = levenberg_marquardt(problem, z0, &options.least_squares)?;
+
+    // the dense output needs every step
+    let solver = options.solver.clone().save_at(SaveAt::EveryStep);
+    let mut solution = Solution::default();
+    for k in 0..segments {
+        let segment = runge_kutta(
+            rhs,
+            state(&fit.x, k),
+            nodes[k],
+            (),
+            nodes[k + 1],
+            solver.clone(),
+        )?;
+        let skip = usize::from(k > 0);
+        solution.t.extend_from_slice(&segment.t[skip..]);
+        solution.y.extend_from_slice(&segment.y[skip..]);
+        solution.dy.extend_from_slice(&segment.dy[skip..]);
+        solution.stats += segment.stats;
+    }
+    Ok(BvpSolution {
+        solution,
+        nodes: nodes[..segments].to_vec(),
+        converged: fit.residuals.amax() <= options.tol,
+        residuals: fit.residuals,
+        iterations: fit.iterations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector2;
+    use std::f64::consts::FRAC_PI_2;
+
+    fn oscillator(dy: &mut Vector2<f64>, y: &Vector2<f64>, _t: f64, _p: &()) {
+        *dy = Vector2::new(y[1], -y[0]);
+    }
+
+    #[test]
+    fn test_single_shooting() {
+        // y'' = -y, y(0) = 0, y(pi / 2) = 1 has y = sin t
+        let bc = |r: &mut DVector<f64>, ya: &Vector2<f64>, yb: &Vector2<f64>, _p: &()| {
+            r[0] = ya[0];
+            r[1] = yb[0] - 1.0;
+        };
+        let sol = solve_bvp(
+            oscillator,
+            bc,
+            |_| Vector2::zeros(),
+            0.0,
+            FRAC_PI_2,
+            (),
+            &BvpOptions::default(),
+        )
+        .unwrap();
+        assert!(sol.converged);
+        assert!((sol.solution.y[0][1] - 1.0).abs() < 1e-8);
+        let y = sol.solution.interpolate(0.7).unwrap();
+        assert!((y[0] - 0.7_f64.sin()).abs() < 1e-6);
+        assert_eq!(sol.solution.t.last(), Some(&FRAC_PI_2));
+    }
+
+    #[test]
+    fn test_multiple_shooting_bratu() {
+        // y'' + exp(y) = 0 with y(0) = y(1) = 0; the lower solution has
+        // y(1/2) = 0.140539...
+        let bratu = |dy: &mut Vector2<f64>, y: &Vector2<f64>, _t: f64, lambda: &f64| {
+            *dy = Vector2::new(y[1], -lambda * y[0].exp());
+        };
+        let bc = |r: &mut DVector<f64>, ya: &Vector2<f64>, yb: &Vector2<f64>, _p: &f64| {
+            r[0] = ya[0];
+            r[1] = yb[0];
+        };
+        let single = solve_bvp(
+            bratu,
+            bc,
+            |_| Vector2::zeros(),
+            0.0,
+            1.0,
+            1.0,
+            &BvpOptions::default(),
+        )
+        .unwrap();
+        // the saved times of the solver options do not thin the trajectory
+        let solver = BvpOptions::default().solver.save_at(SaveAt::Final);
+        let options = BvpOptions::default().segments(4).solver(solver);
+        let multiple = solve_bvp(bratu, bc, |_| Vector2::zeros(), 0.0, 1.0, 1.0, &options).unwrap();
+        assert!(single.converged && multiple.converged);
+        assert_eq!(multiple.nodes, vec![0.0, 0.25, 0.5, 0.75]);
+        assert_eq!(multiple.residuals.len(), 8);
+        assert!(multiple.residuals.amax() <= options.tol);
+        assert!(multiple.solution.len() > 2 * multiple.nodes.len());
+        for sol in [&single, &multiple] {
+            let mid = sol.solution.interpolate(0.5).unwrap();
+            assert!((mid[0] - 0.140539).abs() < 1e-5);
+            assert!(sol.solution.t.windows(2).all(|w| w[1] > w[0]));
+        }
+    }
+
+    #[test]
+    fn test_invalid_setup() {
+        let bc =
+            |r: &mut DVector<f64>, ya: &Vector2<f64>, _yb: &Vector2<f64>, _p: &()| r.copy_from(ya);
+        let options = BvpOptions::default().segments(0);
+        assert!(solve_bvp(oscillator, bc, |_| Vector2::zeros(), 0.0, 1.0, (), &options).is_err());
+        let options = BvpOptions::default();
+        assert!(solve_bvp(oscillator, bc, |_| Vector2::zeros(), 1.0, 1.0, (), &options).is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use crate::error::SolverError;
+use crate::integrator::Stats;
+use crate::output::Saver;
+use crate::solution::Solution;
+use crate::solver::SolverOptions;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Everything an `Integrator` needs to continue exactly where it stopped:
+/// time, state, step size, controller history, the FSAL derivative and the
+/// output saved so far. The right-hand side, parameters, events and observers
+/// are not stored and must be supplied again when restoring.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint<Ty> {
+    pub options: SolverOptions,
+    pub t_end: f64,
+    pub t: f64,
+    pub y: Ty,
+    /// Derivative at `(t, y)`, reused as the first stage of the next step
+    pub dy: Ty,
+    pub t_prev: f64,
+    pub y_prev: Ty,
+    pub dy_prev: Ty,
+    /// Signed size of the next step
+    pub dt: f64,
+    /// Error norm of the last accepted step, used by the PI controller
+    pub err_prev: f64,
+    pub rejected_last: bool,
+    /// Event condition values at `t`
+    pub g: Vec<f64>,
+    pub terminated: bool,
+    /// Progress through `options.save_at`
+    pub(crate) saver: Saver,
+    /// Points and events saved before the checkpoint
+    pub saved: Solution<Ty>,
+    pub stats: Stats,
+}
+
+impl<Ty: Serialize + DeserializeOwned> Checkpoint<Ty> {
+    pub fn to_json(&self) -> Result<String, SolverError> {
+        serde_json::to_string(self).map_err(|e| SolverError::Checkpoint(e.to_string()))
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, SolverError> {
+        serde_json::from_str(json).map_err(|e| SolverError::Checkpoint(e.to_string()))
+    }
+
+    /// Compact binary encoding
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SolverError> {
+        bincode::serialize(self).map_err(|e| SolverError::Checkpoint(e.to_string()))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SolverError> {
+        bincode::deserialize(bytes).map_err(|e| SolverError::Checkpoint(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::butcher::Tableau;
+    use crate::events::Event;
+    use crate::integrator::Integrator;
+    use crate::output::SaveAt;
+    use crate::solver::{Solver, SolverOptions};
+    use crate::Checkpoint;
+    use nalgebra::Vector2;
+
+    // Van der Pol oscillator, stiff enough to exercise step rejections
+    fn van_der_pol(dy: &mut Vector2<f64>, y: &Vector2<f64>, _t: f64, mu: &f64) {
+        *dy = Vector2::new(y[1], mu * (1.0 - y[0] * y[0]) * y[1] - y[0]);
+    }
+
+    fn integrator(options: SolverOptions) -> Integrator<'static, Vector2<f64>, f64> {
+        let mut integrator =
+            Integrator::new(van_der_pol, Vector2::new(2.0, 0.0), 0.0, 5.0, 20.0, options).unwrap();
+        integrator.add_event(Event::new(|_t, y: &Vector2<f64>, _mu: &f64| y[0]));
+        integrator
+    }
+
+    fn run_to_end(
+        mut integrator: Integrator<Vector2<f64>, f64>,
+        history: &mut Vec<(f64, Vector2<f64>)>,
+    ) {
+        while !integrator.is_finished() {
+            integrator.step().unwrap();
+            history.push((integrator.t(), *integrator.y()));
+        }
+    }
+
+    #[test]
+    fn test_restart_is_bit_for_bit() {
+        for options in [
+            SolverOptions::new(Tableau::Tsit5).tolerance(1e-8, 1e-10),
+            SolverOptions::fixed(Tableau::RK4, 0.01),
+        ] {
+            let mut uninterrupted = vec![];
+            run_to_end(integrator(options.clone()), &mut uninterrupted);
+
+            let mut first = integrator(options);
+            let mut history = vec![];
+            for _ in 0..uninterrupted.len() / 2 {
+                first.step().unwrap();
+                history.push((first.t(), *first.y()));
+            }
+            let json = first.checkpoint().to_json().unwrap();
+            let bytes = first.checkpoint().to_bytes().unwrap();
+            drop(first);
+
+            for checkpoint in [
+                Checkpoint::from_json(&json).unwrap(),
+                Checkpoint::from_bytes(&bytes).unwrap(),
+            ] {
+                let mut restored = Integrator::restore(van_der_pol, 5.0, checkpoint).unwrap();
+                restored.add_event(Event::new(|_t, y: &Vector2<f64>, _mu: &f64| y[0]));
+                let mut resumed = history.clone();
+                run_to_end(restored, &mut resumed);
+                assert_eq!(resumed.len(), uninterrupted.len());
+                for ((t0, y0), (t1, y1)) in resumed.iter().zip(&uninterrupted) {
+                    assert_eq!(t0.to_bits(), t1.to_bits());
+                    assert_eq!(y0[0].to_bits(), y1[0].to_bits());
+                    assert_eq!(y0[1].to_bits(), y1[1].to_bits());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_resume_through_solve() {
+        let times: Vec<f64> = (0..=40).map(|i| 0.5 * i as f64).collect();
+        for save_at in [SaveAt::EveryNth(3), SaveAt::Times(times)] {
+            let options = SolverOptions::new(Tableau::Tsit5)
+                .tolerance(1e-8, 1e-10)
+                .save_at(save_at);
+            let uninterrupted = integrator(options.clone()).solve().unwrap();
+
+            let mut first = integrator(options);
+            for _ in 0..uninterrupted.stats.accepted / 2 {
+                first.step().unwrap();
+            }
+            let json = first.checkpoint().to_json().unwrap();
+            drop(first);
+
+            let resumed = Solver::default()
+                .event(Event::new(|_t, y: &Vector2<f64>, _mu: &f64| y[0]))
+                .restore(van_der_pol, 5.0, Checkpoint::from_json(&json).unwrap())
+                .unwrap()
+                .solve()
+                .unwrap();
+            assert_eq!(resumed, uninterrupted);
+            assert!(!resumed.events.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_corrupt_checkpoint() {
+        assert!(Checkpoint::<f64>::from_json("{").is_err());
+        assert!(Checkpoint::<f64>::from_bytes(&[1, 2, 3]).is_err());
+    }
+}
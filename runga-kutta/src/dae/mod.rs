@@ -0,0 +1,415 @@
+use crate::error::SolverError;
+use crate::integrator::{clamp_step, error_norm, initial_step, Stats, Stops};
+use crate::jacobian::central_difference;
+use crate::output::{Saver, Step};
+use crate::solution::Solution;
+use crate::solver::{Solver, SolverOptions};
+use crate::state::{from_slice, to_dvector, OdeState};
+use nalgebra::{DMatrix, DVector};
+
+// Rodas3 of Sandu et al. (1997): four stages, order 3 with an embedded
+// order 2 solution, stiffly accurate and L-stable, so algebraic components
+// are computed as accurately as differential ones.
+const GAMMA: f64 = 0.5;
+const A: [[f64; 3]; 4] = [
+    [0.0, 0.0, 0.0],
+    [0.0, 0.0, 0.0],
+    [2.0, 0.0, 0.0],
+    [2.0, 0.0, 1.0],
+];
+const C: [[f64; 3]; 4] = [
+    [0.0, 0.0, 0.0],
+    [4.0, 0.0, 0.0],
+    [1.0, -1.0, 0.0],
+    [1.0, -1.0, -8.0 / 3.0],
+];
+/// Stage times as fractions of the step
+const ALPHA: [f64; 4] = [0.0, 0.0, 1.0, 1.0];
+/// Coefficients of the time derivative of `f` in each stage
+const GAMMA_T: [f64; 4] = [0.5, 1.5, 0.0, 0.0];
+const M: [f64; 4] = [2.0, 0.0, 1.0, 1.0];
+const E: [f64; 4] = [0.0, 0.0, 0.0, 1.0];
+/// The error estimate is O(h^3), from the order 2 embedded solution, so step
+/// sizes scale with its inverse cube root
+const ERROR_ORDER: f64 = 3.0;
+
+/// The mass matrix of a semi-explicit DAE of dimension `n`: `dy[i] = f[i]`
+/// for the differential components and `0 = f[i]` for those in `algebraic`
+pub fn semi_explicit_mass(n: usize, algebraic: &[usize]) -> DMatrix<f64> {
+    let diagonal = DVector::from_fn(n, |i, _| if algebraic.contains(&i) { 0.0 } else { 1.0 });
+    DMatrix::from_diagonal(&diagonal)
+}
+
+/// `mass * dy = f(y, t, p)` evaluated on component vectors, with the
+/// singular value decomposition of the mass matrix split into its range,
+/// the differential part, and its null spaces, the algebraic part
+struct MassSystem<'s, Ty, Tp, F> {
+    f: F,
+    mass: &'s DMatrix<f64>,
+    p: &'s Tp,
+    template: Ty,
+    /// Maps states to their differential components
+    range: DMatrix<f64>,
+    /// Maps `f` to the derivative of the differential components
+    inverse: DMatrix<f64>,
+    left_null: DMatrix<f64>,
+    right_null: DMatrix<f64>,
+}
+
+impl<'s, Ty: OdeState, Tp, F: Fn(&mut Ty, &Ty, f64, &Tp)> MassSystem<'s, Ty, Tp, F> {
+    fn new(f: F, mass: &'s DMatrix<f64>, y0: &Ty, p: &'s Tp) -> Result<Self, SolverError> {
+        let n = y0.dim();
+        if mass.shape() != (n, n) {
+            return Err(SolverError::InvalidInput(
+                "mass matrix must be square with the dimension of the state".to_string(),
+            ));
+        }
+        let svd = mass.clone().svd(true, true);
+        let (u, v_t) = (svd.u.unwrap(), svd.v_t.unwrap());
+        let sigma = &svd.singular_values;
+        let cutoff = sigma.max() * n as f64 * f64::EPSILON;
+        let (rank, null): (Vec<usize>, Vec<usize>) = (0..n).partition(|&i| sigma[i] > cutoff);
+        let inverse = DMatrix::from_fn(rank.len(), n, |i, j| u[(j, rank[i])] / sigma[rank[i]]);
+        Ok(Self {
+            f,
+            mass,
+            p,
+            template: y0.zeros_like(),
+            range: v_t.select_rows(&rank),
+            inverse,
+            left_null: u.select_columns(&null),
+            right_null: v_t.select_rows(&null).transpose(),
+        })
+    }
+
+    fn eval(&self, y: &DVector<f64>, t: f64) -> DVector<f64> {
+        let y = from_slice(&self.template, y.as_slice());
+        let mut dy = self.template.zeros_like();
+        (self.f)(&mut dy, &y, t, self.p);
+        to_dvector(&dy)
+    }
+
+    fn consistent_initial(&self, y0: &Ty, t0: f64) -> Result<Ty, SolverError> {
+        let n = y0.dim();
+        if self.right_null.ncols() == 0 {
+            return Ok(y0.clone());
+        }
+        let (left, right) = (&self.left_null, &self.right_null);
+
+        let mut y = to_dvector(y0);
+        for _ in 0..50 {
+            let g = left.transpose() * self.eval(&y, t0);
+            let jac = central_difference(&y, n, |y| self.eval(y, t0));
+            let reduced = left.transpose() * jac * right;
+            let delta = reduced.lu().solve(&g).ok_or(SolverError::SingularMatrix)?;
+            y -= right * &delta;
+            if delta.amax() <= 1e-12 * y.amax().max(1.0) {
+                return Ok(from_slice(y0, y.as_slice()));
+            }
+        }
+        Err(SolverError::InvalidInput(
+            "no consistent initial state found".to_string(),
+        ))
+    }
+
+    /// First step from a consistent `(t, y)` towards `t_end` by the shared
+    /// estimate on the differential components, whose derivative is
+    /// determined by `f`, keeping the algebraic components fixed
+    fn initial_step(&self, y: &DVector<f64>, t: f64, t_end: f64, options: &SolverOptions) -> f64 {
+        let z = &self.range * y;
+        let dz = &self.inverse * self.eval(y, t);
+        initial_step(&z, &dz, t, t_end, ERROR_ORDER - 1.0, options, |z1, t1| {
+            let y1 = y + self.range.transpose() * (z1 - &z);
+            &self.inverse * self.eval(&y1, t1)
+        })
+    }
+
+    /// One Rosenbrock step of size `h` from `(t, y)`, where `f` is `f0` with
+    /// Jacobian `jac` and time derivative `ft`. Returns the new state and the
+    /// error estimate, or `None` if the iteration matrix is singular.
+    #[allow(clippy::too_many_arguments)]
+    fn rosenbrock_step(
+        &self,
+        t: f64,
+        y: &DVector<f64>,
+        f0: &DVector<f64>,
+        jac: &DMatrix<f64>,
+        ft: &DVector<f64>,
+        h: f64,
+        fevals: &mut usize,
+    ) -> Option<(DVector<f64>, DVector<f64>)> {
+        let lu = (self.mass / (GAMMA * h) - jac).lu();
+        let mut k: Vec<DVector<f64>> = Vec::with_capacity(4);
+        let mut fi = f0.clone();
+        for i in 0..4 {
+            if i > 0 && A[i].iter().any(|&a| a != 0.0) {
+                let yi = (0..i).fold(y.clone(), |yi, j| yi + &k[j] * A[i][j]);
+                fi = self.eval(&yi, t + ALPHA[i] * h);
+                *fevals += 1;
+            }
+            let coupling = (0..i).fold(DVector::zeros(y.len()), |s, j| s + &k[j] * (C[i][j] / h));
+            let rhs = &fi + self.mass * coupling + ft * (GAMMA_T[i] * h);
+            k.push(lu.solve(&rhs)?);
+        }
+        let y_new = (0..4).fold(y.clone(), |y_new, i| y_new + &k[i] * M[i]);
+        let err = (0..4).fold(DVector::zeros(y.len()), |err, i| err + &k[i] * E[i]);
+        Some((y_new, err))
+    }
+}
+
+/// Adjusts `y0` to satisfy the algebraic equations of `mass * dy = f(y, t0,
+/// p)`, keeping its differential part. The equations `u' f = 0` for `u` in
+/// the left null space of the mass matrix are solved by Newton's method for
+/// a correction in its right null space.
+pub fn consistent_initial<Ty: OdeState, Tp>(
+    f: impl Fn(&mut Ty, &Ty, f64, &Tp),
+    mass: &DMatrix<f64>,
+    y0: &Ty,
+    t0: f64,
+    p: &Tp,
+) -> Result<Ty, SolverError> {
+    MassSystem::new(f, mass, y0, p)?.consistent_initial(y0, t0)
+}
+
+/// Integrates `mass * dy = f(y, t, p)` from `t0` to `t_end`, backward in
+/// time if `t_end < t0`, with the linearly implicit Rodas3 method, for
+/// `Solver::mass_matrix`. The algebraic components of `y0` are first made
+/// consistent with the differential ones. Jacobians come from finite
+/// differences. Without `dt` the first step is selected from the
+/// differential components.
+///
+/// The saved derivatives are the slopes of the steps ending at each point,
+/// as an index-1 DAE does not give the derivative of its algebraic
+/// components directly, so saved times are stepped onto exactly.
+pub(crate) fn solve_dae<'a, Ty: OdeState, Tp>(
+    solver: Solver<'a, Ty, Tp>,
+    f: impl Fn(&mut Ty, &Ty, f64, &Tp),
+    y0: Ty,
+    t0: f64,
+    p: Tp,
+    t_end: f64,
+) -> Result<Solution<Ty>, SolverError> {
+    solver.observers_only()?;
+    let Solver {
+        options,
+        mut observers,
+        mass,
+        ..
+    } = solver;
+    let Some(mass) = mass else {
+        return Err(SolverError::InvalidInput(
+            "DAE integration requires a mass matrix".to_string(),
+        ));
+    };
+    options.validate_steps()?;
+
+    let system = MassSystem::new(f, &mass, &y0, &p)?;
+    let y0 = system.consistent_initial(&y0, t0)?;
+    let n = y0.dim();
+    let as_state = |y: &DVector<f64>| from_slice(&y0, y.as_slice());
+    let mut sol = Solution::default();
+    let mut saver = Saver::new(options.save_at.clone(), t0, t_end);
+    let stops = Stops::new(
+        options.tstops.iter().chain(&saver.times).copied(),
+        t0,
+        t_end,
+    );
+    let mut stats = Stats::default();
+
+    let mut t = t0;
+    let mut y = to_dvector(&y0);
+    let mut slope = DVector::zeros(n);
+    saver.start(&mut sol, t, &y0, &as_state(&slope));
+    let mut h = match options.dt {
+        Some(dt) => dt.abs(),
+        None => system.initial_step(&y, t, t_end, &options),
+    };
+
+    while t != t_end {
+        if stats.accepted + stats.rejected >= options.max_steps {
+            return Err(SolverError::MaxStepsExceeded { t });
+        }
+        let f0 = system.eval(&y, t);
+        let jac = central_difference(&y, n, |y| system.eval(y, t));
+        let dt = 1e-7 * t.abs().max(1.0);
+        let ft = (system.eval(&y, t + dt) - &f0) / dt;
+        stats.fevals += 2 + 2 * n;
+        let stop = stops.next(t);
+
+        loop {
+            let (h_try, hits_stop) = clamp_step(t, h, stop, &options)?;
+            let step = system.rosenbrock_step(t, &y, &f0, &jac, &ft, h_try, &mut stats.fevals);
+            let Some((y_new, err)) = step else {
+                if options.tol.is_none() {
+                    return Err(SolverError::SingularMatrix);
+                }
+                stats.rejected += 1;
+                h = 0.25 * h_try;
+                continue;
+            };
+            if let Some(tol) = options.tol {
+                let norm = error_norm(&err, &y, &y_new, &tol);
+                if !norm.is_finite() {
+                    stats.rejected += 1;
+                    h = 0.2 * h_try;
+                    continue;
+                }
+                let fac = if norm == 0.0 {
+                    5.0
+                } else {
+                    0.9 * norm.powf(-1.0 / ERROR_ORDER)
+                };
+                if norm > 1.0 {
+                    stats.rejected += 1;
+                    h = h_try * fac.clamp(0.2, 1.0);
+                    continue;
+                }
+                h = h_try * fac.clamp(0.2, 5.0);
+            }
+            if !y_new.iter().all(|v| v.is_finite()) {
+                return Err(SolverError::NonFiniteState { t });
+            }
+
+            let t_new = if hits_stop { stop } else { t + h_try };
+            let slope_new = (&y_new - &y) / h_try;
+            stats.accepted += 1;
+            let (y0_state, dy0_state) = (as_state(&y), as_state(&slope));
+            let (y1_state, dy1_state) = (as_state(&y_new), as_state(&slope_new));
+            saver.after_step(
+                &mut sol,
+                Step {
+                    t0: t,
+                    y0: &y0_state,
+                    dy0: &dy0_state,
+                    t1: t_new,
+                    y1: &y1_state,
+                    dy1: &dy1_state,
+                },
+            );
+            for observer in observers.iter_mut() {
+                observer(t_new, &y1_state);
+            }
+            t = t_new;
+            y = y_new;
+            slope = slope_new;
+            break;
+        }
+    }
+    saver.finish(&mut sol, t, &as_state(&y), &as_state(&slope));
+    sol.stats = stats;
+    Ok(sol)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Event;
+    use crate::output::SaveAt;
+    use crate::solver::Solver;
+    use nalgebra::{Vector2, Vector3};
+
+    // y0' = 1 - y0 - y1 with 0 = y1 - y0: y0 = (1 - exp(-2t)) / 2
+    fn relaxation(dy: &mut Vector2<f64>, y: &Vector2<f64>, _t: f64, _p: &()) {
+        *dy = Vector2::new(1.0 - y[0] - y[1], y[1] - y[0]);
+    }
+
+    #[test]
+    fn test_consistent_initialization() {
+        let mass = semi_explicit_mass(2, &[1]);
+        let y0 = consistent_initial(relaxation, &mass, &Vector2::new(0.3, 5.0), 0.0, &()).unwrap();
+        assert_eq!(y0[0], 0.3);
+        assert!((y0[1] - 0.3).abs() < 1e-12);
+
+        // a mass matrix that mixes the components: y0 + y1 is differential
+        // and f0 = f1 is the algebraic equation
+        let mass = DMatrix::from_row_slice(2, 2, &[1.0, 1.0, 1.0, 1.0]);
+        let f = |dy: &mut Vector2<f64>, y: &Vector2<f64>, _t: f64, _p: &()| {
+            *dy = Vector2::new(-y[0] - y[1], -2.0 * y[1]);
+        };
+        let y0 = consistent_initial(f, &mass, &Vector2::new(2.0, 0.0), 0.0, &()).unwrap();
+        assert!((y0 - Vector2::new(1.0, 1.0)).amax() < 1e-12);
+    }
+
+    #[test]
+    fn test_semi_explicit_dae() {
+        let solver = || {
+            Solver::new(SolverOptions::default().tolerance(1e-8, 1e-10))
+                .mass_matrix(semi_explicit_mass(2, &[1]))
+        };
+        let mut steps = 0;
+        let sol = solver()
+            .observer(|_t, _y| steps += 1)
+            .solve(relaxation, Vector2::new(0.0, 1.0), 0.0, (), 2.0)
+            .unwrap();
+        let (t1, y1) = sol.last().unwrap();
+        assert_eq!(t1, 2.0);
+        let exact = 0.5 * (1.0 - (-4.0_f64).exp());
+        assert!((y1[0] - exact).abs() < 1e-6);
+        assert!((y1[1] - exact).abs() < 1e-6);
+        assert_eq!(steps, sol.stats.accepted);
+
+        let result = solver()
+            .event(Event::new(|_t, y: &Vector2<f64>, _p: &()| y[0] - 0.25))
+            .solve(relaxation, Vector2::new(0.0, 1.0), 0.0, (), 2.0);
+        assert!(matches!(result, Err(SolverError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_backward_integration() {
+        let exact = |t: f64| 0.5 * (1.0 - (-2.0 * t).exp());
+        let options = SolverOptions::default()
+            .tolerance(1e-8, 1e-10)
+            .save_at(SaveAt::Times(vec![0.5, 0.0]));
+        let sol = Solver::new(options)
+            .mass_matrix(semi_explicit_mass(2, &[1]))
+            .solve(relaxation, Vector2::repeat(exact(1.0)), 1.0, (), 0.0)
+            .unwrap();
+        assert_eq!(sol.t, vec![0.5, 0.0]);
+        for (&t, y) in sol.t.iter().zip(&sol.y) {
+            assert!((y[0] - exact(t)).abs() < 1e-6);
+            assert!((y[1] - exact(t)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_third_order_convergence() {
+        let f = |dy: &mut f64, y: &f64, t: f64, _p: &()| *dy = -y + t.sin();
+        // y = (3 exp(-t) + sin t - cos t) / 2
+        let exact = |t: f64| (3.0 * (-t).exp() + t.sin() - t.cos()) / 2.0;
+        let error = |dt: f64| {
+            let sol = Solver::new(SolverOptions::default().fixed_dt(dt))
+                .mass_matrix(DMatrix::identity(1, 1))
+                .solve(f, 1.0, 0.0, (), 1.0)
+                .unwrap();
+            (sol.last().unwrap().1 - exact(1.0)).abs()
+        };
+        let order = (error(0.02) / error(0.01)).log2();
+        assert!((order - 3.0).abs() < 0.3, "order {}", order);
+    }
+
+    #[test]
+    fn test_stiff_robertson() {
+        // Robertson's chemical kinetics with the third species given by
+        // conservation of mass
+        let f = |dy: &mut Vector3<f64>, y: &Vector3<f64>, _t: f64, _p: &()| {
+            *dy = Vector3::new(
+                -0.04 * y[0] + 1e4 * y[1] * y[2],
+                0.04 * y[0] - 1e4 * y[1] * y[2] - 3e7 * y[1] * y[1],
+                y[0] + y[1] + y[2] - 1.0,
+            );
+        };
+        let options = SolverOptions::default()
+            .tolerance(1e-6, 1e-10)
+            .save_at(SaveAt::Times(vec![40.0]));
+        let sol = Solver::new(options)
+            .mass_matrix(semi_explicit_mass(3, &[2]))
+            .solve(f, Vector3::new(1.0, 0.0, 0.0), 0.0, (), 40.0)
+            .unwrap();
+        assert_eq!(sol.t, vec![40.0]);
+        // reference values from Hairer and Wanner
+        let y = sol.y[0];
+        assert!((y[0] - 0.7158).abs() < 1e-3);
+        assert!((y[2] - 0.2842).abs() < 1e-3);
+        assert!(sol.stats.accepted < 500);
+    }
+}
@@ -0,0 +1,294 @@
+use crate::error::SolverError;
+use crate::events::{Crossing, Event};
+use crate::integrator::{Integrator, Stats};
+use crate::output::{SaveAt, Saver, Step};
+use crate::solution::{hermite, Solution};
+use crate::solver::SolverOptions;
+use crate::state::{axpy, OdeState};
+use std::cell::RefCell;
+
+type InitialHistory<'a, Ty> = Box<dyn Fn(f64) -> Ty + 'a>;
+type StateDelay<'a, Ty, Tp> = Box<dyn Fn(f64, &Ty, &Tp) -> f64 + 'a>;
+
+/// Two discontinuities closer than this are treated as one
+const MERGE_TOL: f64 = 1e-12;
+
+/// The past of a DDE solution: the initial history before `t0` and the dense
+/// output of the accepted steps after it
+pub struct History<'a, Ty> {
+    initial: &'a dyn Fn(f64) -> Ty,
+    t0: f64,
+    steps: Solution<Ty>,
+}
+
+impl<'a, Ty: OdeState> History<'a, Ty> {
+    /// The solution at `t`. Times beyond the last accepted step, which a
+    /// stage can ask for when a delay is shorter than the step, are
+    /// extrapolated from the last step.
+    pub fn at(&self, t: f64) -> Ty {
+        if t < self.t0 {
+            return (self.initial)(t);
+        }
+        if let Some(y) = self.steps.interpolate(t) {
+            return y;
+        }
+        let n = self.steps.len();
+        if n == 1 {
+            return axpy(&self.steps.y[0], t - self.t0, &self.steps.dy[0]);
+        }
+        let s = &self.steps;
+        hermite(
+            s.t[n - 2],
+            &s.y[n - 2],
+            &s.dy[n - 2],
+            s.t[n - 1],
+            &s.y[n - 1],
+            &s.dy[n - 1],
+            t,
+        )
+    }
+}
+
+/// The delays and initial history of a delay differential equation
+/// `dy = f(y, y(t - tau_1), ..., t, p)`
+pub struct Dde<'a, Ty, Tp> {
+    history: InitialHistory<'a, Ty>,
+    delays: Vec<f64>,
+    state_delays: Vec<StateDelay<'a, Ty, Tp>>,
+    discontinuities: Vec<f64>,
+}
+
+impl<'a, Ty: OdeState, Tp> Dde<'a, Ty, Tp> {
+    /// `history(t)` gives the solution for `t` before the initial time
+    pub fn new(history: impl Fn(f64) -> Ty + 'a) -> Self {
+        Self {
+            history: Box::new(history),
+            delays: vec![],
+            state_delays: vec![],
+            discontinuities: vec![],
+        }
+    }
+
+    pub fn constant_delay(mut self, tau: f64) -> Self {
+        self.delays.push(tau);
+        self
+    }
+
+    /// A delay `tau(t, y, p)` that may depend on the time and state
+    pub fn state_delay(mut self, tau: impl Fn(f64, &Ty, &Tp) -> f64 + 'a) -> Self {
+        self.state_delays.push(Box::new(tau));
+        self
+    }
+
+    /// Times at which the initial history or its derivatives jump. The
+    /// initial time is always a discontinuity.
+    pub fn discontinuities(mut self, discontinuities: Vec<f64>) -> Self {
+        self.discontinuities = discontinuities;
+        self
+    }
+}
+
+/// A discontinuity of the solution and the order of the derivative it is in
+#[derive(Debug, Clone, Copy)]
+struct Discontinuity {
+    t: f64,
+    level: usize,
+}
+
+/// Adds `d` and its propagation through the constant delays, down to
+/// `max_level`
+fn propagate(known: &mut Vec<Discontinuity>, d: Discontinuity, delays: &[f64], max_level: usize) {
+    if known
+        .iter()
+        .any(|k| k.level <= d.level && (k.t - d.t).abs() <= MERGE_TOL * d.t.abs().max(1.0))
+    {
+        return;
+    }
+    known.push(d);
+    if d.level < max_level {
+        for &tau in delays {
+            let next = Discontinuity {
+                t: d.t + tau,
+                level: d.level + 1,
+            };
+            propagate(known, next, delays, max_level);
+        }
+    }
+}
+
+/// Integrates the delay differential equation `dy = f(y, history, t, p)`
+/// forward from `t0`, where `history.at(t - tau)` looks up lagged states.
+/// The initial state is `dde`'s history at `t0`.
+///
+/// Discontinuities of the initial history propagate into the solution
+/// through the delays, one derivative smoother each time. Those carried by
+/// constant delays are stepped onto exactly; those carried by state-dependent
+/// delays are located as events and the integration restarts there. Both are
+/// tracked until they are smoother than the order of the method.
+pub fn solve_dde<Ty: OdeState, Tp>(
+    f: impl Fn(&mut Ty, &Ty, &History<Ty>, f64, &Tp),
+    dde: &Dde<Ty, Tp>,
+    t0: f64,
+    p: Tp,
+    t_end: f64,
+    options: SolverOptions,
+) -> Result<Solution<Ty>, SolverError> {
+    if t_end < t0 {
+        return Err(SolverError::InvalidInput(
+            "delay equations integrate forward only".to_string(),
+        ));
+    }
+    if dde.delays.iter().any(|&tau| tau <= 0.0 || !tau.is_finite()) {
+        return Err(SolverError::InvalidInput(
+            "delays must be positive and finite".to_string(),
+        ));
+    }
+
+    let max_level = options.order() + 1;
+    let mut known = vec![];
+    for t in std::iter::once(t0).chain(dde.discontinuities.iter().copied()) {
+        let d = Discontinuity { t, level: 0 };
+        propagate(&mut known, d, &dde.delays, max_level);
+    }
+
+    let y0 = (dde.history)(t0);
+    let history = RefCell::new(History {
+        initial: &*dde.history,
+        t0,
+        steps: Solution::default(),
+    });
+    let p = &p;
+    let rhs = |dy: &mut Ty, y: &Ty, t: f64, _: &()| f(dy, y, &history.borrow(), t, p);
+
+    let mut sol = Solution::default();
+    let mut saver = Saver::new(options.save_at.clone(), t0, t_end);
+    let mut stats = Stats::default();
+    let (mut t, mut y) = (t0, y0);
+    loop {
+        let mut tstops = options.tstops.clone();
+        tstops.extend(known.iter().map(|d| d.t));
+        // the output is saved across segments below rather than by each
+        // segment's integrator
+        let segment = options
+            .clone()
+            .tstops(tstops)
+            .save_at(SaveAt::Final)
+            .max_steps(
+                options
+                    .max_steps
+                    .saturating_sub(stats.accepted + stats.rejected),
+            );
+        let mut integrator = Integrator::new(rhs, y, t, (), t_end, segment)?;
+        if history.borrow().steps.is_empty() {
+            let (y, dy) = (integrator.y().clone(), integrator.dy().clone());
+            saver.start(&mut sol, t, &y, &dy);
+            history.borrow_mut().steps.push(t, y, dy);
+        }
+
+        // one terminal event per state-dependent delay and discontinuity
+        // still ahead of it
+        let mut sources = vec![];
+        for d in known.iter().filter(|d| d.level < max_level) {
+            for tau in &dde.state_delays {
+                let xi = d.t;
+                let event = move |t: f64, y: &Ty, _: &()| t - tau(t, y, p) - xi;
+                integrator.add_event(Event::new(event).crossing(Crossing::Rising).terminal(true));
+                sources.push(*d);
+            }
+        }
+
+        let mut found = None;
+        while !integrator.is_finished() {
+            integrator.step()?;
+            let (t1, y1, dy1) = (integrator.t(), integrator.y(), integrator.dy());
+            let mut past = history.borrow_mut();
+            let n = past.steps.len();
+            let (t_prev, y_prev, dy_prev) = (
+                past.steps.t[n - 1],
+                past.steps.y[n - 1].clone(),
+                past.steps.dy[n - 1].clone(),
+            );
+            past.steps.push(t1, y1.clone(), dy1.clone());
+            drop(past);
+            saver.after_step(
+                &mut sol,
+                Step {
+                    t0: t_prev,
+                    y0: &y_prev,
+                    dy0: &dy_prev,
+                    t1,
+                    y1,
+                    dy1,
+                },
+            );
+            for record in integrator.take_events() {
+                found = Some(sources[record.index]);
+                sol.events.push(record);
+            }
+        }
+        stats += integrator.stats();
+        t = integrator.t();
+        y = integrator.y().clone();
+
+        match found {
+            Some(source) if t < t_end => {
+                let d = Discontinuity {
+                    t,
+                    level: source.level + 1,
+                };
+                propagate(&mut known, d, &dde.delays, max_level);
+            }
+            _ => {
+                let dy = integrator.dy().clone();
+                saver.finish(&mut sol, t, &y, &dy);
+                break;
+            }
+        }
+    }
+    sol.stats = stats;
+    Ok(sol)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_delay() {
+        // y' = -y(t - 1) with y = 1 before 0 is a polynomial on each unit
+        // interval; y(3) = -1/6
+        let f = |dy: &mut f64, _y: &f64, h: &History<f64>, t: f64, _p: &()| *dy = -h.at(t - 1.0);
+        let dde = Dde::new(|_t| 1.0).constant_delay(1.0);
+        let options = SolverOptions::default().tolerance(1e-10, 1e-12);
+        let sol = solve_dde(f, &dde, 0.0, (), 3.0, options).unwrap();
+        let (t1, y1) = sol.last().unwrap();
+        assert_eq!(t1, 3.0);
+        assert!((y1 + 1.0 / 6.0).abs() < 1e-9);
+        assert!(sol.t.contains(&1.0) && sol.t.contains(&2.0));
+        assert!((sol.interpolate(1.5).unwrap() + 0.375).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_state_dependent_delay() {
+        // y' = -y(t / 2 - 1) with y = 1 before 0: y = 1 - t up to the
+        // discontinuity at t = 2, where the lag reaches the initial time
+        let f = |dy: &mut f64, _y: &f64, h: &History<f64>, t: f64, p: &f64| {
+            *dy = -h.at(t - (1.0 + p * t));
+        };
+        let dde = Dde::new(|_t| 1.0).state_delay(|t, _y: &f64, p: &f64| 1.0 + p * t);
+        let options = SolverOptions::default().tolerance(1e-10, 1e-12);
+        let sol = solve_dde(f, &dde, 0.0, 0.5, 4.0, options).unwrap();
+        assert!((sol.last().unwrap().1 + 2.0).abs() < 1e-8);
+        assert!(sol.t.iter().any(|&t| (t - 2.0).abs() < 1e-8));
+        assert!((sol.events[0].t - 2.0).abs() < 1e-8);
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        let f = |dy: &mut f64, _y: &f64, h: &History<f64>, t: f64, _p: &()| *dy = -h.at(t - 1.0);
+        let dde = Dde::new(|_t| 1.0).constant_delay(1.0);
+        assert!(solve_dde(f, &dde, 0.0, (), -1.0, SolverOptions::default()).is_err());
+        let dde = Dde::new(|_t| 1.0).constant_delay(0.0);
+        assert!(solve_dde(f, &dde, 0.0, (), 1.0, SolverOptions::default()).is_err());
+    }
+}
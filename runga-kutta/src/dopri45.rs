@@ -1,28 +1,49 @@
-use crate::butcher::ButcherTableau;
-
-mod dopri45 {
-
-    pub fn tableau() {
-        let a = [
-            [],
-            [1 / 5],
-            [3 / 40, 9 / 40],
-            [44 / 45, -56 / 15, 32 / 9],
-            [19372 / 6561, -25360 / 2187, 64448 / 6561, -212 / 729],
-            [
-                9017 / 3168,
-                -355 / 33,
-                46732 / 5247,
-                49 / 176,
-                -5103 / 18656,
-            ],
-            [35 / 384, 0, 500 / 1113, 125 / 192, -2187 / 6784, 11 / 84],
-        ];
-        
-        let b = [5179/57600,0,7571/16695,393/640,-92097/339200,187/2100,1/40];
-
-        let c = [0,1/5,3/10,4/5,8/9,1,1];
-
-        ButcherTableau::new(a,b,c);
-    }
-}
+use crate::butcher::{ButcherTableau, MAX_STAGES};
+
+/// Dormand-Prince 5(4) pair
+pub fn tableau() -> ButcherTableau {
+    let mut a = [[0.0; MAX_STAGES]; MAX_STAGES];
+    a[1][..1].copy_from_slice(&[1.0 / 5.0]);
+    a[2][..2].copy_from_slice(&[3.0 / 40.0, 9.0 / 40.0]);
+    a[3][..3].copy_from_slice(&[44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0]);
+    a[4][..4].copy_from_slice(&[
+        19372.0 / 6561.0,
+        -25360.0 / 2187.0,
+        64448.0 / 6561.0,
+        -212.0 / 729.0,
+    ]);
+    a[5][..5].copy_from_slice(&[
+        9017.0 / 3168.0,
+        -355.0 / 33.0,
+        46732.0 / 5247.0,
+        49.0 / 176.0,
+        -5103.0 / 18656.0,
+    ]);
+    a[6][..6].copy_from_slice(&[
+        35.0 / 384.0,
+        0.0,
+        500.0 / 1113.0,
+        125.0 / 192.0,
+        -2187.0 / 6784.0,
+        11.0 / 84.0,
+    ]);
+
+    let mut b = [0.0; MAX_STAGES];
+    b[..7].copy_from_slice(&a[6][..7]);
+
+    let mut b_hat = [0.0; MAX_STAGES];
+    b_hat[..7].copy_from_slice(&[
+        5179.0 / 57600.0,
+        0.0,
+        7571.0 / 16695.0,
+        393.0 / 640.0,
+        -92097.0 / 339200.0,
+        187.0 / 2100.0,
+        1.0 / 40.0,
+    ]);
+
+    let mut c = [0.0; MAX_STAGES];
+    c[..7].copy_from_slice(&[0.0, 1.0 / 5.0, 3.0 / 10.0, 4.0 / 5.0, 8.0 / 9.0, 1.0, 1.0]);
+
+    ButcherTableau::new(a, b, c, 7, 5).with_embedded(b_hat, 4)
+}
@@ -0,0 +1,203 @@
+use crate::error::SolverError;
+use crate::output::SaveAt;
+use crate::runge_kutta;
+use crate::solution::Solution;
+use crate::solver::SolverOptions;
+use crate::state::{to_dvector, OdeState};
+use nalgebra::DVector;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
+use uncertainty::UncertainValue;
+
+/// Random number generator handed to the sampler of each ensemble member
+pub type EnsembleRng = ChaCha8Rng;
+
+/// The generator for `member` of an ensemble seeded with `seed`. Each member
+/// draws from its own stream, so results do not depend on the number of
+/// threads or the order in which members run.
+pub fn member_rng(seed: u64, member: usize) -> EnsembleRng {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    rng.set_stream(member as u64);
+    rng
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnsembleOptions {
+    pub solver: SolverOptions,
+    pub members: usize,
+    pub seed: u64,
+    /// Times at which every member is saved and the ensemble is summarized
+    pub times: Vec<f64>,
+}
+
+impl EnsembleOptions {
+    pub fn new(members: usize, times: Vec<f64>) -> Self {
+        Self {
+            solver: SolverOptions::default(),
+            members,
+            seed: 0,
+            times,
+        }
+    }
+
+    pub fn solver(mut self, solver: SolverOptions) -> Self {
+        self.solver = solver;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+/// Solutions of every member of an ensemble at the ensemble times
+#[derive(Debug, Clone)]
+pub struct EnsembleSolution<Ty> {
+    pub t: Vec<f64>,
+    pub members: Vec<Solution<Ty>>,
+}
+
+impl<Ty: OdeState> EnsembleSolution<Ty> {
+    /// States of the members that reached time index `i`, one column each
+    fn samples(&self, i: usize) -> Vec<DVector<f64>> {
+        self.members
+            .iter()
+            .filter_map(|sol| {
+                let j = sol.t.iter().position(|&t| t == self.t[i])?;
+                Some(to_dvector(&sol.y[j]))
+            })
+            .collect()
+    }
+
+    pub fn mean(&self, i: usize) -> DVector<f64> {
+        let samples = self.samples(i);
+        let n = samples.len() as f64;
+        let dim = samples.first().map_or(0, |y| y.len());
+        samples.into_iter().fold(DVector::zeros(dim), |a, b| a + b) / n
+    }
+
+    /// Sample standard deviation of each component
+    pub fn std(&self, i: usize) -> DVector<f64> {
+        let samples = self.samples(i);
+        let mean = self.mean(i);
+        let n = samples.len() as f64;
+        let var = samples
+            .iter()
+            .map(|y| (y - &mean).component_mul(&(y - &mean)))
+            .fold(DVector::zeros(mean.len()), |a, b| a + b)
+            / (n - 1.0).max(1.0);
+        var.map(f64::sqrt)
+    }
+
+    /// The `q`-th percentile (0 to 100) of each component, interpolating
+    /// linearly between order statistics
+    pub fn percentile(&self, i: usize, q: f64) -> DVector<f64> {
+        let samples = self.samples(i);
+        let dim = samples.first().map_or(0, |y| y.len());
+        DVector::from_fn(dim, |c, _| {
+            let mut values: Vec<f64> = samples.iter().map(|y| y[c]).collect();
+            values.sort_by(f64::total_cmp);
+            let rank = (q / 100.0).clamp(0.0, 1.0) * (values.len() - 1) as f64;
+            let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+            values[lo] + (rank - lo as f64) * (values[hi] - values[lo])
+        })
+    }
+
+    /// Mean of each component with its standard deviation as uncertainty
+    pub fn uncertain(&self, i: usize) -> Vec<UncertainValue<f64>> {
+        self.mean(i)
+            .iter()
+            .zip(self.std(i).iter())
+            .map(|(&m, &s)| UncertainValue::new(m, s))
+            .collect()
+    }
+}
+
+/// Solves `dy = f(y, t, p)` for `options.members` initial conditions and
+/// parameters drawn by `sample`, in parallel. Each member's sampler receives
+/// the generator from `member_rng`, so an ensemble is reproducible from its
+/// seed. Fails with the error of the first failing member.
+pub fn ensemble<Ty, Tp>(
+    f: impl Fn(&mut Ty, &Ty, f64, &Tp) + Sync,
+    sample: impl Fn(&mut EnsembleRng) -> (Ty, Tp) + Sync,
+    t0: f64,
+    t_end: f64,
+    options: &EnsembleOptions,
+) -> Result<EnsembleSolution<Ty>, SolverError>
+where
+    Ty: OdeState + Send,
+{
+    let solver = options
+        .solver
+        .clone()
+        .save_at(SaveAt::Times(options.times.clone()));
+    let members = (0..options.members)
+        .into_par_iter()
+        .map(|member| {
+            let (y0, p) = sample(&mut member_rng(options.seed, member));
+            runge_kutta(&f, y0, t0, p, t_end, solver.clone())
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut t: Vec<f64> = members.iter().flat_map(|sol| sol.t.clone()).collect();
+    t.sort_by(|a, b| ((t_end - t0) * a).total_cmp(&((t_end - t0) * b)));
+    t.dedup();
+    Ok(EnsembleSolution { t, members })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use rand_distr::{Distribution, Normal};
+
+    fn decay(dy: &mut f64, y: &f64, _t: f64, k: &f64) {
+        *dy = -k * y;
+    }
+
+    fn uniform_rate(rng: &mut EnsembleRng) -> (f64, f64) {
+        (1.0, rng.gen_range(0.5..1.5))
+    }
+
+    #[test]
+    fn test_ensemble_statistics() {
+        let options = EnsembleOptions::new(2000, vec![0.0, 1.0, 2.0]).seed(7);
+        let sol = ensemble(decay, uniform_rate, 0.0, 2.0, &options).unwrap();
+        assert_eq!(sol.t, vec![0.0, 1.0, 2.0]);
+        assert_eq!(sol.members.len(), 2000);
+
+        // E[exp(-k t)] for k uniform on [0.5, 1.5]
+        let t: f64 = 2.0;
+        let expected = ((-0.5 * t).exp() - (-1.5 * t).exp()) / t;
+        assert!((sol.mean(2)[0] - expected).abs() < 0.01);
+        assert_eq!(sol.std(0)[0], 0.0);
+        assert!(sol.std(2)[0] > 0.0);
+        let (lo, median, hi) = (
+            sol.percentile(2, 5.0)[0],
+            sol.percentile(2, 50.0)[0],
+            sol.percentile(2, 95.0)[0],
+        );
+        assert!(lo < median && median < hi);
+        assert!((median - (-t).exp()).abs() < 0.01);
+        let u = sol.uncertain(2)[0];
+        assert_eq!(u.value, sol.mean(2)[0]);
+    }
+
+    #[test]
+    fn test_ensemble_is_reproducible() {
+        let sample = |rng: &mut EnsembleRng| {
+            let y0 = Normal::new(1.0, 0.1).unwrap().sample(rng);
+            (y0, rng.gen_range(0.5..1.5))
+        };
+        let options = EnsembleOptions::new(50, vec![1.0]).seed(42);
+        let a = ensemble(decay, sample, 0.0, 1.0, &options).unwrap();
+        let b = ensemble(decay, sample, 0.0, 1.0, &options).unwrap();
+        for (x, y) in a.members.iter().zip(&b.members) {
+            assert_eq!(x.y, y.y);
+        }
+        let c = ensemble(decay, sample, 0.0, 1.0, &options.clone().seed(43)).unwrap();
+        assert_ne!(a.members[0].y, c.members[0].y);
+    }
+}
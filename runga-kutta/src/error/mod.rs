@@ -0,0 +1,41 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SolverError {
+    /// The step size fell below what floating point can resolve at time `t`
+    StepSizeTooSmall { t: f64, dt: f64 },
+    /// The state or its derivative became NaN or infinite at time `t`
+    NonFiniteState { t: f64 },
+    /// The step limit in the solver options was reached at time `t`
+    MaxStepsExceeded { t: f64 },
+    /// A checkpoint could not be written or read
+    Checkpoint(String),
+    /// The integration options are inconsistent
+    InvalidInput(String),
+    /// A linear system could not be solved
+    SingularMatrix,
+    /// The state could not be projected onto its constraints at time `t`
+    ProjectionFailed { t: f64 },
+}
+
+impl fmt::Display for SolverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SolverError::StepSizeTooSmall { t, dt } => {
+                write!(f, "step size {} too small at t = {}", dt, t)
+            }
+            SolverError::NonFiniteState { t } => write!(f, "non-finite state at t = {}", t),
+            SolverError::MaxStepsExceeded { t } => {
+                write!(f, "maximum number of steps exceeded at t = {}", t)
+            }
+            SolverError::Checkpoint(msg) => write!(f, "checkpoint error: {}", msg),
+            SolverError::InvalidInput(msg) => write!(f, "invalid input: {}", msg),
+            SolverError::SingularMatrix => write!(f, "singular matrix"),
+            SolverError::ProjectionFailed { t } => {
+                write!(f, "projection onto constraints failed at t = {}", t)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SolverError {}
@@ -0,0 +1,270 @@
+use crate::error::SolverError;
+use crate::least_squares::{levenberg_marquardt, LeastSquaresOptions};
+use crate::output::SaveAt;
+use crate::sensitivity::forward_sensitivity;
+use crate::solver::SolverOptions;
+use crate::state::OdeState;
+use nalgebra::{DMatrix, DVector};
+use uncertainty::UncertainValue;
+
+/// A measurement of one state component with its standard deviation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    pub t: f64,
+    pub component: usize,
+    pub value: f64,
+    pub sigma: f64,
+}
+
+impl Measurement {
+    pub fn new(t: f64, component: usize, value: f64, sigma: f64) -> Self {
+        Self {
+            t,
+            component,
+            value,
+            sigma,
+        }
+    }
+
+    /// A measurement whose uncertainty is its standard deviation
+    pub fn uncertain(t: f64, component: usize, value: UncertainValue<f64>) -> Self {
+        Self::new(t, component, value.value, value.uncertainty)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FitOptions {
+    pub solver: SolverOptions,
+    pub least_squares: LeastSquaresOptions,
+    /// Components of the initial state estimated along with the parameters
+    pub free_states: Vec<usize>,
+}
+
+impl FitOptions {
+    pub fn solver(mut self, solver: SolverOptions) -> Self {
+        self.solver = solver;
+        self
+    }
+
+    pub fn least_squares(mut self, least_squares: LeastSquaresOptions) -> Self {
+        self.least_squares = least_squares;
+        self
+    }
+
+    pub fn free_states(mut self, free_states: Vec<usize>) -> Self {
+        self.free_states = free_states;
+        self
+    }
+}
+
+/// Estimated initial state and parameters of a model
+#[derive(Debug, Clone)]
+pub struct Fit<Ty, Tp> {
+    pub y0: Ty,
+    pub p: Tp,
+    /// Components of `y0` that were estimated
+    pub free_states: Vec<usize>,
+    /// Covariance of the free initial states followed by the parameters
+    pub covariance: DMatrix<f64>,
+    /// Weighted residuals `(model - value) / sigma`
+    pub residuals: DVector<f64>,
+    pub chi2: f64,
+    /// Number of measurements less the number of estimated values
+    pub dof: usize,
+    pub iterations: usize,
+    pub converged: bool,
+}
+
+impl<Ty: OdeState, Tp: OdeState> Fit<Ty, Tp> {
+    pub fn reduced_chi2(&self) -> f64 {
+        self.chi2 / self.dof as f64
+    }
+
+    /// Estimated parameters with their standard deviations
+    pub fn parameters(&self) -> Vec<UncertainValue<f64>> {
+        let offset = self.covariance.nrows() - self.p.dim();
+        (0..self.p.dim())
+            .map(|i| {
+                let sigma = self.covariance[(offset + i, offset + i)].sqrt();
+                UncertainValue::new(self.p.component(i), sigma)
+            })
+            .collect()
+    }
+
+    /// Estimated initial states with their standard deviations, in the order
+    /// of `free_states`
+    pub fn initial_states(&self) -> Vec<UncertainValue<f64>> {
+        self.free_states
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| {
+                UncertainValue::new(self.y0.component(c), self.covariance[(i, i)].sqrt())
+            })
+            .collect()
+    }
+}
+
+/// Fits the parameters, and the initial states selected in `options`, of
+/// `dy = f(y, t, p)` to `measurements` by weighted nonlinear least squares.
+/// The Jacobian of the residuals comes from forward sensitivities. `y0` and
+/// `p` are the initial guesses.
+pub fn fit<Ty: OdeState, Tp: OdeState>(
+    f: impl Fn(&mut Ty, &Ty, f64, &Tp),
+    y0: Ty,
+    t0: f64,
+    p: Tp,
+    measurements: &[Measurement],
+    options: &FitOptions,
+) -> Result<Fit<Ty, Tp>, SolverError> {
+    let free = &options.free_states;
+    let (n, m) = (y0.dim(), p.dim());
+    let unknowns = free.len() + m;
+    let invalid = |msg: &str| Err(SolverError::InvalidInput(msg.to_string()));
+    if measurements.len() <= unknowns {
+        return invalid("more measurements than estimated values are required");
+    }
+    if free.iter().any(|&c| c >= n) || measurements.iter().any(|m| m.component >= n) {
+        return invalid("state component out of range");
+    }
+    if measurements
+        .iter()
+        .any(|m| m.sigma <= 0.0 || !m.sigma.is_finite())
+    {
+        return invalid("measurement sigma must be positive and finite");
+    }
+    let t_end = measurements
+        .iter()
+        .map(|m| m.t)
+        .max_by(|a, b| (a - t0).abs().total_cmp(&(b - t0).abs()))
+        .unwrap_or(t0);
+    if measurements.iter().any(|m| (m.t - t0) * (t_end - t0) < 0.0) {
+        return invalid("measurements must all lie on one side of t0");
+    }
+    let times: Vec<f64> = measurements.iter().map(|m| m.t).collect();
+
+    let unpack = |x: &DVector<f64>| {
+        let mut y = y0.clone();
+        for (i, &c) in free.iter().enumerate() {
+            y.set_component(c, x[i]);
+        }
+        let mut q = p.clone();
+        for j in 0..m {
+            q.set_component(j, x[free.len() + j]);
+        }
+        (y, q)
+    };
+
+    let problem = |x: &DVector<f64>| {
+        let (y, q) = unpack(x);
+        let solver = options.solver.clone().save_at(SaveAt::Times(times.clone()));
+        let sol = forward_sensitivity(&f, y, t0, q, t_end, solver)?;
+        let mut r = DVector::zeros(measurements.len());
+        let mut jac = DMatrix::zeros(measurements.len(), unknowns);
+        for (k, meas) in measurements.iter().enumerate() {
+            let i = sol
+                .solution
+                .t
+                .iter()
+                .position(|&t| t == meas.t)
+                .expect("every measurement time is saved");
+            let c = meas.component;
+            r[k] = (sol.solution.y[i].component(c) - meas.value) / meas.sigma;
+            for (j, &s) in free.iter().enumerate() {
+                jac[(k, j)] = sol.stm[i][(c, s)] / meas.sigma;
+            }
+            for j in 0..m {
+                jac[(k, free.len() + j)] = sol.sensitivity[i][(c, j)] / meas.sigma;
+            }
+        }
+        Ok((r, jac))
+    };
+
+    let mut x0 = DVector::zeros(unknowns);
+    for (i, &c) in free.iter().enumerate() {
+        x0[i] = y0.component(c);
+    }
+    for j in 0..m {
+        x0[free.len() + j] = p.component(j);
+    }
+    let result = levenberg_marquardt(problem, x0, &options.least_squares)?;
+    let covariance = result.covariance()?;
+    let (y0, p) = unpack(&result.x);
+
+    Ok(Fit {
+        y0,
+        p,
+        free_states: free.clone(),
+        covariance,
+        chi2: 2.0 * result.cost,
+        residuals: result.residuals,
+        dof: measurements.len() - unknowns,
+        iterations: result.iterations,
+        converged: result.converged,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector2;
+
+    #[test]
+    fn test_fit_decay() {
+        // y = 2 exp(-0.7 t), sampled exactly
+        let f = |dy: &mut f64, y: &f64, _t: f64, k: &f64| *dy = -k * y;
+        let measurements: Vec<Measurement> = (0..10)
+            .map(|i| {
+                let t = 0.5 * i as f64;
+                Measurement::new(t, 0, 2.0 * (-0.7 * t).exp(), 0.01)
+            })
+            .collect();
+        let options = FitOptions::default()
+            .solver(SolverOptions::default().tolerance(1e-10, 1e-12))
+            .free_states(vec![0]);
+        let fit = fit(f, 1.0, 0.0, 0.2, &measurements, &options).unwrap();
+        assert!(fit.converged);
+        assert!((fit.y0 - 2.0).abs() < 1e-6);
+        assert_eq!(fit.initial_states().len(), 1);
+        assert!((fit.p - 0.7).abs() < 1e-6);
+        assert!(fit.chi2 < 1e-6);
+        assert_eq!(fit.dof, 8);
+    }
+
+    #[test]
+    fn test_fit_uncertain_measurements() {
+        // oscillator frequency from noisy positions
+        let f = |dy: &mut Vector2<f64>, y: &Vector2<f64>, _t: f64, w: &f64| {
+            *dy = Vector2::new(y[1], -w * w * y[0]);
+        };
+        let noise = [
+            0.01, -0.02, 0.015, -0.005, 0.0, 0.02, -0.01, -0.015, 0.005, 0.01,
+        ];
+        let measurements: Vec<Measurement> = noise
+            .iter()
+            .enumerate()
+            .map(|(i, e)| {
+                let t = 0.3 * (i + 1) as f64;
+                Measurement::uncertain(t, 0, UncertainValue::new((1.5 * t).cos() + e, 0.02))
+            })
+            .collect();
+        let options =
+            FitOptions::default().solver(SolverOptions::default().tolerance(1e-10, 1e-12));
+        let fit = fit(f, Vector2::new(1.0, 0.0), 0.0, 1.3, &measurements, &options).unwrap();
+        let w = fit.parameters()[0];
+        assert!(w.uncertainty > 0.0);
+        assert!((w.value - 1.5).abs() < 3.0 * w.uncertainty);
+        assert!(fit.reduced_chi2() < 3.0);
+    }
+
+    #[test]
+    fn test_fit_rejects_bad_input() {
+        let f = |dy: &mut f64, y: &f64, _t: f64, k: &f64| *dy = -k * y;
+        let one = [Measurement::new(1.0, 0, 1.0, 0.1)];
+        assert!(fit(f, 1.0, 0.0, 1.0, &one, &FitOptions::default()).is_err());
+        let bad_sigma = [
+            Measurement::new(1.0, 0, 1.0, 0.0),
+            Measurement::new(2.0, 0, 1.0, 0.1),
+        ];
+        assert!(fit(f, 1.0, 0.0, 1.0, &bad_sigma, &FitOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use serde::{Deserialize, Serialize};
+
+/// Which sign changes of an event condition trigger the event. Directions are
+/// taken along the direction of integration, so a condition that increases
+/// with time is `Falling` when integrating backward.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Crossing {
+    Rising,
+    Falling,
+    #[default]
+    Either,
+}
+
+impl Crossing {
+    pub(crate) fn crossed(&self, g0: f64, g1: f64) -> bool {
+        let rising = g0 < 0.0 && g1 >= 0.0;
+        let falling = g0 > 0.0 && g1 <= 0.0;
+        match self {
+            Crossing::Rising => rising,
+            Crossing::Falling => falling,
+            Crossing::Either => rising || falling,
+        }
+    }
+}
+
+type Condition<'a, Ty, Tp> = Box<dyn Fn(f64, &Ty, &Tp) -> f64 + 'a>;
+
+/// A zero crossing of `condition(t, y, p)` to be located during integration
+pub struct Event<'a, Ty, Tp> {
+    pub(crate) condition: Condition<'a, Ty, Tp>,
+    pub(crate) crossing: Crossing,
+    pub(crate) terminal: bool,
+}
+
+impl<'a, Ty, Tp> Event<'a, Ty, Tp> {
+    pub fn new(condition: impl Fn(f64, &Ty, &Tp) -> f64 + 'a) -> Self {
+        Self {
+            condition: Box::new(condition),
+            crossing: Crossing::Either,
+            terminal: false,
+        }
+    }
+
+    pub fn crossing(mut self, crossing: Crossing) -> Self {
+        self.crossing = crossing;
+        self
+    }
+
+    /// Stop the integration at the first occurrence of this event
+    pub fn terminal(mut self, terminal: bool) -> Self {
+        self.terminal = terminal;
+        self
+    }
+
+    pub(crate) fn evaluate(&self, t: f64, y: &Ty, p: &Tp) -> f64 {
+        (self.condition)(t, y, p)
+    }
+}
+
+/// An event located during integration. `index` is the position of the event
+/// in the order the events were added.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventRecord<Ty> {
+    pub index: usize,
+    pub t: f64,
+    pub y: Ty,
+}
+
+/// Locates a root of `g` bracketed by `(ta, ga)` and `(tb, gb)` using the
+/// Illinois variant of regula falsi. Returns the end of the final bracket on
+/// the `tb` side so the located time never precedes the sign change.
+pub(crate) fn find_root(
+    g: impl Fn(f64) -> f64,
+    mut ta: f64,
+    mut ga: f64,
+    mut tb: f64,
+    mut gb: f64,
+) -> f64 {
+    if gb == 0.0 {
+        return tb;
+    }
+    let mut side = 0;
+    for _ in 0..100 {
+        let tol = 4.0 * f64::EPSILON * ta.abs().max(tb.abs()).max(1.0);
+        if (tb - ta).abs() <= tol {
+            break;
+        }
+        let mut tm = (ta * gb - tb * ga) / (gb - ga);
+        if !tm.is_finite() || (tm - ta) * (tm - tb) >= 0.0 {
+            tm = 0.5 * (ta + tb);
+        }
+        let gm = g(tm);
+        if gm == 0.0 {
+            return tm;
+        }
+        if gm.signum() == gb.signum() {
+            tb = tm;
+            gb = gm;
+            if side == -1 {
+                ga *= 0.5;
+            }
+            side = -1;
+        } else {
+            ta = tm;
+            ga = gm;
+            if side == 1 {
+                gb *= 0.5;
+            }
+            side = 1;
+        }
+    }
+    tb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crossed() {
+        assert!(Crossing::Rising.crossed(-1.0, 1.0));
+        assert!(!Crossing::Rising.crossed(1.0, -1.0));
+        assert!(Crossing::Falling.crossed(1.0, 0.0));
+        assert!(Crossing::Either.crossed(1.0, -1.0));
+        assert!(!Crossing::Either.crossed(0.0, 1.0));
+    }
+
+    #[test]
+    fn test_find_root() {
+        let g = |t: f64| t.cos();
+        let root = find_root(g, 0.0, 1.0, 3.0, 3.0_f64.cos());
+        assert!((root - std::f64::consts::FRAC_PI_2).abs() < 1e-14);
+
+        // backward bracket
+        let root = find_root(g, 3.0, 3.0_f64.cos(), 0.0, 1.0);
+        assert!((root - std::f64::consts::FRAC_PI_2).abs() < 1e-14);
+    }
+}
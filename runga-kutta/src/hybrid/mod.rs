@@ -0,0 +1,310 @@
+use crate::error::SolverError;
+use crate::integrator::Stats;
+use crate::output::{SaveAt, Saver, Step};
+use crate::solution::Solution;
+use crate::solver::Solver;
+use crate::state::OdeState;
+use std::cell::RefCell;
+
+type Update<'a, Ty, Td, Tp> = Box<dyn FnMut(&mut Td, &Ty, f64, &Tp) + 'a>;
+
+/// Two sample times closer than this, relative to their magnitude, coincide
+const SAMPLE_TOL: f64 = 1e-9;
+
+/// A discrete-time update run at `offset + n * period`
+struct DiscreteUpdate<'a, Ty, Td, Tp> {
+    period: f64,
+    offset: f64,
+    update: Update<'a, Ty, Td, Tp>,
+}
+
+impl<Ty, Td, Tp> DiscreteUpdate<'_, Ty, Td, Tp> {
+    /// The first sample at or after `t`
+    fn next_sample(&self, t: f64) -> f64 {
+        let n = ((t - self.offset) / self.period).ceil().max(0.0);
+        let sample = self.offset + n * self.period;
+        if same_time(sample - self.period, t) && n > 0.0 {
+            sample - self.period
+        } else {
+            sample
+        }
+    }
+}
+
+fn same_time(a: f64, b: f64) -> bool {
+    (a - b).abs() <= SAMPLE_TOL * a.abs().max(b.abs()).max(1.0)
+}
+
+/// Discrete-time updates, such as flight software running at fixed rates,
+/// that drive a continuous plant. The discrete state is held constant between
+/// samples and passed to the right-hand side, so outputs written into it act
+/// as zero-order holds.
+pub struct Hybrid<'a, Ty, Td, Tp> {
+    updates: Vec<DiscreteUpdate<'a, Ty, Td, Tp>>,
+}
+
+impl<'a, Ty, Td, Tp> Default for Hybrid<'a, Ty, Td, Tp> {
+    fn default() -> Self {
+        Self { updates: vec![] }
+    }
+}
+
+impl<'a, Ty: OdeState, Td, Tp> Hybrid<'a, Ty, Td, Tp> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `update(d, y, t, p)`, run at `offset + n * period` for
+    /// `n = 0, 1, ...`. Updates due at the same sample run in the order they
+    /// were added and see the discrete state left by the ones before.
+    pub fn update(
+        mut self,
+        period: f64,
+        offset: f64,
+        update: impl FnMut(&mut Td, &Ty, f64, &Tp) + 'a,
+    ) -> Self {
+        self.updates.push(DiscreteUpdate {
+            period,
+            offset,
+            update: Box::new(update),
+        });
+        self
+    }
+
+    /// The next sample of any update strictly after `t`, if before `t_end`
+    fn next_sample(&self, t: f64, t_end: f64) -> Option<f64> {
+        self.updates
+            .iter()
+            .map(|u| {
+                let s = u.next_sample(t);
+                if same_time(s, t) {
+                    u.next_sample(s + u.period)
+                } else {
+                    s
+                }
+            })
+            .filter(|&s| s < t_end && !same_time(s, t_end))
+            .min_by(f64::total_cmp)
+    }
+
+    /// Runs the updates with a sample at `t`
+    fn run(&mut self, d: &mut Td, y: &Ty, t: f64, p: &Tp) -> bool {
+        let mut ran = false;
+        for u in self.updates.iter_mut() {
+            if same_time(u.next_sample(t), t) {
+                (u.update)(d, y, t, p);
+                ran = true;
+            }
+        }
+        ran
+    }
+}
+
+/// Continuous solution of a hybrid system with the discrete state after each
+/// sample
+#[derive(Debug, Clone)]
+pub struct HybridSolution<Ty, Td> {
+    pub solution: Solution<Ty>,
+    /// Sample times, starting with the initial time
+    pub t: Vec<f64>,
+    pub discrete: Vec<Td>,
+}
+
+/// A `Solver` with the discrete-time updates set by `Solver::hybrid`
+pub struct HybridSolver<'a, Ty, Td, Tp> {
+    pub(crate) solver: Solver<'a, Ty, Tp>,
+    pub(crate) hybrid: Hybrid<'a, Ty, Td, Tp>,
+}
+
+impl<'a, Ty: OdeState, Td: Clone, Tp> HybridSolver<'a, Ty, Td, Tp> {
+    /// Integrates `dy = f(y, d, t, p)` forward from `t0`, where the discrete
+    /// state `d` changes only at the samples of the updates. The integration
+    /// stops exactly at every sample, runs the updates that are due and
+    /// restarts from there, so no step straddles a change of `d`. Samples at
+    /// `t0` run before the integration starts; samples at `t_end` do not run.
+    pub fn solve(
+        self,
+        f: impl Fn(&mut Ty, &Ty, &Td, f64, &Tp),
+        y0: Ty,
+        d0: Td,
+        t0: f64,
+        mut p: Tp,
+        t_end: f64,
+    ) -> Result<HybridSolution<Ty, Td>, SolverError> {
+        let invalid = |msg: &str| Err(SolverError::InvalidInput(msg.to_string()));
+        let HybridSolver { solver, mut hybrid } = self;
+        if t_end < t0 {
+            return invalid("hybrid systems are integrated forward in time");
+        }
+        if hybrid
+            .updates
+            .iter()
+            .any(|u| u.period <= 0.0 || !u.period.is_finite() || !u.offset.is_finite())
+        {
+            return invalid("update periods must be positive and finite");
+        }
+
+        // the right-hand side of each segment reads `d`, which the updates
+        // change between segments
+        let d = RefCell::new(d0);
+        hybrid.run(&mut d.borrow_mut(), &y0, t0, &p);
+        let mut sol = HybridSolution {
+            solution: Solution::default(),
+            t: vec![t0],
+            discrete: vec![d.borrow().clone()],
+        };
+        let options = solver.options.clone();
+        let mut saver = Saver::new(options.save_at.clone(), t0, t_end);
+        let mut stats = Stats::default();
+        let (mut t, mut y) = (t0, y0);
+        // rebound so the segments may borrow `d` for less than `'a`
+        let mut solver: Solver<Ty, Tp> = solver;
+        let mut dt = options.dt;
+        loop {
+            let sample = hybrid.next_sample(t, t_end);
+            let segment_end = sample.unwrap_or(t_end);
+            let mut segment = options.clone();
+            segment.dt = dt;
+            // the output is saved across segments below
+            segment.save_at = SaveAt::Final;
+            segment.max_steps = options
+                .max_steps
+                .saturating_sub(stats.accepted + stats.rejected);
+            solver.options = segment;
+            let rhs = |dy: &mut Ty, y: &Ty, t: f64, p: &Tp| f(dy, y, &d.borrow(), t, p);
+            let mut integrator = solver.integrator(rhs, y, t, p, segment_end)?;
+            if t == t0 {
+                saver.start(&mut sol.solution, t, integrator.y(), integrator.dy());
+            }
+            while !integrator.is_finished() {
+                let (t_prev, y_prev, dy_prev) = (
+                    integrator.t(),
+                    integrator.y().clone(),
+                    integrator.dy().clone(),
+                );
+                integrator.step()?;
+                saver.after_step(
+                    &mut sol.solution,
+                    Step {
+                        t0: t_prev,
+                        y0: &y_prev,
+                        dy0: &dy_prev,
+                        t1: integrator.t(),
+                        y1: integrator.y(),
+                        dy1: integrator.dy(),
+                    },
+                );
+            }
+            stats += integrator.stats();
+            sol.solution.events.extend(integrator.take_events());
+            if options.tol.is_some() {
+                dt = Some(integrator.dt().abs());
+            }
+            t = integrator.t();
+            y = integrator.y().clone();
+            if sample.is_none() || t != segment_end {
+                let dy = integrator.dy().clone();
+                saver.finish(&mut sol.solution, t, &y, &dy);
+                break;
+            }
+            (solver, p) = integrator.detach();
+            if hybrid.run(&mut d.borrow_mut(), &y, t, &p) {
+                sol.t.push(t);
+                sol.discrete.push(d.borrow().clone());
+            }
+        }
+        sol.solution.stats = stats;
+        Ok(sol)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::butcher::Tableau;
+    use crate::events::Event;
+    use crate::solver::SolverOptions;
+
+    #[test]
+    fn test_sampled_feedback() {
+        // y' = u with u = -k y sampled every 0.1 and held: y(n / 10) = (1 - k / 10)^n
+        let plant = |dy: &mut f64, _y: &f64, u: &f64, _t: f64, _k: &f64| *dy = *u;
+        let controller = Hybrid::new().update(0.1, 0.0, |u: &mut f64, y: &f64, _t, k: &f64| {
+            *u = -k * y;
+        });
+        let sol = Solver::default()
+            .hybrid(controller)
+            .solve(plant, 1.0, 0.0, 0.0, 5.0, 1.0)
+            .unwrap();
+        let (t1, y1) = sol.solution.last().unwrap();
+        assert_eq!(t1, 1.0);
+        assert!((y1 - 0.5_f64.powi(10)).abs() < 1e-12);
+        assert_eq!(sol.t.len(), 10);
+        for (i, &t) in sol.t.iter().enumerate() {
+            assert!((t - 0.1 * i as f64).abs() < 1e-12);
+            assert!(sol.solution.t.contains(&t));
+        }
+        assert!((sol.discrete[1] + 2.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_rates_and_offsets() {
+        // a fast sampler and a slow counter offset from it
+        #[derive(Debug, Clone, Default)]
+        struct Software {
+            sampled: f64,
+            ticks: usize,
+        }
+        let plant = |dy: &mut f64, _y: &f64, _d: &Software, _t: f64, _p: &()| *dy = 1.0;
+        let software = Hybrid::new()
+            .update(0.2, 0.0, |d: &mut Software, y: &f64, _t, _p: &()| {
+                d.sampled = *y
+            })
+            .update(0.25, 0.1, |d: &mut Software, _y: &f64, _t, _p: &()| {
+                d.ticks += 1
+            });
+        let options = SolverOptions::fixed(Tableau::RK4, 0.3);
+        let sol = Solver::new(options)
+            .hybrid(software)
+            .solve(plant, 0.0, Software::default(), 0.0, (), 1.0)
+            .unwrap();
+        let expected = [0.0, 0.1, 0.2, 0.35, 0.4, 0.6, 0.8, 0.85];
+        assert_eq!(sol.t.len(), expected.len());
+        for (t, e) in sol.t.iter().zip(expected) {
+            assert!((t - e).abs() < 1e-12);
+        }
+        let last = sol.discrete.last().unwrap();
+        assert_eq!(last.ticks, 4);
+        assert!((last.sampled - 0.8).abs() < 1e-12);
+        // fixed steps restart at each sample
+        assert!(sol
+            .solution
+            .t
+            .windows(2)
+            .all(|w| w[1] - w[0] <= 0.3 + 1e-12));
+    }
+
+    #[test]
+    fn test_events_and_observers_span_segments() {
+        // the sampled feedback above falls through 0.1 in the segment after
+        // the sample at 0.3, where y = 0.125 - 0.625 (t - 0.3)
+        let plant = |dy: &mut f64, _y: &f64, u: &f64, _t: f64, _k: &f64| *dy = *u;
+        let controller = Hybrid::new().update(0.1, 0.0, |u: &mut f64, y: &f64, _t, k: &f64| {
+            *u = -k * y;
+        });
+        let mut steps = 0;
+        let sol = Solver::default()
+            .event(Event::new(|_t, y: &f64, _k: &f64| y - 0.1).terminal(true))
+            .observer(|_t, _y| steps += 1)
+            .hybrid(controller)
+            .solve(plant, 1.0, 0.0, 0.0, 5.0, 1.0)
+            .unwrap();
+        let (t1, y1) = sol.solution.last().unwrap();
+        assert!((t1 - 0.34).abs() < 1e-9);
+        assert!((y1 - 0.1).abs() < 1e-9);
+        assert_eq!(sol.solution.events.len(), 1);
+        assert_eq!(sol.solution.events[0].t, t1);
+        assert_eq!(sol.t.len(), 4);
+        assert_eq!(steps, sol.solution.stats.accepted);
+    }
+}
@@ -0,0 +1,902 @@
+use crate::butcher::ButcherTableau;
+use crate::checkpoint::Checkpoint;
+use crate::error::SolverError;
+use crate::events::{find_root, Event, EventRecord};
+use crate::output::{Observer, Saver, Step};
+use crate::projection::Projection;
+use crate::solution::{hermite, Solution};
+use crate::solver::{Solver, SolverOptions};
+use crate::state::{axpy, is_finite, OdeState};
+use serde::{Deserialize, Serialize};
+
+/// Relative and absolute error tolerances for adaptive stepping
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Tolerance {
+    pub rtol: f64,
+    pub atol: f64,
+}
+
+impl Tolerance {
+    pub fn new(rtol: f64, atol: f64) -> Self {
+        Self { rtol, atol }
+    }
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            rtol: 1e-6,
+            atol: 1e-9,
+        }
+    }
+}
+
+/// Counters collected during an integration
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stats {
+    pub accepted: usize,
+    pub rejected: usize,
+    pub fevals: usize,
+    pub events: usize,
+}
+
+impl std::ops::AddAssign for Stats {
+    fn add_assign(&mut self, other: Stats) {
+        self.accepted += other.accepted;
+        self.rejected += other.rejected;
+        self.fevals += other.fevals;
+        self.events += other.events;
+    }
+}
+
+/// Boxed right-hand side `f(dy, y, t, p)`
+pub type OdeFunction<'a, Ty, Tp> = Box<dyn Fn(&mut Ty, &Ty, f64, &Tp) + 'a>;
+
+const SAFETY: f64 = 0.9;
+const FAC_MIN: f64 = 0.2;
+const FAC_MAX: f64 = 10.0;
+const BETA: f64 = 0.04;
+
+/// Times an integration steps onto exactly: the stops strictly inside
+/// `(t0, t_end)`, sorted along the integration, then `t_end`
+#[derive(Debug, Clone)]
+pub(crate) struct Stops {
+    times: Vec<f64>,
+    t_end: f64,
+    dir: f64,
+}
+
+impl Stops {
+    pub(crate) fn new(stops: impl IntoIterator<Item = f64>, t0: f64, t_end: f64) -> Self {
+        let dir = if t_end < t0 { -1.0 } else { 1.0 };
+        let mut times: Vec<f64> = stops
+            .into_iter()
+            .filter(|&s| dir * (s - t0) > 0.0 && dir * (t_end - s) > 0.0)
+            .collect();
+        times.sort_by(|a, b| (dir * a).total_cmp(&(dir * b)));
+        times.dedup();
+        Self { times, t_end, dir }
+    }
+
+    /// The first stop ahead of `t`, or the end time
+    pub(crate) fn next(&self, t: f64) -> f64 {
+        let dir = self.dir;
+        let i = self.times.partition_point(|&s| dir * (s - t) <= 0.0);
+        self.times.get(i).copied().unwrap_or(self.t_end)
+    }
+}
+
+/// Limits a step of size `h` from `t` to `dtmax`, and shortens it to land on
+/// `target` when it would reach or nearly reach it. Returns the signed step
+/// and whether it ends on the target, or `StepSizeTooSmall` below `dtmin` or
+/// the resolution of `t`.
+pub(crate) fn clamp_step(
+    t: f64,
+    h: f64,
+    target: f64,
+    options: &SolverOptions,
+) -> Result<(f64, bool), SolverError> {
+    let remaining = target - t;
+    let h = remaining.signum() * h.abs().min(options.dtmax);
+    let last = remaining.abs() - h.abs() <= 1e-10 * h.abs();
+    let h = if last { remaining } else { h };
+    if h.abs() <= 16.0 * f64::EPSILON * t.abs() || (!last && h.abs() < options.dtmin) {
+        return Err(SolverError::StepSizeTooSmall { t, dt: h });
+    }
+    Ok((h, last))
+}
+
+/// RMS of the error estimate `err` of a step from `y0` to `y1`, scaled
+/// componentwise by the tolerances
+pub(crate) fn error_norm<Ty: OdeState>(err: &Ty, y0: &Ty, y1: &Ty, tol: &Tolerance) -> f64 {
+    let n = err.dim();
+    let mut sum = 0.0;
+    for i in 0..n {
+        let scale = tol.atol + tol.rtol * y0.component(i).abs().max(y1.component(i).abs());
+        let e = err.component(i) / scale;
+        sum += e * e;
+    }
+    (sum / n.max(1) as f64).sqrt()
+}
+
+/// Hairer-Wanner estimate of a first step from `(t, y)` towards `t_end` for a
+/// method of order `order`, from the tolerances in `options`, the derivative
+/// `dy` and the derivative `eval(y1, t1)` after a trial Euler step. Returns
+/// the signed step, clamped into the step limits.
+pub(crate) fn initial_step<Ty: OdeState>(
+    y: &Ty,
+    dy: &Ty,
+    t: f64,
+    t_end: f64,
+    order: f64,
+    options: &SolverOptions,
+    mut eval: impl FnMut(&Ty, f64) -> Ty,
+) -> f64 {
+    let tol = options.tol.unwrap_or_default();
+    let dir = if t_end < t { -1.0 } else { 1.0 };
+    let span = (t_end - t).abs();
+    let n = y.dim().max(1) as f64;
+    let scale: Vec<f64> = (0..y.dim())
+        .map(|i| tol.atol + tol.rtol * y.component(i).abs())
+        .collect();
+    let rms = |x: &Ty| {
+        let sum: f64 = (0..x.dim())
+            .map(|i| (x.component(i) / scale[i]).powi(2))
+            .sum();
+        (sum / n).sqrt()
+    };
+    let d0 = rms(y);
+    let d1 = rms(dy);
+    let h0 = if d0 < 1e-5 || d1 < 1e-5 {
+        1e-6
+    } else {
+        0.01 * d0 / d1
+    };
+    let h0 = h0.min(span);
+
+    let y1 = axpy(y, dir * h0, dy);
+    let dy1 = eval(&y1, t + dir * h0);
+    let d2 = rms(&(dy1 - dy.clone())) / h0;
+
+    let d = d1.max(d2);
+    let h1 = if d <= 1e-15 {
+        (h0 * 1e-3).max(1e-6)
+    } else {
+        (0.01 / d).powf(1.0 / (order + 1.0))
+    };
+    let h = (100.0 * h0).min(h1).min(span);
+    dir * h.clamp(options.dtmin, options.dtmax)
+}
+
+/// Steps an ODE `dy = f(y, t, p)` from `t0` towards `t_end`, which may lie
+/// before `t0` to integrate backward in time.
+pub struct Integrator<'a, Ty, Tp> {
+    f: OdeFunction<'a, Ty, Tp>,
+    p: Tp,
+    options: SolverOptions,
+    tableau: ButcherTableau,
+    t_end: f64,
+    dir: f64,
+    /// `options.tstops` strictly between the start and `t_end`, in the
+    /// direction of integration
+    stops: Stops,
+    t: f64,
+    y: Ty,
+    dy: Ty,
+    t_prev: f64,
+    y_prev: Ty,
+    dy_prev: Ty,
+    h: f64,
+    err_prev: f64,
+    rejected_last: bool,
+    events: Vec<Event<'a, Ty, Tp>>,
+    g: Vec<f64>,
+    found: Vec<EventRecord<Ty>>,
+    terminated: bool,
+    observers: Vec<Observer<'a, Ty>>,
+    projections: Vec<Projection<'a, Ty, Tp>>,
+    /// Output selected by `options.save_at` so far
+    saver: Saver,
+    saved: Solution<Ty>,
+    stats: Stats,
+}
+
+impl<'a, Ty: OdeState, Tp> Integrator<'a, Ty, Tp> {
+    /// Only the magnitude of `options.dt` is used; the sign of the step
+    /// follows from `t_end - t0`. Fails if the options are invalid.
+    pub fn new(
+        f: impl Fn(&mut Ty, &Ty, f64, &Tp) + 'a,
+        y0: Ty,
+        t0: f64,
+        p: Tp,
+        t_end: f64,
+        options: SolverOptions,
+    ) -> Result<Self, SolverError> {
+        options.validate()?;
+        let dir = if t_end < t0 { -1.0 } else { 1.0 };
+        let tableau = options.tableau.tableau();
+        let stops = Stops::new(options.tstops.iter().copied(), t0, t_end);
+        let dt = options.dt;
+        let mut dy = y0.zeros_like();
+        f(&mut dy, &y0, t0, &p);
+        let mut saver = Saver::new(options.save_at.clone(), t0, t_end);
+        let mut saved = Solution::default();
+        saver.start(&mut saved, t0, &y0, &dy);
+        Ok(Self {
+            f: Box::new(f),
+            p,
+            options,
+            tableau,
+            t_end,
+            dir,
+            stops,
+            t: t0,
+            y: y0.clone(),
+            dy: dy.clone(),
+            t_prev: t0,
+            y_prev: y0,
+            dy_prev: dy,
+            h: dir * dt.unwrap_or(0.0).abs(),
+            err_prev: 1e-4,
+            rejected_last: false,
+            events: vec![],
+            g: vec![],
+            found: vec![],
+            terminated: false,
+            observers: vec![],
+            projections: vec![],
+            saver,
+            saved,
+            stats: Stats {
+                fevals: 1,
+                ..Stats::default()
+            },
+        })
+    }
+
+    /// Resumes an integration from a checkpoint. Events must be added again
+    /// in their original order, and projections again.
+    pub fn restore(
+        f: impl Fn(&mut Ty, &Ty, f64, &Tp) + 'a,
+        p: Tp,
+        checkpoint: Checkpoint<Ty>,
+    ) -> Result<Self, SolverError> {
+        checkpoint.options.validate()?;
+        let tableau = checkpoint.options.tableau.tableau();
+        let stops = Stops::new(
+            checkpoint.options.tstops.iter().copied(),
+            checkpoint.t,
+            checkpoint.t_end,
+        );
+        Ok(Self {
+            f: Box::new(f),
+            p,
+            tableau,
+            stops,
+            options: checkpoint.options,
+            t_end: checkpoint.t_end,
+            dir: if checkpoint.t_end < checkpoint.t {
+                -1.0
+            } else {
+                1.0
+            },
+            t: checkpoint.t,
+            y: checkpoint.y,
+            dy: checkpoint.dy,
+            t_prev: checkpoint.t_prev,
+            y_prev: checkpoint.y_prev,
+            dy_prev: checkpoint.dy_prev,
+            h: checkpoint.dt,
+            err_prev: checkpoint.err_prev,
+            rejected_last: checkpoint.rejected_last,
+            events: vec![],
+            g: checkpoint.g,
+            found: vec![],
+            terminated: checkpoint.terminated,
+            observers: vec![],
+            projections: vec![],
+            saver: checkpoint.saver,
+            saved: checkpoint.saved,
+            stats: checkpoint.stats,
+        })
+    }
+
+    /// Snapshot of the integration state for restarting with `restore`
+    pub fn checkpoint(&self) -> Checkpoint<Ty> {
+        Checkpoint {
+            options: self.options.clone(),
+            t_end: self.t_end,
+            t: self.t,
+            y: self.y.clone(),
+            dy: self.dy.clone(),
+            t_prev: self.t_prev,
+            y_prev: self.y_prev.clone(),
+            dy_prev: self.dy_prev.clone(),
+            dt: self.h,
+            err_prev: self.err_prev,
+            rejected_last: self.rejected_last,
+            g: self.g.clone(),
+            terminated: self.terminated,
+            saver: self.saver.clone(),
+            saved: self.saved.clone(),
+            stats: self.stats,
+        }
+    }
+
+    pub fn add_event(&mut self, event: Event<'a, Ty, Tp>) {
+        self.events.push(event);
+    }
+
+    /// Adds a callback invoked with `(t, y)` after each accepted step
+    pub fn add_observer(&mut self, observer: impl FnMut(f64, &Ty) + 'a) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Adds a projection applied, in the order added, to the state after
+    /// each accepted step
+    pub fn add_projection(&mut self, projection: Projection<'a, Ty, Tp>) {
+        self.projections.push(projection);
+    }
+
+    pub(crate) fn add_boxed_observer(&mut self, observer: Observer<'a, Ty>) {
+        self.observers.push(observer);
+    }
+
+    /// Takes back the parameters, events, observers and projections, the
+    /// reverse of `Solver::integrator`
+    pub(crate) fn detach(self) -> (Solver<'a, Ty, Tp>, Tp) {
+        let mut solver = Solver::new(self.options);
+        solver.events = self.events;
+        solver.observers = self.observers;
+        solver.projections = self.projections;
+        (solver, self.p)
+    }
+
+    pub fn options(&self) -> &SolverOptions {
+        &self.options
+    }
+
+    pub fn t(&self) -> f64 {
+        self.t
+    }
+
+    pub fn y(&self) -> &Ty {
+        &self.y
+    }
+
+    pub fn dy(&self) -> &Ty {
+        &self.dy
+    }
+
+    pub fn p(&self) -> &Tp {
+        &self.p
+    }
+
+    /// Signed size of the next step
+    pub fn dt(&self) -> f64 {
+        self.h
+    }
+
+    /// +1 for forward integration, -1 for backward
+    pub fn direction(&self) -> f64 {
+        self.dir
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.terminated || self.t == self.t_end
+    }
+
+    /// Events located by the most recent calls to `step`
+    pub fn take_events(&mut self) -> Vec<EventRecord<Ty>> {
+        std::mem::take(&mut self.found)
+    }
+
+    /// Dense output over the last accepted step
+    pub fn interpolate(&self, t: f64) -> Ty {
+        hermite(
+            self.t_prev,
+            &self.y_prev,
+            &self.dy_prev,
+            self.t,
+            &self.y,
+            &self.dy,
+            t,
+        )
+    }
+
+    fn project(&self, y: &mut Ty, t: f64) -> Result<(), SolverError> {
+        for projection in &self.projections {
+            projection.apply(y, t, &self.p)?;
+        }
+        Ok(())
+    }
+
+    fn eval(&mut self, y: &Ty, t: f64) -> Ty {
+        let mut dy = y.zeros_like();
+        (self.f)(&mut dy, y, t, &self.p);
+        self.stats.fevals += 1;
+        dy
+    }
+
+    /// Takes a single Runge-Kutta step of size `h` from the current point.
+    /// Returns the new state, the last stage and the error estimate.
+    fn rk_step(&mut self, h: f64) -> (Ty, Ty, Option<Ty>) {
+        let bt = self.tableau;
+        let s = bt.stages;
+        let mut k: Vec<Ty> = Vec::with_capacity(s);
+        k.push(self.dy.clone());
+        for i in 1..s {
+            let mut yi = self.y.clone();
+            for (j, kj) in k.iter().enumerate() {
+                if bt.a[i][j] != 0.0 {
+                    yi = axpy(&yi, h * bt.a[i][j], kj);
+                }
+            }
+            let ki = self.eval(&yi, self.t + bt.c[i] * h);
+            k.push(ki);
+        }
+        let mut y_new = self.y.clone();
+        for (i, ki) in k.iter().enumerate() {
+            if bt.b[i] != 0.0 {
+                y_new = axpy(&y_new, h * bt.b[i], ki);
+            }
+        }
+        let err = bt.btilde.map(|btilde| {
+            let mut err = self.y.zeros_like();
+            for (i, ki) in k.iter().enumerate() {
+                if btilde[i] != 0.0 {
+                    err = axpy(&err, h * btilde[i], ki);
+                }
+            }
+            err
+        });
+        let last = k.pop().unwrap();
+        (y_new, last, err)
+    }
+
+    /// Advances the integration by one accepted step
+    pub fn step(&mut self) -> Result<(), SolverError> {
+        if self.is_finished() {
+            return Ok(());
+        }
+        if self.stats.accepted + self.stats.rejected >= self.options.max_steps {
+            return Err(SolverError::MaxStepsExceeded { t: self.t });
+        }
+        if self.h == 0.0 && self.options.tol.is_some() {
+            let (y, dy) = (self.y.clone(), self.dy.clone());
+            let (t, t_end, order) = (self.t, self.t_end, self.tableau.order as f64);
+            let options = self.options.clone();
+            self.h = initial_step(&y, &dy, t, t_end, order, &options, |y, t| self.eval(y, t));
+        }
+        if self.g.len() != self.events.len() {
+            self.g = self
+                .events
+                .iter()
+                .map(|e| e.evaluate(self.t, &self.y, &self.p))
+                .collect();
+        }
+
+        let fixed_h = self.h;
+        let mut h = self.h;
+        let target = self.stops.next(self.t);
+        let (y_new, last_stage, h_used, last) = loop {
+            let (h_try, last) = clamp_step(self.t, h, target, &self.options)?;
+            h = h_try;
+
+            let (y_new, last_stage, err) = self.rk_step(h);
+
+            let tol = match self.options.tol {
+                Some(tol) => tol,
+                None => {
+                    self.h = fixed_h;
+                    break (y_new, last_stage, h, last);
+                }
+            };
+            let err = error_norm(&err.unwrap(), &self.y, &y_new, &tol);
+            let expo = 1.0 / self.tableau.order as f64;
+            if err <= 1.0 {
+                let alpha = expo - 0.75 * BETA;
+                let err = err.max(1e-10);
+                let mut fac = SAFETY * err.powf(-alpha) * self.err_prev.powf(BETA);
+                fac = fac.clamp(FAC_MIN, if self.rejected_last { 1.0 } else { FAC_MAX });
+                self.err_prev = err.max(1e-4);
+                self.rejected_last = false;
+                self.h = h * fac;
+                break (y_new, last_stage, h, last);
+            }
+            self.stats.rejected += 1;
+            self.rejected_last = true;
+            let fac = if err.is_finite() {
+                (SAFETY * err.powf(-expo)).max(FAC_MIN)
+            } else {
+                FAC_MIN
+            };
+            h *= fac;
+        };
+
+        let t_new = if last { target } else { self.t + h_used };
+        let mut y_new = y_new;
+        self.project(&mut y_new, t_new)?;
+        let dy_new = if self.tableau.is_fsal() && self.projections.is_empty() {
+            last_stage
+        } else {
+            self.eval(&y_new, t_new)
+        };
+        if !is_finite(&y_new) || !is_finite(&dy_new) {
+            return Err(SolverError::NonFiniteState { t: t_new });
+        }
+        self.stats.accepted += 1;
+
+        self.t_prev = self.t;
+        self.y_prev = std::mem::replace(&mut self.y, y_new);
+        self.dy_prev = std::mem::replace(&mut self.dy, dy_new);
+        self.t = t_new;
+
+        if !self.events.is_empty() {
+            let before = self.found.len();
+            self.locate_events()?;
+            self.saved.events.extend_from_slice(&self.found[before..]);
+        }
+        self.saver.after_step(
+            &mut self.saved,
+            Step {
+                t0: self.t_prev,
+                y0: &self.y_prev,
+                dy0: &self.dy_prev,
+                t1: self.t,
+                y1: &self.y,
+                dy1: &self.dy,
+            },
+        );
+        for observer in self.observers.iter_mut() {
+            observer(self.t, &self.y);
+        }
+        Ok(())
+    }
+
+    fn locate_events(&mut self) -> Result<(), SolverError> {
+        let g_new: Vec<f64> = self
+            .events
+            .iter()
+            .map(|e| e.evaluate(self.t, &self.y, &self.p))
+            .collect();
+
+        let mut crossings = vec![];
+        for (i, event) in self.events.iter().enumerate() {
+            if event.crossing.crossed(self.g[i], g_new[i]) {
+                let g = |t: f64| event.evaluate(t, &self.interpolate(t), &self.p);
+                let te = find_root(g, self.t_prev, self.g[i], self.t, g_new[i]);
+                crossings.push((i, te));
+            }
+        }
+        self.g = g_new;
+        let dir = self.dir;
+        crossings.sort_by(|a, b| (dir * a.1).total_cmp(&(dir * b.1)));
+
+        for (i, te) in crossings {
+            let y = self.interpolate(te);
+            self.stats.events += 1;
+            self.found.push(EventRecord {
+                index: i,
+                t: te,
+                y: y.clone(),
+            });
+            if self.events[i].terminal {
+                self.truncate_step(te)?;
+                self.terminated = true;
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Redoes the last step so that it ends at `te`
+    fn truncate_step(&mut self, te: f64) -> Result<(), SolverError> {
+        if te == self.t {
+            return Ok(());
+        }
+        self.t = self.t_prev;
+        self.y = self.y_prev.clone();
+        self.dy = self.dy_prev.clone();
+        let h = te - self.t;
+        if h != 0.0 {
+            let (mut y_new, _, _) = self.rk_step(h);
+            self.project(&mut y_new, te)?;
+            let dy_new = self.eval(&y_new, te);
+            if !is_finite(&y_new) {
+                return Err(SolverError::NonFiniteState { t: te });
+            }
+            self.y = y_new;
+            self.dy = dy_new;
+            self.t = te;
+        }
+        self.g = self
+            .events
+            .iter()
+            .map(|e| e.evaluate(self.t, &self.y, &self.p))
+            .collect();
+        if let Some(record) = self.found.last_mut() {
+            record.y = self.y.clone();
+        }
+        Ok(())
+    }
+
+    /// Integrates to the end time and returns the points selected by
+    /// `save_at`, including those saved by earlier calls to `step`
+    pub fn solve(mut self) -> Result<Solution<Ty>, SolverError> {
+        while !self.is_finished() {
+            self.step()?;
+        }
+        self.saver
+            .finish(&mut self.saved, self.t, &self.y, &self.dy);
+        self.saved.stats = self.stats;
+        Ok(self.saved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::butcher::Tableau;
+    use crate::events::Crossing;
+    use crate::output::SaveAt;
+    use nalgebra::Vector2;
+
+    fn decay(dy: &mut f64, y: &f64, _t: f64, k: &f64) {
+        *dy = -k * y;
+    }
+
+    fn oscillator(dy: &mut Vector2<f64>, y: &Vector2<f64>, _t: f64, _p: &()) {
+        *dy = Vector2::new(y[1], -y[0]);
+    }
+
+    fn rk4(dt: f64) -> SolverOptions {
+        SolverOptions::fixed(Tableau::RK4, dt)
+    }
+
+    fn tight(tableau: Tableau) -> SolverOptions {
+        SolverOptions::new(tableau).tolerance(1e-10, 1e-12)
+    }
+
+    #[test]
+    fn test_fixed_step_forward_and_backward() {
+        let sol = Integrator::new(decay, 1.0, 0.0, 1.0, 1.0, rk4(0.01))
+            .unwrap()
+            .solve()
+            .unwrap();
+        assert_eq!(*sol.t.last().unwrap(), 1.0);
+        assert!((sol.y.last().unwrap() - (-1.0_f64).exp()).abs() < 1e-10);
+
+        let y1 = (-1.0_f64).exp();
+        let sol = Integrator::new(decay, y1, 1.0, 1.0, 0.0, rk4(0.01))
+            .unwrap()
+            .solve()
+            .unwrap();
+        assert_eq!(*sol.t.last().unwrap(), 0.0);
+        assert!(sol.t.windows(2).all(|w| w[1] < w[0]));
+        assert!((sol.y.last().unwrap() - 1.0).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_step_does_not_overshoot() {
+        let sol = Integrator::new(decay, 1.0, 0.0, 1.0, 1.0, rk4(0.3))
+            .unwrap()
+            .solve()
+            .unwrap();
+        assert_eq!(sol.t, vec![0.0, 0.3, 0.6, 0.8999999999999999, 1.0]);
+        let sol = Integrator::new(decay, 1.0, 0.0, 1.0, -1.0, rk4(0.3))
+            .unwrap()
+            .solve()
+            .unwrap();
+        assert_eq!(sol.t, vec![0.0, -0.3, -0.6, -0.8999999999999999, -1.0]);
+    }
+
+    #[test]
+    fn test_steps_land_on_tstops() {
+        let options = tight(Tableau::DoPri45).tstops(vec![0.35, 3.0, 0.7, -1.0]);
+        let sol = Integrator::new(decay, 1.0, 0.0, 1.0, 1.0, options.clone())
+            .unwrap()
+            .solve()
+            .unwrap();
+        assert!(sol.t.contains(&0.35) && sol.t.contains(&0.7));
+        assert_eq!(sol.t.last(), Some(&1.0));
+        let sol = Integrator::new(decay, 1.0, 0.0, 1.0, -2.0, options)
+            .unwrap()
+            .solve()
+            .unwrap();
+        assert!(sol.t.contains(&-1.0) && !sol.t.contains(&0.35));
+    }
+
+    #[test]
+    fn test_adaptive_round_trip() {
+        for tableau in [Tableau::DoPri45, Tableau::Tsit5, Tableau::BS3] {
+            let y0 = Vector2::new(1.0, 0.0);
+            let forward = Integrator::new(oscillator, y0, 0.0, (), 10.0, tight(tableau))
+                .unwrap()
+                .solve()
+                .unwrap();
+            let (t1, y1) = forward.last().unwrap();
+            assert_eq!(t1, 10.0);
+            assert!((y1[0] - 10.0_f64.cos()).abs() < 1e-7, "{:?}", tableau);
+
+            let backward = Integrator::new(oscillator, *y1, 10.0, (), 0.0, tight(tableau))
+                .unwrap()
+                .solve()
+                .unwrap();
+            let (t0, y) = backward.last().unwrap();
+            assert_eq!(t0, 0.0);
+            assert!((y - y0).norm() < 1e-7, "{:?}", tableau);
+
+            // dense output of the backward solution
+            let y = backward.interpolate(2.5).unwrap();
+            assert!((y[0] - 2.5_f64.cos()).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_save_at_times() {
+        let times = vec![0.0, 0.25, 1.0, 3.3, 10.0];
+        let options = tight(Tableau::DoPri45).save_at(SaveAt::Times(times.clone()));
+        let sol = Integrator::new(oscillator, Vector2::new(1.0, 0.0), 0.0, (), 10.0, options)
+            .unwrap()
+            .solve()
+            .unwrap();
+        assert_eq!(sol.t, times);
+        for (t, y) in sol.t.iter().zip(&sol.y) {
+            assert!((y[0] - t.cos()).abs() < 1e-6);
+        }
+        assert!(sol.stats.accepted > times.len());
+    }
+
+    #[test]
+    fn test_save_every_nth_and_final() {
+        let solve = |save_at: SaveAt| {
+            Integrator::new(decay, 1.0, 0.0, 1.0, 1.0, rk4(0.1).save_at(save_at))
+                .unwrap()
+                .solve()
+                .unwrap()
+        };
+        let sol = solve(SaveAt::EveryNth(3));
+        assert_eq!(sol.len(), 5);
+        assert_eq!(*sol.t.last().unwrap(), 1.0);
+        assert!((sol.t[1] - 0.3).abs() < 1e-12);
+
+        let sol = solve(SaveAt::Final);
+        assert_eq!(sol.t, vec![1.0]);
+        assert!((sol.y[0] - (-1.0_f64).exp()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_observer_streams_every_step() {
+        let mut seen = vec![];
+        let mut integrator =
+            Integrator::new(decay, 1.0, 0.0, 1.0, 1.0, rk4(0.1).save_at(SaveAt::Final)).unwrap();
+        integrator.add_observer(|t, y| seen.push((t, *y)));
+        let sol = integrator.solve().unwrap();
+        assert_eq!(seen.len(), 10);
+        assert_eq!(seen.last().unwrap().1, sol.y[0]);
+    }
+
+    #[test]
+    fn test_initial_step_selection() {
+        let options = SolverOptions::default().tolerance(1e-8, 1e-10);
+        let mut auto = Integrator::new(
+            oscillator,
+            Vector2::new(1.0, 0.0),
+            0.0,
+            (),
+            -10.0,
+            options.clone(),
+        )
+        .unwrap();
+        auto.step().unwrap();
+        assert!(auto.t() < 0.0);
+        assert!(auto.t() > -1.0);
+        assert_eq!(auto.stats().rejected, 0);
+
+        // the estimate is clamped into the step limits
+        let loose = SolverOptions::default().tolerance(1e-3, 1e-6).dtmin(0.2);
+        let mut clamped = Integrator::new(decay, 1.0, 0.0, 1.0, 1.0, loose).unwrap();
+        clamped.step().unwrap();
+        assert_eq!(clamped.t(), 0.2);
+
+        let sol = Integrator::new(
+            oscillator,
+            Vector2::new(1.0, 0.0),
+            0.0,
+            (),
+            -10.0,
+            options.clone(),
+        )
+        .unwrap()
+        .solve()
+        .unwrap();
+        assert_eq!(*sol.t.last().unwrap(), -10.0);
+        assert!((sol.y.last().unwrap()[0] - 10.0_f64.cos()).abs() < 1e-6);
+
+        // a poor explicit guess is still honoured, at the cost of rejections
+        let sol = Integrator::new(
+            oscillator,
+            Vector2::new(1.0, 0.0),
+            0.0,
+            (),
+            -10.0,
+            options.dt(5.0),
+        )
+        .unwrap()
+        .solve()
+        .unwrap();
+        assert!(sol.stats.rejected > 0);
+    }
+
+    #[test]
+    fn test_fixed_step_requires_dt() {
+        let mut options = rk4(0.1);
+        options.dt = None;
+        let result = Integrator::new(decay, 1.0, 0.0, 1.0, 1.0, options);
+        assert!(matches!(result, Err(SolverError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_adaptive_requires_embedded_method() {
+        let result = Integrator::new(decay, 1.0, 0.0, 1.0, 1.0, SolverOptions::new(Tableau::RK4));
+        assert!(matches!(result, Err(SolverError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_terminal_event_backward() {
+        // y = cos(t) crosses zero at -pi/2 when integrating backward from 0
+        let mut integrator = Integrator::new(
+            oscillator,
+            Vector2::new(1.0, 0.0),
+            0.0,
+            (),
+            -10.0,
+            tight(Tableau::DoPri45),
+        )
+        .unwrap();
+        integrator.add_event(
+            Event::new(|_t, y: &Vector2<f64>, _p: &()| y[0])
+                .crossing(Crossing::Falling)
+                .terminal(true),
+        );
+        let sol = integrator.solve().unwrap();
+        assert_eq!(sol.events.len(), 1);
+        let te = sol.events[0].t;
+        assert!((te + std::f64::consts::FRAC_PI_2).abs() < 1e-8);
+        assert_eq!(*sol.t.last().unwrap(), te);
+        assert!(sol.y.last().unwrap()[0].abs() < 1e-8);
+    }
+
+    #[test]
+    fn test_event_direction_along_integration() {
+        // sin(t) rises through zero at t = 2 pi going forward, which is a
+        // falling crossing when integrating backward
+        let events = |t_end: f64, crossing: Crossing| {
+            let mut integrator = Integrator::new(
+                oscillator,
+                Vector2::new(0.0, 1.0),
+                0.0,
+                (),
+                t_end,
+                tight(Tableau::DoPri45),
+            )
+            .unwrap();
+            integrator
+                .add_event(Event::new(|_t, y: &Vector2<f64>, _p: &()| y[0]).crossing(crossing));
+            integrator.solve().unwrap().events
+        };
+        let rising = events(7.0, Crossing::Rising);
+        assert_eq!(rising.len(), 1);
+        assert!((rising[0].t - 2.0 * std::f64::consts::PI).abs() < 1e-8);
+
+        let falling = events(-7.0, Crossing::Falling);
+        assert_eq!(falling.len(), 1);
+        assert!((falling[0].t + 2.0 * std::f64::consts::PI).abs() < 1e-8);
+        assert_eq!(events(-7.0, Crossing::Either).len(), 2);
+    }
+}
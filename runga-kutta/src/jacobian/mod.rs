@@ -0,0 +1,98 @@
+use crate::state::{to_dvector, OdeState};
+use nalgebra::{DMatrix, DVector};
+
+/// Central difference of `eval` with respect to each component of `x`. Column
+/// `j` holds the derivative of the output with respect to component `j`.
+pub(crate) fn central_difference<X: OdeState>(
+    x: &X,
+    rows: usize,
+    mut eval: impl FnMut(&X) -> DVector<f64>,
+) -> DMatrix<f64> {
+    let scale = f64::EPSILON.cbrt();
+    let mut jac = DMatrix::zeros(rows, x.dim());
+    let mut xp = x.clone();
+    for j in 0..x.dim() {
+        let xj = x.component(j);
+        let h = scale * xj.abs().max(1.0);
+        xp.set_component(j, xj + h);
+        let fp = eval(&xp);
+        xp.set_component(j, xj - h);
+        let fm = eval(&xp);
+        xp.set_component(j, xj);
+        jac.set_column(j, &((fp - fm) / (2.0 * h)));
+    }
+    jac
+}
+
+/// Jacobian `df/dy` of the right-hand side at `(y, t)`, by central differences
+pub fn state_jacobian<Ty: OdeState, Tp>(
+    f: impl Fn(&mut Ty, &Ty, f64, &Tp),
+    y: &Ty,
+    t: f64,
+    p: &Tp,
+) -> DMatrix<f64> {
+    let mut dy = y.zeros_like();
+    central_difference(y, y.dim(), |y| {
+        f(&mut dy, y, t, p);
+        to_dvector(&dy)
+    })
+}
+
+/// Jacobian `df/dp` of the right-hand side with respect to its parameters
+pub fn param_jacobian<Ty: OdeState, Tp: OdeState>(
+    f: impl Fn(&mut Ty, &Ty, f64, &Tp),
+    y: &Ty,
+    t: f64,
+    p: &Tp,
+) -> DMatrix<f64> {
+    let mut dy = y.zeros_like();
+    central_difference(p, y.dim(), |p| {
+        f(&mut dy, y, t, p);
+        to_dvector(&dy)
+    })
+}
+
+/// Gradient of a scalar function, by central differences
+pub fn gradient<X: OdeState>(g: impl Fn(&X) -> f64, x: &X) -> DVector<f64> {
+    central_difference(x, 1, |x| DVector::from_element(1, g(x)))
+        .row(0)
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Matrix2, Vector2};
+
+    fn pendulum(dy: &mut Vector2<f64>, y: &Vector2<f64>, _t: f64, p: &Vector2<f64>) {
+        // p = (g / l, damping)
+        *dy = Vector2::new(y[1], -p[0] * y[0].sin() - p[1] * y[1]);
+    }
+
+    #[test]
+    fn test_state_jacobian() {
+        let y = Vector2::new(0.3, -0.2);
+        let p = Vector2::new(9.81, 0.5);
+        let jac = state_jacobian(pendulum, &y, 0.0, &p);
+        let expected = Matrix2::new(0.0, 1.0, -p[0] * y[0].cos(), -p[1]);
+        assert!((jac - expected).abs().max() < 1e-8);
+    }
+
+    #[test]
+    fn test_param_jacobian() {
+        let y = Vector2::new(0.3, -0.2);
+        let p = Vector2::new(9.81, 0.5);
+        let jac = param_jacobian(pendulum, &y, 0.0, &p);
+        let expected = Matrix2::new(0.0, 0.0, -y[0].sin(), -y[1]);
+        assert!((jac - expected).abs().max() < 1e-8);
+    }
+
+    #[test]
+    fn test_gradient() {
+        let grad = gradient(
+            |y: &Vector2<f64>| y[0] * y[0] * y[1],
+            &Vector2::new(2.0, 3.0),
+        );
+        assert!((grad - Vector2::new(12.0, 4.0)).abs().max() < 1e-8);
+    }
+}
@@ -0,0 +1,182 @@
+use crate::error::SolverError;
+use nalgebra::{DMatrix, DVector};
+
+/// Residuals and their Jacobian at a point
+pub type Linearization = (DVector<f64>, DMatrix<f64>);
+
+/// Damping beyond which no step is expected to reduce the cost
+const MAX_DAMPING: f64 = 1e16;
+
+/// Stopping criteria for `levenberg_marquardt`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LeastSquaresOptions {
+    pub max_iterations: usize,
+    /// Relative change in the unknowns below which the fit has converged
+    pub xtol: f64,
+    /// Relative change in the cost below which the fit has converged
+    pub ftol: f64,
+    /// Largest gradient component at which the fit has converged
+    pub gtol: f64,
+}
+
+impl Default for LeastSquaresOptions {
+    fn default() -> Self {
+        Self {
+            max_iterations: 100,
+            xtol: 1e-10,
+            ftol: 1e-12,
+            gtol: 1e-12,
+        }
+    }
+}
+
+/// Result of a least squares fit
+#[derive(Debug, Clone)]
+pub struct LeastSquares {
+    pub x: DVector<f64>,
+    pub residuals: DVector<f64>,
+    pub jacobian: DMatrix<f64>,
+    /// Half the sum of squared residuals
+    pub cost: f64,
+    pub iterations: usize,
+    pub converged: bool,
+}
+
+impl LeastSquares {
+    /// Covariance `(J' J)^-1` of the unknowns for residuals with unit variance
+    pub fn covariance(&self) -> Result<DMatrix<f64>, SolverError> {
+        (self.jacobian.transpose() * &self.jacobian)
+            .try_inverse()
+            .ok_or(SolverError::SingularMatrix)
+    }
+}
+
+/// Minimizes the sum of squared residuals returned by `problem`, which
+/// evaluates the residuals and their Jacobian at the given unknowns
+pub fn levenberg_marquardt(
+    mut problem: impl FnMut(&DVector<f64>) -> Result<Linearization, SolverError>,
+    x0: DVector<f64>,
+    options: &LeastSquaresOptions,
+) -> Result<LeastSquares, SolverError> {
+    let mut x = x0;
+    let (mut r, mut jac) = problem(&x)?;
+    let mut cost = 0.5 * r.norm_squared();
+    let mut lambda = 1e-3;
+    let mut iterations = 0;
+    let mut converged = false;
+    let mut stalled = false;
+
+    while iterations < options.max_iterations {
+        iterations += 1;
+        let jt = jac.transpose();
+        let g = &jt * &r;
+        if g.amax() <= options.gtol {
+            converged = true;
+            break;
+        }
+        let a = &jt * &jac;
+
+        // increase the damping until a step reduces the cost
+        loop {
+            let mut damped = a.clone();
+            for i in 0..damped.nrows() {
+                damped[(i, i)] += lambda * a[(i, i)].max(1e-12);
+            }
+            let dx = damped
+                .cholesky()
+                .ok_or(SolverError::SingularMatrix)?
+                .solve(&-&g);
+            let x_new = &x + &dx;
+            let trial = problem(&x_new);
+            let (r_new, jac_new) = match trial {
+                Ok(lin) => lin,
+                // treat points where the model fails like a cost increase
+                Err(_) if lambda < MAX_DAMPING => {
+                    lambda *= 10.0;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let cost_new = 0.5 * r_new.norm_squared();
+            if cost_new.is_finite() && cost_new <= cost {
+                let small_step = dx.norm() <= options.xtol * (x.norm() + options.xtol);
+                let small_change = cost - cost_new <= options.ftol * cost;
+                x = x_new;
+                r = r_new;
+                jac = jac_new;
+                cost = cost_new;
+                lambda = (lambda / 10.0).max(1e-12);
+                converged = small_step || small_change;
+                break;
+            }
+            lambda *= 10.0;
+            if lambda > MAX_DAMPING {
+                stalled = true;
+                break;
+            }
+        }
+        if converged || stalled {
+            break;
+        }
+    }
+
+    Ok(LeastSquares {
+        x,
+        residuals: r,
+        jacobian: jac,
+        cost,
+        iterations,
+        converged,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rosenbrock() {
+        let problem = |x: &DVector<f64>| {
+            let r = DVector::from_vec(vec![10.0 * (x[1] - x[0] * x[0]), 1.0 - x[0]]);
+            let jac = DMatrix::from_row_slice(2, 2, &[-20.0 * x[0], 10.0, -1.0, 0.0]);
+            Ok((r, jac))
+        };
+        let x0 = DVector::from_vec(vec![-1.2, 1.0]);
+        let fit = levenberg_marquardt(problem, x0, &LeastSquaresOptions::default()).unwrap();
+        assert!(fit.converged);
+        assert!((fit.x[0] - 1.0).abs() < 1e-8);
+        assert!((fit.x[1] - 1.0).abs() < 1e-8);
+    }
+
+    #[test]
+    fn test_linear_covariance() {
+        // straight line through three points with unit variance
+        let t = [0.0, 1.0, 2.0];
+        let data = [1.0, 3.1, 4.9];
+        let problem = |x: &DVector<f64>| {
+            let r = DVector::from_fn(3, |i, _| x[0] + x[1] * t[i] - data[i]);
+            let jac = DMatrix::from_fn(3, 2, |i, j| if j == 0 { 1.0 } else { t[i] });
+            Ok((r, jac))
+        };
+        let fit = levenberg_marquardt(problem, DVector::zeros(2), &LeastSquaresOptions::default())
+            .unwrap();
+        assert!((fit.x[1] - 1.95).abs() < 1e-8);
+        let cov = fit.covariance().unwrap();
+        assert!((cov[(1, 1)] - 0.5).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_stalled_damping() {
+        // the residual has a cusp at the start that every step climbs, however
+        // short, while the Jacobian claims it falls
+        let problem = |x: &DVector<f64>| {
+            let r = DVector::from_element(1, 1.0 + x[0].abs().sqrt());
+            Ok((r, DMatrix::from_element(1, 1, -1.0)))
+        };
+        let fit = levenberg_marquardt(problem, DVector::zeros(1), &LeastSquaresOptions::default())
+            .unwrap();
+        assert!(!fit.converged);
+        assert_eq!(fit.iterations, 1);
+        assert_eq!(fit.x[0], 0.0);
+    }
+}
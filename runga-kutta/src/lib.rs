@@ -1,63 +1,108 @@
-mod butcher;
+pub mod adjoint;
+pub mod block;
+pub mod brownian;
+pub mod butcher;
+pub mod bvp;
+pub mod checkpoint;
+pub mod dae;
+pub mod dde;
+mod dopri45;
+pub mod ensemble;
+pub mod error;
+pub mod estimation;
+pub mod events;
+pub mod hybrid;
+pub mod integrator;
+pub mod jacobian;
+pub mod least_squares;
+pub mod lie;
+pub mod linearize;
+pub mod orbital;
+pub mod output;
+pub mod periodic;
+pub mod projection;
+pub mod rigid;
+pub mod sde;
+pub mod sensitivity;
+pub mod signal;
+pub mod simval;
+pub mod solution;
+pub mod solver;
+pub mod state;
+pub mod table;
+pub mod trim;
+mod tsit5;
+pub mod uncertain;
 
-use std::ops::{Add, Mul};
-use nalgebra::{SVector,Dim};
+pub use adjoint::{adjoint_gradient, AdjointGradient, Objective};
+pub use block::{Block, Diagram, System, SystemSolution};
+pub use brownian::BrownianPath;
+pub use butcher::{ButcherTableau, Tableau};
+pub use bvp::{solve_bvp, BvpOptions, BvpSolution};
+pub use checkpoint::Checkpoint;
+pub use dae::{consistent_initial, semi_explicit_mass};
+pub use dde::{solve_dde, Dde, History};
+pub use ensemble::{ensemble, EnsembleOptions, EnsembleSolution};
+pub use error::SolverError;
+pub use estimation::{fit, Fit, FitOptions, Measurement};
+pub use events::{Crossing, Event, EventRecord};
+pub use hybrid::{Hybrid, HybridSolution, HybridSolver};
+pub use integrator::{Integrator, Stats, Tolerance};
+pub use lie::{solve_lie, Attitude, LieMethod, LieSolution};
+pub use linearize::{linearize, linearize_with_output, Mode, OperatingPoint, StateSpace};
+pub use orbital::{
+    orbit_dynamics, two_body, Drag, ExponentialAtmosphere, KeplerianElements, OrbitModel,
+};
+pub use output::SaveAt;
+pub use periodic::{periodic_orbit, poincare_section, PeriodicOptions, PeriodicOrbit};
+pub use projection::Projection;
+pub use rigid::{rigid_body_dynamics, RigidBody};
+pub use sde::{Diffusion, SdeMethod};
+pub use sensitivity::{forward_sensitivity, state_transition, SensitivitySolution};
+pub use signal::Signal;
+pub use simval::{SimVal, UnitError, Units};
+pub use solution::Solution;
+pub use solver::{Solver, SolverOptions};
+pub use state::OdeState;
+pub use table::{Interpolation, Table1, TableNd};
+pub use trim::{trim, Trim, TrimPoint};
+pub use uncertain::{propagate_uncertainty, UncertainSolution, UncertainState};
 
+/// Integrates `dy = f(y, t, p)` from `t0` to `t_end` with the method, step
+/// control and output selected by `options`. `t_end` may be less than `t0` to
+/// integrate backward in time.
 pub fn runge_kutta<Ty, Tp>(
-    f: impl Fn(Ty, Ty, f64, Tp) -> Ty, // Function to solve
-    y0: Ty,                            // Initial value
-    t0: f64,                           // Initial time
-    p: Tp,                             //ode function parameters
-    mut dt: f64,                       // Initial step size
-    mut t_end: f64,                    // End time
-    order: usize,                      // Order of the Runge-Kutta
-    tableau: butcher::Tableau,         // butcher tableau of RK coefficients
-) -> (Vec<f64>, Vec<Ty>)
+    f: impl Fn(&mut Ty, &Ty, f64, &Tp), // Function to solve
+    y0: Ty,                             // Initial value
+    t0: f64,                            // Initial time
+    p: Tp,                              // ode function parameters
+    t_end: f64,                         // End time
+    options: SolverOptions,             // method, tolerances and output
+) -> Result<Solution<Ty>, SolverError>
 where
-    Ty: Copy + Default + Mul<f64, Output = Ty>,
+    Ty: OdeState,
 {
-    let mut y = y0;
-    let mut t = t0;
-    let mut result = (vec![], vec![]);
-
-    result.0.push(t);
-    result.1.push(y);
-
-    while t < t_end {
-        if t + dt > t_end {
-            dt = t_end - t;
-        }
-
-        let mut k = vec![Ty::default(); order];
-
-        let dy = Ty::default();
-
-        k[0] = f(dy,y,t,p) * dt ;
-
-        for i in 1..order {
-            let mut sum = Ty::default();
-            for j in 0..i {
-                sum = sum
-                    + k[j]
-                        * (T::from(1.0).unwrap()
-                            / (T::from(2.0).unwrap() + T::from(j as f64).unwrap()));
-            }
-            k[i] = f(t + T::from(i as f64).unwrap() * dt, y + sum) * dt;
-        }
+    Integrator::new(f, y0, t0, p, t_end, options)?.solve()
+}
 
-        let mut sum = T::default();
-        for i in 0..order {
-            sum = sum
-                + k[i]
-                    * (T::from(1.0).unwrap()
-                        / (T::from(2.0).unwrap() + T::from(i as f64).unwrap()));
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        y = y + sum;
-        t = t + dt;
+    #[test]
+    fn test_runge_kutta_backward() {
+        let f = |dy: &mut f64, y: &f64, t: f64, _p: &()| *dy = t * y;
+        // y = exp(t^2 / 2)
+        let options = SolverOptions::default().tolerance(1e-10, 1e-12);
+        let sol = runge_kutta(f, 1.0, 0.0, (), 2.0, options).unwrap();
+        let (t1, y1) = sol.last().unwrap();
+        assert_eq!(t1, 2.0);
+        assert!((y1 - 2.0_f64.exp()).abs() < 1e-8);
 
-        result.push((t, y));
+        let options = SolverOptions::fixed(Tableau::RK4, 0.01);
+        let sol = runge_kutta(f, *y1, 2.0, (), 0.0, options).unwrap();
+        let (t0, y0) = sol.last().unwrap();
+        assert_eq!(t0, 0.0);
+        assert!((y0 - 1.0).abs() < 1e-8);
     }
-
-    result
 }
@@ -0,0 +1,464 @@
+use crate::butcher::{ButcherTableau, Tableau, MAX_STAGES};
+use crate::error::SolverError;
+use crate::integrator::{clamp_step, error_norm, initial_step, Stats, Stops};
+use crate::output::Saver;
+use crate::solver::SolverOptions;
+use crate::state::{from_slice, OdeState};
+use nalgebra::{DVector, UnitQuaternion, Vector3};
+
+/// An orientation together with the rest of the state, such as the angular
+/// velocity, of a rotating body
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attitude<Ty> {
+    /// Rotation from the body frame to the reference frame
+    pub q: UnitQuaternion<f64>,
+    pub x: Ty,
+}
+
+impl<Ty> Attitude<Ty> {
+    pub fn new(q: UnitQuaternion<f64>, x: Ty) -> Self {
+        Self { q, x }
+    }
+}
+
+/// Integrators that advance the orientation by exponentials of rotation
+/// vectors, so it stays a unit quaternion to rounding error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LieMethod {
+    /// Runge–Kutta–Munthe-Kaas: the solver's tableau is applied to the
+    /// rotation vector of the step, keeping its order. Embedded tableaus allow
+    /// adaptive steps.
+    #[default]
+    Rkmk,
+    /// Third order Crouch–Grossman method, composing one exponential per
+    /// stage without the inverse derivative of the exponential. Steps are
+    /// fixed.
+    CrouchGrossman3,
+}
+
+impl LieMethod {
+    fn tableau(&self, tableau: Tableau) -> ButcherTableau {
+        match self {
+            LieMethod::Rkmk => tableau.tableau(),
+            LieMethod::CrouchGrossman3 => {
+                let mut a = [[0.0; MAX_STAGES]; MAX_STAGES];
+                a[1][0] = 0.75;
+                a[2][..2].copy_from_slice(&[119.0 / 216.0, 17.0 / 108.0]);
+                let mut b = [0.0; MAX_STAGES];
+                b[..3].copy_from_slice(&[13.0 / 51.0, -2.0 / 3.0, 24.0 / 17.0]);
+                let mut c = [0.0; MAX_STAGES];
+                c[..3].copy_from_slice(&[0.0, 0.75, 17.0 / 24.0]);
+                ButcherTableau::new(a, b, c, 3, 3)
+            }
+        }
+    }
+}
+
+/// Time history of a Lie-group integration
+#[derive(Debug, Clone)]
+pub struct LieSolution<Ty> {
+    pub t: Vec<f64>,
+    pub y: Vec<Attitude<Ty>>,
+    pub stats: Stats,
+}
+
+impl<Ty> LieSolution<Ty> {
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    pub fn last(&self) -> Option<(f64, &Attitude<Ty>)> {
+        Some((*self.t.last()?, self.y.last()?))
+    }
+}
+
+/// Inverse of the derivative of the exponential on so(3), applied to `v`.
+/// A body-frame velocity `w` at `q0 exp(u)` moves the rotation vector `u` at
+/// `dexp_inv(-u, w)`.
+fn dexp_inv(u: &Vector3<f64>, v: &Vector3<f64>) -> Vector3<f64> {
+    let theta = u.norm();
+    let coef = if theta < 1e-4 {
+        1.0 / 12.0 + theta * theta / 720.0
+    } else {
+        let half = 0.5 * theta;
+        (1.0 - half / half.tan()) / (theta * theta)
+    };
+    v - 0.5 * u.cross(v) + coef * u.cross(&u.cross(v))
+}
+
+fn exp(u: &Vector3<f64>) -> UnitQuaternion<f64> {
+    UnitQuaternion::from_scaled_axis(*u)
+}
+
+/// The result of one step: new state and error estimates for the rotation
+/// vector and the rest of the state
+type LieStep<Ty> = (Attitude<Ty>, Vector3<f64>, Option<Ty>);
+
+/// Evaluates the body angular velocity and the derivative of the rest of the
+/// state
+struct LieSystem<'s, Tp, F> {
+    f: F,
+    p: &'s Tp,
+    fevals: usize,
+}
+
+impl<'s, Tp, F> LieSystem<'s, Tp, F> {
+    fn eval<Ty: OdeState>(&mut self, y: &Attitude<Ty>, t: f64) -> (Vector3<f64>, Ty)
+    where
+        F: Fn(&mut Vector3<f64>, &mut Ty, &Attitude<Ty>, f64, &Tp),
+    {
+        self.fevals += 1;
+        let mut w = Vector3::zeros();
+        let mut dx = y.x.zeros_like();
+        (self.f)(&mut w, &mut dx, y, t, self.p);
+        (w, dx)
+    }
+
+    fn step<Ty: OdeState>(
+        &mut self,
+        method: LieMethod,
+        bt: &ButcherTableau,
+        t: f64,
+        y: &Attitude<Ty>,
+        h: f64,
+    ) -> LieStep<Ty>
+    where
+        F: Fn(&mut Vector3<f64>, &mut Ty, &Attitude<Ty>, f64, &Tp),
+    {
+        let s = bt.stages;
+        let mut k: Vec<Vector3<f64>> = Vec::with_capacity(s);
+        let mut dx: Vec<Ty> = Vec::with_capacity(s);
+        for i in 0..s {
+            let mut x = y.x.clone();
+            for (j, dxj) in dx.iter().enumerate() {
+                if bt.a[i][j] != 0.0 {
+                    x = x + dxj.clone() * (h * bt.a[i][j]);
+                }
+            }
+            let u: Vector3<f64> = (0..i).map(|j| k[j] * (h * bt.a[i][j])).sum();
+            let q = match method {
+                LieMethod::Rkmk => y.q * exp(&u),
+                LieMethod::CrouchGrossman3 => {
+                    (0..i).fold(y.q, |q, j| q * exp(&(k[j] * (h * bt.a[i][j]))))
+                }
+            };
+            let (w, dxi) = self.eval(&Attitude::new(q, x), t + bt.c[i] * h);
+            let ki = match method {
+                LieMethod::Rkmk => dexp_inv(&-u, &w),
+                LieMethod::CrouchGrossman3 => w,
+            };
+            k.push(ki);
+            dx.push(dxi);
+        }
+
+        let mut x = y.x.clone();
+        for (i, dxi) in dx.iter().enumerate() {
+            x = x + dxi.clone() * (h * bt.b[i]);
+        }
+        let q = match method {
+            LieMethod::Rkmk => {
+                let u: Vector3<f64> = (0..s).map(|i| k[i] * (h * bt.b[i])).sum();
+                y.q * exp(&u)
+            }
+            LieMethod::CrouchGrossman3 => (0..s).fold(y.q, |q, i| q * exp(&(k[i] * (h * bt.b[i])))),
+        };
+        let (err_u, err_x) = match bt.btilde {
+            Some(btilde) => {
+                let err_u = (0..s).map(|i| k[i] * (h * btilde[i])).sum();
+                let err_x = dx.iter().enumerate().fold(y.x.zeros_like(), |e, (i, dxi)| {
+                    e + dxi.clone() * (h * btilde[i])
+                });
+                (err_u, Some(err_x))
+            }
+            None => (Vector3::zeros(), None),
+        };
+        (Attitude::new(q, x), err_u, err_x)
+    }
+}
+
+/// Integrates a rotating body from `t0` to `t_end` with `method`, backward in
+/// time if `t_end < t0`. `f(w, dx, y, t, p)` writes the angular velocity `w` in the body frame, so
+/// `dq = q (0, w / 2)`, and the derivative `dx` of the rest of the state. The
+/// orientation is only ever multiplied by exponentials of rotation vectors
+/// and never renormalized.
+///
+/// Steps are fixed at `options.dt` unless a tolerance is set, in which case
+/// `dt` is the first step, selected automatically when `None`. Rotation
+/// errors are measured against a rotation of one radian. There is no dense output, so saved times are stepped onto
+/// exactly.
+pub fn solve_lie<Ty: OdeState, Tp>(
+    f: impl Fn(&mut Vector3<f64>, &mut Ty, &Attitude<Ty>, f64, &Tp),
+    y0: Attitude<Ty>,
+    t0: f64,
+    p: Tp,
+    t_end: f64,
+    method: LieMethod,
+    options: SolverOptions,
+) -> Result<LieSolution<Ty>, SolverError> {
+    let invalid = |msg: &str| Err(SolverError::InvalidInput(msg.to_string()));
+    match method {
+        LieMethod::Rkmk => options.validate()?,
+        LieMethod::CrouchGrossman3 => options.validate_steps()?,
+    }
+    let bt = method.tableau(options.tableau);
+    if options.tol.is_some() && !bt.is_adaptive() {
+        return invalid("adaptive stepping requires an Rkmk method with an embedded tableau");
+    }
+
+    let mut system = LieSystem {
+        f,
+        p: &p,
+        fevals: 0,
+    };
+    let mut saver = Saver::new(options.save_at.clone(), t0, t_end);
+    let stops = Stops::new(
+        options.tstops.iter().chain(&saver.times).copied(),
+        t0,
+        t_end,
+    );
+    let mut sol = LieSolution {
+        t: vec![],
+        y: vec![],
+        stats: Stats::default(),
+    };
+    if saver.save_start(t0) {
+        sol.t.push(t0);
+        sol.y.push(y0.clone());
+    }
+
+    // rotation vector errors, then those of the rest of the state, with the
+    // rotation scaled by a unit magnitude
+    let stacked = |u: &Vector3<f64>, x: &Ty| {
+        DVector::from_iterator(
+            3 + x.dim(),
+            u.iter()
+                .copied()
+                .chain((0..x.dim()).map(|i| x.component(i))),
+        )
+    };
+    let unit = Vector3::repeat(1.0);
+
+    let dir = if t_end < t0 { -1.0 } else { 1.0 };
+    let mut h = match options.dt {
+        Some(dt) => dir * dt.abs(),
+        None => {
+            // the rotation vector of the trial step is offset by the unit
+            // magnitude its errors are measured against
+            let (w, dx) = system.eval(&y0, t0);
+            let mut eval = |z: &DVector<f64>, t: f64| {
+                let u = z.fixed_rows::<3>(0) - unit;
+                let x = from_slice(&y0.x, &z.as_slice()[3..]);
+                let (w, dx) = system.eval(&Attitude::new(y0.q * exp(&u), x), t);
+                stacked(&w, &dx)
+            };
+            let (z, dz) = (stacked(&unit, &y0.x), stacked(&w, &dx));
+            initial_step(&z, &dz, t0, t_end, bt.order as f64, &options, &mut eval)
+        }
+    };
+    let mut t = t0;
+    let mut y = y0;
+    let mut stats = Stats::default();
+    let q = 1.0 / (bt.embedded_order as f64 + 1.0);
+    while t != t_end {
+        if stats.accepted + stats.rejected >= options.max_steps {
+            return Err(SolverError::MaxStepsExceeded { t });
+        }
+        let stop = stops.next(t);
+        let (h_try, hits_stop) = clamp_step(t, h, stop, &options)?;
+
+        let (y_new, err_u, err_x) = system.step(method, &bt, t, &y, h_try);
+        let finite = y_new.q.coords.iter().all(|v| v.is_finite())
+            && (0..y_new.x.dim()).all(|i| y_new.x.component(i).is_finite());
+        if !finite {
+            return Err(SolverError::NonFiniteState { t });
+        }
+        if let (Some(tol), Some(err_x)) = (options.tol, err_x) {
+            let norm = error_norm(
+                &stacked(&err_u, &err_x),
+                &stacked(&unit, &y.x),
+                &stacked(&unit, &y_new.x),
+                &tol,
+            );
+            let fac = if norm == 0.0 {
+                5.0
+            } else {
+                0.9 * norm.powf(-q)
+            };
+            if norm > 1.0 {
+                stats.rejected += 1;
+                h = h_try * fac.max(0.2);
+                continue;
+            }
+            h = h_try * fac.clamp(0.2, 5.0);
+        }
+
+        let t_new = if hits_stop { stop } else { t + h_try };
+        stats.accepted += 1;
+        // saved times are stops, so every time saved is the end of the step
+        saver.step_times(t_new, dir, |_| {
+            sol.t.push(t_new);
+            sol.y.push(y_new.clone());
+        });
+        t = t_new;
+        y = y_new;
+    }
+    if saver.save_final(t, sol.t.last().copied()) {
+        sol.t.push(t);
+        sol.y.push(y);
+    }
+    stats.fevals = system.fevals;
+    sol.stats = stats;
+    Ok(sol)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::SaveAt;
+
+    /// Torque-free rigid body with principal inertia `p`; the rest of the
+    /// state is the body angular velocity
+    fn free_body(
+        w: &mut Vector3<f64>,
+        dw: &mut Vector3<f64>,
+        y: &Attitude<Vector3<f64>>,
+        _t: f64,
+        inertia: &Vector3<f64>,
+    ) {
+        let l = y.x.component_mul(inertia);
+        *w = y.x;
+        *dw = -y.x.cross(&l).component_div(inertia);
+    }
+
+    fn tumbling() -> Attitude<Vector3<f64>> {
+        Attitude::new(UnitQuaternion::identity(), Vector3::new(0.1, 2.0, 0.3))
+    }
+
+    fn angular_momentum(y: &Attitude<Vector3<f64>>, inertia: &Vector3<f64>) -> Vector3<f64> {
+        y.q * y.x.component_mul(inertia)
+    }
+
+    #[test]
+    fn test_constant_rate_is_exact() {
+        let w0 = Vector3::new(0.3, -0.2, 1.1);
+        let f = |w: &mut Vector3<f64>, dx: &mut f64, _y: &Attitude<f64>, _t: f64, _p: &()| {
+            *w = w0;
+            *dx = 0.0;
+        };
+        for method in [LieMethod::Rkmk, LieMethod::CrouchGrossman3] {
+            let sol = solve_lie(
+                f,
+                Attitude::new(UnitQuaternion::identity(), 0.0),
+                0.0,
+                (),
+                10.0,
+                method,
+                SolverOptions::fixed(Tableau::RK4, 0.7),
+            )
+            .unwrap();
+            let (t1, y1) = sol.last().unwrap();
+            assert_eq!(t1, 10.0);
+            assert!(y1.q.angle_to(&exp(&(w0 * 10.0))) < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_convergence_order() {
+        let inertia = Vector3::new(1.0, 2.0, 3.0);
+        let reference = solve_lie(
+            free_body,
+            tumbling(),
+            0.0,
+            inertia,
+            2.0,
+            LieMethod::Rkmk,
+            SolverOptions::fixed(Tableau::RK4, 1e-3),
+        )
+        .unwrap();
+        let q_ref = reference.last().unwrap().1.q;
+        for (method, tableau, order) in [
+            (LieMethod::Rkmk, Tableau::RK4, 4.0),
+            (LieMethod::Rkmk, Tableau::BS3, 3.0),
+            (LieMethod::CrouchGrossman3, Tableau::RK4, 3.0),
+        ] {
+            let error = |dt: f64| {
+                let options = SolverOptions::fixed(tableau, dt);
+                let sol =
+                    solve_lie(free_body, tumbling(), 0.0, inertia, 2.0, method, options).unwrap();
+                sol.last().unwrap().1.q.angle_to(&q_ref)
+            };
+            let observed = (error(0.04) / error(0.02)).log2();
+            assert!(
+                (observed - order).abs() < 0.4,
+                "{:?} order {}",
+                method,
+                observed
+            );
+        }
+    }
+
+    #[test]
+    fn test_adaptive_conserves_momentum() {
+        let inertia = Vector3::new(1.0, 2.0, 3.0);
+        // the first step is selected from the tolerances
+        let options = SolverOptions::new(Tableau::DoPri45)
+            .tolerance(1e-10, 1e-12)
+            .save_at(SaveAt::Times(vec![5.0, 20.0]));
+        let y0 = tumbling();
+        let sol = solve_lie(
+            free_body,
+            y0.clone(),
+            0.0,
+            inertia,
+            20.0,
+            LieMethod::Rkmk,
+            options,
+        )
+        .unwrap();
+        assert_eq!(sol.t, vec![5.0, 20.0]);
+        let l0 = angular_momentum(&y0, &inertia);
+        for y in &sol.y {
+            assert!((angular_momentum(y, &inertia) - l0).norm() < 1e-7);
+            assert!((y.q.coords.norm() - 1.0).abs() < 1e-14);
+        }
+        assert!(solve_lie(
+            free_body,
+            y0,
+            0.0,
+            inertia,
+            1.0,
+            LieMethod::CrouchGrossman3,
+            SolverOptions::default().dt(0.1)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_backward_returns_to_start() {
+        let inertia = Vector3::new(1.0, 2.0, 3.0);
+        let options = SolverOptions::default().tolerance(1e-11, 1e-13);
+        let y0 = tumbling();
+        let forward = solve_lie(
+            free_body,
+            y0.clone(),
+            0.0,
+            inertia,
+            3.0,
+            LieMethod::Rkmk,
+            options.clone(),
+        )
+        .unwrap();
+        let y1 = forward.last().unwrap().1.clone();
+        let backward =
+            solve_lie(free_body, y1, 3.0, inertia, 0.0, LieMethod::Rkmk, options).unwrap();
+        let (t, y) = backward.last().unwrap();
+        assert_eq!(t, 0.0);
+        assert!(backward.t.windows(2).all(|w| w[1] < w[0]));
+        assert!(y.q.angle_to(&y0.q) < 1e-8);
+        assert!((y.x - y0.x).norm() < 1e-8);
+    }
+}
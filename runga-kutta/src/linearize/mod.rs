@@ -0,0 +1,250 @@
+use crate::jacobian::central_difference;
+use crate::state::{to_dvector, OdeState};
+use nalgebra::{Complex, DMatrix, DVector};
+
+/// Linear model `dx = A x + B u`, `y = C x + D u` in deviations from an
+/// operating point
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateSpace {
+    pub a: DMatrix<f64>,
+    pub b: DMatrix<f64>,
+    pub c: DMatrix<f64>,
+    pub d: DMatrix<f64>,
+}
+
+/// An eigenvalue of `A` with its mode shape and the quantities used to judge
+/// it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mode {
+    pub eigenvalue: Complex<f64>,
+    /// Eigenvector with unit norm, turned so that its largest component is
+    /// real and positive. The magnitudes and phases of the components show
+    /// how the states move together in the mode.
+    pub shape: DVector<Complex<f64>>,
+    /// `|lambda|`, in radians per unit time
+    pub natural_frequency: f64,
+    /// `-Re(lambda) / |lambda|`; one for a zero eigenvalue
+    pub damping_ratio: f64,
+    /// `-1 / Re(lambda)`, infinite for modes on the imaginary axis and
+    /// negative for unstable ones
+    pub time_constant: f64,
+}
+
+impl StateSpace {
+    pub fn states(&self) -> usize {
+        self.a.nrows()
+    }
+
+    pub fn inputs(&self) -> usize {
+        self.b.ncols()
+    }
+
+    pub fn outputs(&self) -> usize {
+        self.c.nrows()
+    }
+
+    pub fn eigenvalues(&self) -> DVector<Complex<f64>> {
+        self.a.complex_eigenvalues()
+    }
+
+    /// The modes of `A`, slowest decaying first
+    pub fn modes(&self) -> Vec<Mode> {
+        let mut modes: Vec<Mode> = self
+            .eigenvalues()
+            .iter()
+            .map(|&eigenvalue| {
+                let time_constant = if eigenvalue.re == 0.0 {
+                    f64::INFINITY
+                } else {
+                    -1.0 / eigenvalue.re
+                };
+                let natural_frequency = eigenvalue.re.hypot(eigenvalue.im);
+                let damping_ratio = if natural_frequency == 0.0 {
+                    1.0
+                } else {
+                    -eigenvalue.re / natural_frequency
+                };
+                Mode {
+                    eigenvalue,
+                    shape: self.eigenvector(eigenvalue),
+                    natural_frequency,
+                    damping_ratio,
+                    time_constant,
+                }
+            })
+            .collect();
+        modes.sort_by(|a, b| b.eigenvalue.re.total_cmp(&a.eigenvalue.re));
+        modes
+    }
+
+    /// The right singular vector of `A - lambda I` with the smallest singular
+    /// value, which is an eigenvector for an eigenvalue `lambda`. Repeated
+    /// eigenvalues share one vector.
+    fn eigenvector(&self, lambda: Complex<f64>) -> DVector<Complex<f64>> {
+        let n = self.states();
+        let shifted = self.a.map(Complex::from) - DMatrix::from_diagonal_element(n, n, lambda);
+        let svd = shifted.svd(false, true);
+        let smallest = svd.singular_values.imin();
+        let v_t = svd.v_t.expect("right singular vectors were requested");
+        let v = v_t.row(smallest).adjoint();
+        let largest = v
+            .iter()
+            .map(|c| c.re.hypot(c.im))
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        let phase = largest.map_or(Complex::from(1.0), |(i, norm)| v[i].conj() / norm);
+        v * phase
+    }
+
+    /// Whether all eigenvalues of `A` lie strictly in the left half-plane
+    pub fn is_stable(&self) -> bool {
+        self.eigenvalues().iter().all(|l| l.re < 0.0)
+    }
+}
+
+/// States `x` and inputs `u` at time `t`, about which a model is linearized,
+/// as found by `trim`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperatingPoint<Tx, Tu> {
+    pub x: Tx,
+    pub u: Tu,
+    pub t: f64,
+}
+
+impl<Tx, Tu> OperatingPoint<Tx, Tu> {
+    pub fn new(x: Tx, u: Tu, t: f64) -> Self {
+        Self { x, u, t }
+    }
+}
+
+/// Linearizes `dx = f(x, u, t, p)` about `point` by central differences,
+/// with the full state as output (`C = I`, `D = 0`)
+pub fn linearize<Tx: OdeState, Tu: OdeState, Tp>(
+    f: impl Fn(&mut Tx, &Tx, &Tu, f64, &Tp),
+    point: &OperatingPoint<Tx, Tu>,
+    p: &Tp,
+) -> StateSpace {
+    let (a, b) = dynamics(&f, point, p);
+    let (x, u) = (&point.x, &point.u);
+    let n = x.dim();
+    StateSpace {
+        a,
+        b,
+        c: DMatrix::identity(n, n),
+        d: DMatrix::zeros(n, u.dim()),
+    }
+}
+
+/// Linearizes `dx = f(x, u, t, p)` together with the `outputs` components of
+/// `y = h(x, u, t, p)` about `point` by central differences
+pub fn linearize_with_output<Tx: OdeState, Tu: OdeState, Tp>(
+    f: impl Fn(&mut Tx, &Tx, &Tu, f64, &Tp),
+    h: impl Fn(&mut DVector<f64>, &Tx, &Tu, f64, &Tp),
+    outputs: usize,
+    point: &OperatingPoint<Tx, Tu>,
+    p: &Tp,
+) -> StateSpace {
+    let (a, b) = dynamics(&f, point, p);
+    let OperatingPoint { x, u, t } = point;
+    let t = *t;
+    let mut y = DVector::zeros(outputs);
+    let c = central_difference(x, outputs, |x| {
+        h(&mut y, x, u, t, p);
+        y.clone()
+    });
+    let d = central_difference(u, outputs, |u| {
+        h(&mut y, x, u, t, p);
+        y.clone()
+    });
+    StateSpace { a, b, c, d }
+}
+
+fn dynamics<Tx: OdeState, Tu: OdeState, Tp>(
+    f: &impl Fn(&mut Tx, &Tx, &Tu, f64, &Tp),
+    point: &OperatingPoint<Tx, Tu>,
+    p: &Tp,
+) -> (DMatrix<f64>, DMatrix<f64>) {
+    let OperatingPoint { x, u, t } = point;
+    let t = *t;
+    let mut dx = x.zeros_like();
+    let a = central_difference(x, x.dim(), |x| {
+        f(&mut dx, x, u, t, p);
+        to_dvector(&dx)
+    });
+    let b = central_difference(u, x.dim(), |u| {
+        f(&mut dx, x, u, t, p);
+        to_dvector(&dx)
+    });
+    (a, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Matrix2, Vector2};
+
+    /// Damped pendulum driven by a torque, `p = (g / l, damping)`
+    fn pendulum(dx: &mut Vector2<f64>, x: &Vector2<f64>, u: &f64, _t: f64, p: &Vector2<f64>) {
+        *dx = Vector2::new(x[1], -p[0] * x[0].sin() - p[1] * x[1] + u);
+    }
+
+    #[test]
+    fn test_pendulum_modes() {
+        let p = Vector2::new(4.0, 0.4);
+        let down = linearize(
+            pendulum,
+            &OperatingPoint::new(Vector2::zeros(), 0.0, 0.0),
+            &p,
+        );
+        assert!((&down.a - Matrix2::new(0.0, 1.0, -4.0, -0.4)).abs().max() < 1e-8);
+        assert!((&down.b - Vector2::new(0.0, 1.0)).abs().max() < 1e-8);
+        assert_eq!((down.states(), down.inputs(), down.outputs()), (2, 1, 2));
+        assert!(down.is_stable());
+        for mode in down.modes() {
+            assert!((mode.natural_frequency - 2.0).abs() < 1e-8);
+            assert!((mode.damping_ratio - 0.1).abs() < 1e-8);
+            assert!((mode.time_constant - 5.0).abs() < 1e-6);
+            // A v = lambda v, with the velocity leading the angle by the
+            // eigenvalue
+            let a = down.a.map(Complex::from);
+            let v = &mode.shape;
+            assert!((&a * v - v * mode.eigenvalue).norm() < 1e-8);
+            assert!((v.norm() - 1.0).abs() < 1e-12);
+            let ratio = v[1] / v[0] - mode.eigenvalue;
+            assert!(ratio.re.hypot(ratio.im) < 1e-8);
+        }
+
+        let inverted = OperatingPoint::new(Vector2::new(std::f64::consts::PI, 0.0), 0.0, 0.0);
+        let up = linearize(pendulum, &inverted, &p);
+        assert!(!up.is_stable());
+        let fastest_growth = &up.modes()[0];
+        assert!(fastest_growth.eigenvalue.re > 0.0);
+        assert!(fastest_growth.time_constant < 0.0);
+
+        // undamped oscillations neither grow nor decay
+        let undamped = linearize(
+            pendulum,
+            &OperatingPoint::new(Vector2::zeros(), 0.0, 0.0),
+            &Vector2::new(4.0, 0.0),
+        );
+        for mode in undamped.modes() {
+            assert_eq!(mode.eigenvalue.re, 0.0);
+            assert_eq!(mode.time_constant, f64::INFINITY);
+        }
+    }
+
+    #[test]
+    fn test_output_matrices() {
+        // y = (x0 + 2 u, x0 x1)
+        let h = |y: &mut DVector<f64>, x: &Vector2<f64>, u: &f64, _t: f64, _p: &Vector2<f64>| {
+            y[0] = x[0] + 2.0 * u;
+            y[1] = x[0] * x[1];
+        };
+        let point = OperatingPoint::new(Vector2::new(0.5, -1.5), 0.3, 0.0);
+        let p = Vector2::new(4.0, 0.4);
+        let ss = linearize_with_output(pendulum, h, 2, &point, &p);
+        assert!((&ss.c - Matrix2::new(1.0, 0.0, -1.5, 0.5)).abs().max() < 1e-8);
+        assert!((&ss.d - Vector2::new(2.0, 0.0)).abs().max() < 1e-8);
+        assert!((ss.a[(1, 0)] + 4.0 * 0.5_f64.cos()).abs() < 1e-8);
+    }
+}
@@ -0,0 +1,422 @@
+use nalgebra::{Rotation3, Vector3, Vector6};
+use std::f64::consts::TAU;
+
+/// Gravitational parameter of the Earth, m^3/s^2
+pub const EARTH_MU: f64 = 3.986004418e14;
+/// Equatorial radius of the Earth, m
+pub const EARTH_RADIUS: f64 = 6378137.0;
+/// Zonal harmonics J2 to J6 of the Earth (EGM2008)
+pub const EARTH_J: [f64; 5] = [
+    1.08262668e-3,
+    -2.53265649e-6,
+    -1.61962159e-6,
+    -2.27296082e-7,
+    5.40681239e-7,
+];
+/// Rotation rate of the Earth, rad/s
+pub const EARTH_ROTATION: f64 = 7.292115e-5;
+/// Gravitational parameter of the Sun, m^3/s^2
+pub const SUN_MU: f64 = 1.32712440018e20;
+/// Gravitational parameter of the Moon, m^3/s^2
+pub const MOON_MU: f64 = 4.9028e12;
+/// Astronomical unit, m
+pub const AU: f64 = 1.495978707e11;
+/// Solar radiation pressure at one astronomical unit, N/m^2
+pub const SOLAR_PRESSURE: f64 = 4.56e-6;
+
+/// Position of a body relative to the central body at time `t`
+type Ephemeris<'a> = Box<dyn Fn(f64) -> Vector3<f64> + 'a>;
+
+/// Atmospheric density `rho0 exp(-(h - h0) / scale_height)` at altitude `h`
+/// above a spherical body
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExponentialAtmosphere {
+    /// Density at `h0`, kg/m^3
+    pub rho0: f64,
+    pub h0: f64,
+    pub scale_height: f64,
+    pub radius: f64,
+}
+
+impl ExponentialAtmosphere {
+    pub fn new(rho0: f64, h0: f64, scale_height: f64, radius: f64) -> Self {
+        Self {
+            rho0,
+            h0,
+            scale_height,
+            radius,
+        }
+    }
+
+    pub fn density(&self, r: &Vector3<f64>) -> f64 {
+        self.rho0 * (-(r.norm() - self.radius - self.h0) / self.scale_height).exp()
+    }
+}
+
+/// Cannonball drag `-rho cd (A / m) |v_rel| v_rel / 2`, with the atmosphere
+/// rotating with the central body
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Drag {
+    pub cd: f64,
+    /// Cross-section over mass, m^2/kg
+    pub area_to_mass: f64,
+    pub atmosphere: ExponentialAtmosphere,
+    /// Angular velocity of the atmosphere, rad/s
+    pub rotation: Vector3<f64>,
+}
+
+impl Drag {
+    /// Drag in an atmosphere co-rotating with the Earth
+    pub fn new(cd: f64, area_to_mass: f64, atmosphere: ExponentialAtmosphere) -> Self {
+        Self {
+            cd,
+            area_to_mass,
+            atmosphere,
+            rotation: Vector3::new(0.0, 0.0, EARTH_ROTATION),
+        }
+    }
+
+    pub fn acceleration(&self, r: &Vector3<f64>, v: &Vector3<f64>) -> Vector3<f64> {
+        let v_rel = v - self.rotation.cross(r);
+        -0.5 * self.atmosphere.density(r) * self.cd * self.area_to_mass * v_rel.norm() * v_rel
+    }
+}
+
+struct ThirdBody<'a> {
+    mu: f64,
+    position: Ephemeris<'a>,
+}
+
+struct RadiationPressure<'a> {
+    cr: f64,
+    area_to_mass: f64,
+    sun: Ephemeris<'a>,
+}
+
+/// Orbital elements with the true anomaly. For circular orbits the argument
+/// of periapsis is zero and the anomaly is measured from the ascending node;
+/// for equatorial orbits the node is taken on the x axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeplerianElements {
+    /// Semimajor axis, negative for hyperbolic orbits
+    pub a: f64,
+    pub e: f64,
+    pub i: f64,
+    pub raan: f64,
+    pub argp: f64,
+    pub nu: f64,
+}
+
+/// Eccentricities and node magnitudes below this are treated as zero
+const SINGULAR_TOL: f64 = 1e-11;
+
+impl KeplerianElements {
+    pub fn new(a: f64, e: f64, i: f64, raan: f64, argp: f64, nu: f64) -> Self {
+        Self {
+            a,
+            e,
+            i,
+            raan,
+            argp,
+            nu,
+        }
+    }
+
+    /// Position and velocity `[r, v]`
+    pub fn to_cartesian(&self, mu: f64) -> Vector6<f64> {
+        let p = self.a * (1.0 - self.e * self.e);
+        let (sin_nu, cos_nu) = self.nu.sin_cos();
+        let r = p / (1.0 + self.e * cos_nu);
+        let rotation = Rotation3::from_axis_angle(&Vector3::z_axis(), self.raan)
+            * Rotation3::from_axis_angle(&Vector3::x_axis(), self.i)
+            * Rotation3::from_axis_angle(&Vector3::z_axis(), self.argp);
+        let position = rotation * Vector3::new(r * cos_nu, r * sin_nu, 0.0);
+        let velocity = rotation * ((mu / p).sqrt() * Vector3::new(-sin_nu, self.e + cos_nu, 0.0));
+        Vector6::new(
+            position.x, position.y, position.z, velocity.x, velocity.y, velocity.z,
+        )
+    }
+
+    /// Elements of the orbit through `[r, v]`, with angles in `[0, 2 pi)`
+    pub fn from_cartesian(y: &Vector6<f64>, mu: f64) -> Self {
+        let r: Vector3<f64> = y.fixed_rows::<3>(0).into();
+        let v: Vector3<f64> = y.fixed_rows::<3>(3).into();
+        let h = r.cross(&v);
+        let h_hat = h.normalize();
+        let node = Vector3::z().cross(&h);
+        let e_vec = ((v.norm_squared() - mu / r.norm()) * r - r.dot(&v) * v) / mu;
+        let e = e_vec.norm();
+        let a = 1.0 / (2.0 / r.norm() - v.norm_squared() / mu);
+
+        let node_hat = if node.norm() > SINGULAR_TOL * h.norm() {
+            node.normalize()
+        } else {
+            Vector3::x()
+        };
+        let periapsis = if e > SINGULAR_TOL {
+            e_vec / e
+        } else {
+            node_hat
+        };
+        // angle from a to b about the orbit normal
+        let angle = |a: &Vector3<f64>, b: &Vector3<f64>| {
+            a.cross(b).dot(&h_hat).atan2(a.dot(b)).rem_euclid(TAU)
+        };
+        Self {
+            a,
+            e,
+            i: h_hat.z.clamp(-1.0, 1.0).acos(),
+            raan: node_hat.y.atan2(node_hat.x).rem_euclid(TAU),
+            argp: angle(&node_hat, &periapsis),
+            nu: angle(&periapsis, &r.normalize()),
+        }
+    }
+
+    /// Orbital period of an elliptical orbit
+    pub fn period(&self, mu: f64) -> f64 {
+        TAU * (self.a.powi(3) / mu).sqrt()
+    }
+}
+
+/// Point-mass gravity `dy = [v, -mu r / |r|^3]` for the state `y = [r, v]`,
+/// usable directly as a right-hand side with `mu` as the parameter
+pub fn two_body(dy: &mut Vector6<f64>, y: &Vector6<f64>, _t: f64, mu: &f64) {
+    let r: Vector3<f64> = y.fixed_rows::<3>(0).into();
+    let a = -mu / r.norm().powi(3) * r;
+    *dy = Vector6::new(y[3], y[4], y[5], a.x, a.y, a.z);
+}
+
+/// Acceleration of the zonal harmonics `j[k] = J_{k+2}` of a body with
+/// equatorial radius `radius`, with its pole along z
+pub fn zonal_acceleration(r: &Vector3<f64>, mu: f64, radius: f64, j: &[f64]) -> Vector3<f64> {
+    let rn = r.norm();
+    let r_hat = r / rn;
+    let u = r_hat.z;
+    // Legendre polynomials and their derivatives in u = sin(latitude)
+    let degree = j.len() + 1;
+    let mut p = vec![1.0, u];
+    let mut dp = vec![0.0, 1.0];
+    for n in 1..degree {
+        let nf = n as f64;
+        p.push(((2.0 * nf + 1.0) * u * p[n] - nf * p[n - 1]) / (nf + 1.0));
+        dp.push(dp[n - 1] + (2.0 * nf + 1.0) * p[n]);
+    }
+    let mut acc = Vector3::zeros();
+    for (k, &jn) in j.iter().enumerate() {
+        let n = k + 2;
+        let scale = mu * jn * (radius / rn).powi(n as i32) / (rn * rn);
+        let radial = (n as f64 + 1.0) * p[n] + u * dp[n];
+        acc += scale * (radial * r_hat - dp[n] * Vector3::z());
+    }
+    acc
+}
+
+/// A central body with optional perturbations, for the state `y = [r, v]` in
+/// an inertial frame centred on the body
+pub struct OrbitModel<'a> {
+    pub mu: f64,
+    zonal: Option<(f64, Vec<f64>)>,
+    third_bodies: Vec<ThirdBody<'a>>,
+    drag: Option<Drag>,
+    radiation: Option<RadiationPressure<'a>>,
+}
+
+impl<'a> OrbitModel<'a> {
+    /// Point-mass gravity only
+    pub fn two_body(mu: f64) -> Self {
+        Self {
+            mu,
+            zonal: None,
+            third_bodies: vec![],
+            drag: None,
+            radiation: None,
+        }
+    }
+
+    /// Earth gravity with J2 to J6
+    pub fn earth() -> Self {
+        Self::two_body(EARTH_MU).zonal(EARTH_RADIUS, EARTH_J.to_vec())
+    }
+
+    /// Zonal harmonics `j[k] = J_{k+2}`, so `vec![j2]` models J2 alone
+    pub fn zonal(mut self, radius: f64, j: Vec<f64>) -> Self {
+        self.zonal = Some((radius, j));
+        self
+    }
+
+    /// A point mass at `position(t)` relative to the central body, such as
+    /// the Sun or the Moon
+    pub fn third_body(mut self, mu: f64, position: impl Fn(f64) -> Vector3<f64> + 'a) -> Self {
+        self.third_bodies.push(ThirdBody {
+            mu,
+            position: Box::new(position),
+        });
+        self
+    }
+
+    pub fn drag(mut self, drag: Drag) -> Self {
+        self.drag = Some(drag);
+        self
+    }
+
+    /// Cannonball solar radiation pressure with reflectivity `cr`, from a Sun
+    /// at `sun(t)`. Eclipses are not modelled.
+    pub fn solar_radiation_pressure(
+        mut self,
+        cr: f64,
+        area_to_mass: f64,
+        sun: impl Fn(f64) -> Vector3<f64> + 'a,
+    ) -> Self {
+        self.radiation = Some(RadiationPressure {
+            cr,
+            area_to_mass,
+            sun: Box::new(sun),
+        });
+        self
+    }
+
+    pub fn acceleration(&self, r: &Vector3<f64>, v: &Vector3<f64>, t: f64) -> Vector3<f64> {
+        let mut acc = -self.mu / r.norm().powi(3) * r;
+        if let Some((radius, j)) = &self.zonal {
+            acc += zonal_acceleration(r, self.mu, *radius, j);
+        }
+        for body in &self.third_bodies {
+            let s = (body.position)(t);
+            let d = s - r;
+            acc += body.mu * (d / d.norm().powi(3) - s / s.norm().powi(3));
+        }
+        if let Some(drag) = &self.drag {
+            acc += drag.acceleration(r, v);
+        }
+        if let Some(srp) = &self.radiation {
+            let d = r - (srp.sun)(t);
+            let dn = d.norm();
+            acc += SOLAR_PRESSURE * srp.cr * srp.area_to_mass * (AU / dn).powi(2) * d / dn;
+        }
+        acc
+    }
+
+    pub fn derivative(&self, dy: &mut Vector6<f64>, y: &Vector6<f64>, t: f64) {
+        let r: Vector3<f64> = y.fixed_rows::<3>(0).into();
+        let v: Vector3<f64> = y.fixed_rows::<3>(3).into();
+        let a = self.acceleration(&r, &v, t);
+        *dy = Vector6::new(v.x, v.y, v.z, a.x, a.y, a.z);
+    }
+}
+
+/// `OrbitModel::derivative` as a right-hand side with the model as the
+/// parameter
+pub fn orbit_dynamics(dy: &mut Vector6<f64>, y: &Vector6<f64>, t: f64, model: &OrbitModel) {
+    model.derivative(dy, y, t);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jacobian::gradient;
+    use crate::runge_kutta;
+    use crate::solver::SolverOptions;
+
+    fn leo() -> KeplerianElements {
+        KeplerianElements::new(7000e3, 0.01, 0.9, 0.4, 1.1, 2.0)
+    }
+
+    #[test]
+    fn test_elements_round_trip() {
+        for elements in [
+            leo(),
+            KeplerianElements::new(42164e3, 0.0, 0.3, 2.0, 0.0, 5.0),
+            KeplerianElements::new(8000e3, 0.2, 0.0, 0.0, 1.5, 0.3),
+            KeplerianElements::new(-20000e3, 1.5, 2.5, 4.0, 3.0, 0.5),
+        ] {
+            let y = elements.to_cartesian(EARTH_MU);
+            let back = KeplerianElements::from_cartesian(&y, EARTH_MU);
+            assert!((back.a - elements.a).abs() < 1e-6 * elements.a.abs());
+            assert!((back.e - elements.e).abs() < 1e-9);
+            assert!((back.to_cartesian(EARTH_MU) - y).norm() < 1e-4);
+            for (x, e) in [
+                (back.i, elements.i),
+                (back.raan, elements.raan),
+                (back.argp, elements.argp),
+                (back.nu, elements.nu),
+            ] {
+                assert!((x - e).abs() < 1e-9, "{:?}", back);
+            }
+        }
+    }
+
+    #[test]
+    fn test_two_body_period() {
+        let elements = leo();
+        let y0 = elements.to_cartesian(EARTH_MU);
+        let options = SolverOptions::default().tolerance(1e-12, 1e-6);
+        let period = elements.period(EARTH_MU);
+        let sol = runge_kutta(two_body, y0, 0.0, EARTH_MU, period, options.clone()).unwrap();
+        let y1 = sol.last().unwrap().1;
+        assert!((y1.fixed_rows::<3>(0) - y0.fixed_rows::<3>(0)).norm() < 1e-2);
+
+        // the model without perturbations agrees with two_body
+        let model = OrbitModel::two_body(EARTH_MU);
+        let sol = runge_kutta(orbit_dynamics, y0, 0.0, model, period, options).unwrap();
+        assert!((sol.last().unwrap().1 - y1).norm() < 1e-6);
+    }
+
+    #[test]
+    fn test_zonal_gradient() {
+        // the zonal acceleration is the gradient of -mu / r sum J_n (R / r)^n P_n
+        let potential = |r: &Vector3<f64>| {
+            let (rn, u) = (r.norm(), r.z / r.norm());
+            let mut p = [1.0, u, 0.0, 0.0, 0.0, 0.0, 0.0];
+            for n in 1..6 {
+                let nf = n as f64;
+                p[n + 1] = ((2.0 * nf + 1.0) * u * p[n] - nf * p[n - 1]) / (nf + 1.0);
+            }
+            (0..5)
+                .map(|k| {
+                    -EARTH_MU / rn * EARTH_J[k] * (EARTH_RADIUS / rn).powi(k as i32 + 2) * p[k + 2]
+                })
+                .sum::<f64>()
+        };
+        let r = Vector3::new(4000e3, -3000e3, 5200e3);
+        let expected = gradient(potential, &r);
+        let acc = zonal_acceleration(&r, EARTH_MU, EARTH_RADIUS, &EARTH_J);
+        assert!((acc - expected).norm() < 1e-9 * acc.norm().max(1e-6));
+        // J2 dominates and pulls towards the equator
+        let j2 = zonal_acceleration(&r, EARTH_MU, EARTH_RADIUS, &EARTH_J[..1]);
+        assert!(j2.z < 0.0);
+        assert!((acc - j2).norm() < 1e-2 * j2.norm());
+    }
+
+    #[test]
+    fn test_perturbations() {
+        let sun = Vector3::new(AU, 0.0, 0.0);
+        let r = Vector3::new(7000e3, 0.0, 0.0);
+        let v = Vector3::new(0.0, 7.5e3, 0.0);
+        let gravity = OrbitModel::two_body(EARTH_MU).acceleration(&r, &v, 0.0);
+
+        // radiation pushes away from the Sun at roughly 1 AU
+        let srp = OrbitModel::two_body(EARTH_MU).solar_radiation_pressure(1.5, 0.02, |_t| sun);
+        let push = srp.acceleration(&r, &v, 0.0) - gravity;
+        assert!(push.x < 0.0);
+        assert!((push.norm() - SOLAR_PRESSURE * 1.5 * 0.02).abs() < 1e-3 * push.norm());
+
+        // the solar tide stretches along the Earth-Sun line
+        let tide = OrbitModel::two_body(EARTH_MU).third_body(SUN_MU, |_t| sun);
+        let stretch = tide.acceleration(&r, &v, 0.0) - gravity;
+        let expected = 2.0 * SUN_MU * r.x / AU.powi(3);
+        assert!((stretch.x - expected).abs() < 1e-3 * expected);
+
+        // drag opposes the motion relative to the atmosphere and lowers the
+        // orbital energy
+        let atmosphere = ExponentialAtmosphere::new(1e-11, 400e3, 60e3, EARTH_RADIUS);
+        let drag = Drag::new(2.2, 0.01, atmosphere);
+        let a = drag.acceleration(&r, &v);
+        assert!(a.y < 0.0 && a.dot(&v) < 0.0);
+        let model = OrbitModel::earth().drag(drag);
+        let y0 = leo().to_cartesian(EARTH_MU);
+        let options = SolverOptions::default().tolerance(1e-10, 1e-6);
+        let sol = runge_kutta(orbit_dynamics, y0, 0.0, model, 20000.0, options).unwrap();
+        let a1 = KeplerianElements::from_cartesian(sol.last().unwrap().1, EARTH_MU).a;
+        assert!(a1 < leo().a);
+    }
+}
@@ -0,0 +1,151 @@
+use crate::solution::{hermite, hermite_derivative, Solution};
+use crate::state::OdeState;
+use serde::{Deserialize, Serialize};
+
+/// Which points of the integration are stored in the `Solution`. Dense output
+/// of the solution interpolates between the stored points, so it is only as
+/// accurate as the spacing of what was saved.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum SaveAt {
+    /// Every accepted step, including the initial point
+    #[default]
+    EveryStep,
+    /// Only the given times, interpolated from the steps around them. Times
+    /// outside the integration interval are ignored.
+    Times(Vec<f64>),
+    /// The initial point, every n-th accepted step and the final point
+    EveryNth(usize),
+    /// Only the final point
+    Final,
+}
+
+/// Observer invoked with `(t, y)` after each accepted step
+pub type Observer<'a, Ty> = Box<dyn FnMut(f64, &Ty) + 'a>;
+
+/// Progress through a `SaveAt` during an integration
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Saver {
+    pub(crate) save_at: SaveAt,
+    pub(crate) times: Vec<f64>,
+    pub(crate) next: usize,
+    pub(crate) steps: usize,
+}
+
+/// The endpoints of the step just taken
+pub(crate) struct Step<'s, Ty> {
+    pub(crate) t0: f64,
+    pub(crate) y0: &'s Ty,
+    pub(crate) dy0: &'s Ty,
+    pub(crate) t1: f64,
+    pub(crate) y1: &'s Ty,
+    pub(crate) dy1: &'s Ty,
+}
+
+impl Saver {
+    pub(crate) fn new(save_at: SaveAt, t0: f64, t_end: f64) -> Self {
+        let dir = if t_end < t0 { -1.0 } else { 1.0 };
+        let mut times = match &save_at {
+            SaveAt::Times(times) => times
+                .iter()
+                .copied()
+                .filter(|&t| dir * (t - t0) >= 0.0 && dir * (t_end - t) >= 0.0)
+                .collect(),
+            _ => vec![],
+        };
+        times.sort_by(|a, b| (dir * a).total_cmp(&(dir * b)));
+        times.dedup();
+        Self {
+            save_at,
+            times,
+            next: 0,
+            steps: 0,
+        }
+    }
+
+    /// Whether the initial point at `t` is saved
+    pub(crate) fn save_start(&mut self, t: f64) -> bool {
+        match self.save_at {
+            SaveAt::EveryStep | SaveAt::EveryNth(_) => true,
+            SaveAt::Times(_) => {
+                let save = self.times.first() == Some(&t);
+                if save {
+                    self.next = 1;
+                }
+                save
+            }
+            SaveAt::Final => false,
+        }
+    }
+
+    /// Counts an accepted step ending at `t1` and calls `save` with each time
+    /// to save in it, in order. The times are `t1` itself or, for
+    /// `SaveAt::Times`, requested times up to `t1`.
+    pub(crate) fn step_times(&mut self, t1: f64, dir: f64, mut save: impl FnMut(f64)) {
+        self.steps += 1;
+        match self.save_at {
+            SaveAt::EveryStep => save(t1),
+            SaveAt::EveryNth(n) => {
+                if self.steps.is_multiple_of(n.max(1)) {
+                    save(t1);
+                }
+            }
+            SaveAt::Times(_) => {
+                while let Some(&t) = self.times.get(self.next) {
+                    if dir * (t - t1) > 0.0 {
+                        break;
+                    }
+                    save(t);
+                    self.next += 1;
+                }
+            }
+            SaveAt::Final => {}
+        }
+    }
+
+    /// Whether the final point at `t` is saved, given the last time saved
+    pub(crate) fn save_final(&self, t: f64, last_saved: Option<f64>) -> bool {
+        matches!(self.save_at, SaveAt::EveryNth(_) | SaveAt::Final) && last_saved != Some(t)
+    }
+
+    pub(crate) fn start<Ty: OdeState>(&mut self, sol: &mut Solution<Ty>, t: f64, y: &Ty, dy: &Ty) {
+        if self.save_start(t) {
+            sol.push(t, y.clone(), dy.clone());
+        }
+    }
+
+    /// Saves the points of `step`, interpolating requested times inside it
+    pub(crate) fn after_step<Ty: OdeState>(&mut self, sol: &mut Solution<Ty>, step: Step<Ty>) {
+        let dir = if step.t1 < step.t0 { -1.0 } else { 1.0 };
+        self.step_times(step.t1, dir, |t| {
+            if t == step.t1 {
+                sol.push(t, step.y1.clone(), step.dy1.clone());
+            } else {
+                sol.push(
+                    t,
+                    hermite(step.t0, step.y0, step.dy0, step.t1, step.y1, step.dy1, t),
+                    hermite_derivative(step.t0, step.y0, step.dy0, step.t1, step.y1, step.dy1, t),
+                );
+            }
+        });
+    }
+
+    pub(crate) fn finish<Ty: OdeState>(&mut self, sol: &mut Solution<Ty>, t: f64, y: &Ty, dy: &Ty) {
+        if self.save_final(t, sol.t.last().copied()) {
+            sol.push(t, y.clone(), dy.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_times_filtered_and_sorted() {
+        let saver = Saver::new(SaveAt::Times(vec![0.5, -1.0, 0.25, 2.0, 0.5]), 0.0, 1.0);
+        assert_eq!(saver.times, vec![0.25, 0.5]);
+
+        let saver = Saver::new(SaveAt::Times(vec![-0.5, -1.0, 0.25, -0.25]), 0.0, -1.0);
+        assert_eq!(saver.times, vec![-0.25, -0.5, -1.0]);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::error::SolverError;
+use crate::events::{Event, EventRecord};
+use crate::least_squares::{levenberg_marquardt, LeastSquaresOptions};
+use crate::output::SaveAt;
+use crate::sensitivity::state_transition;
+use crate::solver::{Solver, SolverOptions};
+use crate::state::{from_slice, to_dvector, OdeState};
+use nalgebra::{Complex, DMatrix, DVector};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeriodicOptions {
+    pub solver: SolverOptions,
+    pub least_squares: LeastSquaresOptions,
+    /// Largest residual of the periodicity and phase conditions accepted as
+    /// converged
+    pub tol: f64,
+}
+
+impl Default for PeriodicOptions {
+    fn default() -> Self {
+        Self {
+            solver: SolverOptions::default().tolerance(1e-10, 1e-12),
+            least_squares: LeastSquaresOptions::default(),
+            tol: 1e-8,
+        }
+    }
+}
+
+impl PeriodicOptions {
+    pub fn solver(mut self, solver: SolverOptions) -> Self {
+        self.solver = solver;
+        self
+    }
+
+    pub fn least_squares(mut self, least_squares: LeastSquaresOptions) -> Self {
+        self.least_squares = least_squares;
+        self
+    }
+
+    pub fn tolerance(mut self, tol: f64) -> Self {
+        self.tol = tol;
+        self
+    }
+}
+
+/// A periodic solution through `y0` with its linear stability
+#[derive(Debug, Clone)]
+pub struct PeriodicOrbit<Ty> {
+    pub y0: Ty,
+    pub period: f64,
+    /// State transition matrix over one period
+    pub monodromy: DMatrix<f64>,
+    /// Periodicity residuals `y(T) - y0`, then the phase condition
+    pub residuals: DVector<f64>,
+    pub iterations: usize,
+    pub converged: bool,
+}
+
+impl<Ty> PeriodicOrbit<Ty> {
+    /// Eigenvalues of the monodromy matrix. An autonomous orbit always has
+    /// one multiplier at one, along the flow.
+    pub fn floquet_multipliers(&self) -> DVector<Complex<f64>> {
+        self.monodromy.complex_eigenvalues()
+    }
+
+    /// Whether all multipliers but the one along the flow lie strictly inside
+    /// the unit circle
+    pub fn is_stable(&self) -> bool {
+        let mut multipliers: Vec<Complex<f64>> =
+            self.floquet_multipliers().iter().copied().collect();
+        let distance = |m: &Complex<f64>| (m.re - 1.0).hypot(m.im);
+        let trivial = (0..multipliers.len())
+            .min_by(|&a, &b| distance(&multipliers[a]).total_cmp(&distance(&multipliers[b])));
+        if let Some(i) = trivial {
+            multipliers.remove(i);
+        }
+        multipliers.iter().all(|m| m.re.hypot(m.im) < 1.0)
+    }
+}
+
+/// Finds a periodic solution of the autonomous system `dy = f(y, t, p)` with
+/// unknown period, starting from a point `y0` near the orbit and a period
+/// guess. The period and the initial point are solved for together; the
+/// point is pinned to the hyperplane through `y0` normal to the flow.
+pub fn periodic_orbit<Ty: OdeState, Tp>(
+    f: impl Fn(&mut Ty, &Ty, f64, &Tp),
+    y0: Ty,
+    period: f64,
+    p: Tp,
+    options: &PeriodicOptions,
+) -> Result<PeriodicOrbit<Ty>, SolverError> {
+    if !(period > 0.0 && period.is_finite()) {
+        return Err(SolverError::InvalidInput(
+            "the period guess must be positive and finite".to_string(),
+        ));
+    }
+    let n = y0.dim();
+    let rhs = |dy: &mut Ty, y: &Ty, t: f64, _: &()| f(dy, y, t, &p);
+    let flow = |y: &Ty| {
+        let mut dy = y.zeros_like();
+        rhs(&mut dy, y, 0.0, &());
+        to_dvector(&dy)
+    };
+    let anchor = to_dvector(&y0);
+    let normal = flow(&y0);
+    let mut solver = options.solver.clone();
+    solver.save_at = SaveAt::Final;
+
+    // one period from the point in the first n unknowns, with its monodromy
+    let orbit = |z: &DVector<f64>| {
+        let start = from_slice(&y0, &z.as_slice()[..n]);
+        let sol = state_transition(rhs, start, 0.0, (), z[n], solver.clone())?;
+        let end = sol.solution.y.last().unwrap().clone();
+        Ok::<_, SolverError>((end, sol.stm.last().unwrap().clone()))
+    };
+    let problem = |z: &DVector<f64>| {
+        if z[n] <= 0.0 {
+            return Err(SolverError::InvalidInput("non-positive period".to_string()));
+        }
+        let (end, monodromy) = orbit(z)?;
+        let x = z.rows(0, n);
+        let mut r = DVector::zeros(n + 1);
+        r.rows_mut(0, n).copy_from(&(to_dvector(&end) - x));
+        r[n] = normal.dot(&(x - &anchor));
+        let mut jac = DMatrix::zeros(n + 1, n + 1);
+        jac.view_mut((0, 0), (n, n))
+            .copy_from(&(monodromy - DMatrix::<f64>::identity(n, n)));
+        jac.view_mut((0, n), (n, 1)).copy_from(&flow(&end));
+        jac.view_mut((n, 0), (1, n)).copy_from(&normal.transpose());
+        Ok((r, jac))
+    };
+    let z0 = anchor.clone().push(period);
+    let fit = levenberg_marquardt(problem, z0, &options.least_squares)?;
+    let (_, monodromy) = orbit(&fit.x)?;
+    Ok(PeriodicOrbit {
+        y0: from_slice(&y0, &fit.x.as_slice()[..n]),
+        period: fit.x[n],
+        monodromy,
+        converged: fit.residuals.amax() <= options.tol,
+        residuals: fit.residuals,
+        iterations: fit.iterations,
+    })
+}
+
+/// Crossings of the surface given by the `section` event between `t0` and
+/// `t_end`, integrated with `solver`. Its other events, observers and
+/// `save_at` apply as usual; `SaveAt::Final` avoids storing the trajectory
+/// when only the crossings are needed. A terminal section stops at the first
+/// crossing.
+pub fn poincare_section<'a, Ty: OdeState, Tp>(
+    solver: Solver<'a, Ty, Tp>,
+    section: Event<'a, Ty, Tp>,
+    f: impl Fn(&mut Ty, &Ty, f64, &Tp) + 'a,
+    y0: Ty,
+    t0: f64,
+    p: Tp,
+    t_end: f64,
+) -> Result<Vec<EventRecord<Ty>>, SolverError> {
+    let index = solver.events.len();
+    let sol = solver.event(section).solve(f, y0, t0, p, t_end)?;
+    Ok(sol
+        .events
+        .into_iter()
+        .filter(|e| e.index == index)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Crossing;
+    use nalgebra::Vector2;
+    use std::f64::consts::TAU;
+
+    /// Hopf normal form with the stable limit cycle r = 1 of period 2 pi
+    fn hopf(dy: &mut Vector2<f64>, y: &Vector2<f64>, _t: f64, _p: &()) {
+        let s = 1.0 - y.norm_squared();
+        *dy = Vector2::new(s * y[0] - y[1], s * y[1] + y[0]);
+    }
+
+    fn van_der_pol(dy: &mut Vector2<f64>, y: &Vector2<f64>, _t: f64, mu: &f64) {
+        *dy = Vector2::new(y[1], mu * (1.0 - y[0] * y[0]) * y[1] - y[0]);
+    }
+
+    #[test]
+    fn test_limit_cycle_multipliers() {
+        let orbit = periodic_orbit(
+            hopf,
+            Vector2::new(1.2, 0.1),
+            6.0,
+            (),
+            &PeriodicOptions::default(),
+        )
+        .unwrap();
+        assert!(orbit.converged);
+        assert!((orbit.period - TAU).abs() < 1e-7);
+        assert!((orbit.y0.norm() - 1.0).abs() < 1e-7);
+        // the radial perturbation decays as exp(-2 t)
+        let mut moduli: Vec<f64> = orbit
+            .floquet_multipliers()
+            .iter()
+            .map(|m| m.re.hypot(m.im))
+            .collect();
+        moduli.sort_by(f64::total_cmp);
+        assert!((moduli[0] - (-2.0 * TAU).exp()).abs() < 1e-6);
+        assert!((moduli[1] - 1.0).abs() < 1e-6);
+        assert!(orbit.is_stable());
+    }
+
+    #[test]
+    fn test_van_der_pol_section() {
+        let mu = 1.0;
+        let orbit = periodic_orbit(
+            van_der_pol,
+            Vector2::new(2.0, 0.0),
+            6.5,
+            mu,
+            &PeriodicOptions::default(),
+        )
+        .unwrap();
+        assert!(orbit.converged);
+        assert!((orbit.period - 6.663286859).abs() < 1e-6);
+
+        // crossings of x = 0 with x decreasing settle onto the cycle, one per
+        // period
+        let options = SolverOptions::default()
+            .tolerance(1e-10, 1e-12)
+            .save_at(SaveAt::Final);
+        // a second event, crossings of y = 0, is left out of the section
+        let solver = Solver::new(options).event(Event::new(|_t, y: &Vector2<f64>, _mu: &f64| y[1]));
+        let section =
+            Event::new(|_t, y: &Vector2<f64>, _mu: &f64| y[0]).crossing(Crossing::Falling);
+        let crossings = poincare_section(
+            solver,
+            section,
+            van_der_pol,
+            Vector2::new(0.5, 0.0),
+            0.0,
+            mu,
+            60.0,
+        )
+        .unwrap();
+        assert!(crossings.len() >= 8);
+        let last = &crossings[crossings.len() - 2..];
+        assert!((last[1].t - last[0].t - orbit.period).abs() < 1e-6);
+        assert!((last[1].y - last[0].y).norm() < 1e-6);
+        assert!(crossings
+            .iter()
+            .all(|c| c.y[0].abs() < 1e-9 && c.y[1] < 0.0));
+    }
+}
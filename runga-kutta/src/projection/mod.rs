@@ -0,0 +1,155 @@
+use crate::error::SolverError;
+use crate::jacobian::central_difference;
+use crate::state::{from_slice, to_dvector, OdeState};
+use nalgebra::DVector;
+
+type Constraint<'a, Ty, Tp> = Box<dyn Fn(&mut DVector<f64>, &Ty, f64, &Tp) + 'a>;
+
+const MAX_ITERATIONS: usize = 10;
+
+/// A correction applied to the state after every accepted step to keep it on
+/// a manifold or in a valid region
+pub enum Projection<'a, Ty, Tp> {
+    /// The nearest state, in the Euclidean norm, with `g(y, t, p) = 0` for the
+    /// given number of constraints
+    Constraints(usize, Constraint<'a, Ty, Tp>),
+    /// Negative values of the listed components, or of all components if the
+    /// list is empty, are set to zero
+    NonNegative(Vec<usize>),
+}
+
+impl<'a, Ty: OdeState, Tp> Projection<'a, Ty, Tp> {
+    /// Invariants such as energy or a unit norm, written as
+    /// `g(y, t, p) = 0` with `constraints` components
+    pub fn constraints(
+        constraints: usize,
+        g: impl Fn(&mut DVector<f64>, &Ty, f64, &Tp) + 'a,
+    ) -> Self {
+        Projection::Constraints(constraints, Box::new(g))
+    }
+
+    /// Keeps concentrations, masses and the like from going negative. Indices
+    /// beyond the state give `InvalidInput` on the first step.
+    pub fn nonnegative(components: Vec<usize>) -> Self {
+        Projection::NonNegative(components)
+    }
+
+    /// Projects `y` in place. Constraints are solved by simplified Newton
+    /// iterations `y = y_step - G' lambda` with the constraint Jacobian `G`
+    /// taken at the unprojected state.
+    pub(crate) fn apply(&self, y: &mut Ty, t: f64, p: &Tp) -> Result<(), SolverError> {
+        match self {
+            Projection::NonNegative(components) => {
+                if components.iter().any(|&i| i >= y.dim()) {
+                    return Err(SolverError::InvalidInput(
+                        "nonnegative component index out of range".to_string(),
+                    ));
+                }
+                let all: Vec<usize> = (0..y.dim()).collect();
+                let components = if components.is_empty() {
+                    &all
+                } else {
+                    components
+                };
+                for &i in components {
+                    if y.component(i) < 0.0 {
+                        y.set_component(i, 0.0);
+                    }
+                }
+                Ok(())
+            }
+            Projection::Constraints(m, g) => {
+                let template = y.clone();
+                let eval = |x: &DVector<f64>| {
+                    let mut r = DVector::zeros(*m);
+                    g(&mut r, &from_slice(&template, x.as_slice()), t, p);
+                    r
+                };
+                let y0 = to_dvector(y);
+                let jac = central_difference(&y0, *m, &eval);
+                let gram = (&jac * jac.transpose()).lu();
+                let mut lambda = DVector::zeros(*m);
+                let mut x = y0.clone();
+                for _ in 0..MAX_ITERATIONS {
+                    let step = gram.solve(&eval(&x)).ok_or(SolverError::SingularMatrix)?;
+                    lambda += &step;
+                    x = &y0 - jac.transpose() * &lambda;
+                    if (jac.transpose() * step).amax() <= 1e-12 * x.amax().max(1.0) {
+                        *y = from_slice(&template, x.as_slice());
+                        return Ok(());
+                    }
+                }
+                Err(SolverError::ProjectionFailed { t })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::butcher::Tableau;
+    use crate::solver::{Solver, SolverOptions};
+    use nalgebra::Vector2;
+
+    fn oscillator(dy: &mut Vector2<f64>, y: &Vector2<f64>, _t: f64, _p: &()) {
+        *dy = Vector2::new(y[1], -y[0]);
+    }
+
+    fn energy(r: &mut DVector<f64>, y: &Vector2<f64>, _t: f64, _p: &()) {
+        r[0] = y.norm_squared() - 1.0;
+    }
+
+    #[test]
+    fn test_energy_projection() {
+        let options = SolverOptions::fixed(Tableau::Euler, 0.05);
+        let y0 = Vector2::new(1.0, 0.0);
+        let drift = Solver::new(options.clone())
+            .solve(oscillator, y0, 0.0, (), 20.0)
+            .unwrap();
+        assert!(drift.y.last().unwrap().norm() > 1.5);
+
+        let sol = Solver::new(options)
+            .projection(Projection::constraints(1, energy))
+            .solve(oscillator, y0, 0.0, (), 20.0)
+            .unwrap();
+        for y in &sol.y {
+            assert!((y.norm() - 1.0).abs() < 1e-12);
+        }
+        // the phase error of Euler remains
+        let y1 = sol.y.last().unwrap();
+        assert!((y1 - Vector2::new(20.0_f64.cos(), -20.0_f64.sin())).norm() < 0.6);
+    }
+
+    #[test]
+    fn test_nonnegative() {
+        // explicit Euler with k dt > 1 overshoots below zero
+        let decay = |dy: &mut Vector2<f64>, y: &Vector2<f64>, _t: f64, _p: &()| {
+            *dy = Vector2::new(-3.0 * y[0], -y[1]);
+        };
+        let options = SolverOptions::fixed(Tableau::Euler, 0.5);
+        let sol = Solver::new(options.clone())
+            .solve(decay, Vector2::new(1.0, 1.0), 0.0, (), 2.0)
+            .unwrap();
+        assert!(sol.y.iter().any(|y| y[0] < 0.0));
+        let sol = Solver::new(options)
+            .projection(Projection::nonnegative(vec![]))
+            .solve(decay, Vector2::new(1.0, 1.0), 0.0, (), 2.0)
+            .unwrap();
+        assert!(sol.y.iter().all(|y| y[0] >= 0.0 && y[1] > 0.0));
+        assert_eq!(sol.y.last().unwrap()[0], 0.0);
+        let result = Solver::new(SolverOptions::default())
+            .projection(Projection::nonnegative(vec![2]))
+            .solve(decay, Vector2::new(1.0, 1.0), 0.0, (), 2.0);
+        assert!(matches!(result, Err(SolverError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_degenerate_constraint() {
+        let constant = |r: &mut DVector<f64>, _y: &Vector2<f64>, _t: f64, _p: &()| r[0] = 1.0;
+        let result = Solver::new(SolverOptions::default())
+            .projection(Projection::constraints(1, constant))
+            .solve(oscillator, Vector2::new(1.0, 0.0), 0.0, (), 1.0);
+        assert_eq!(result.unwrap_err(), SolverError::SingularMatrix);
+    }
+}
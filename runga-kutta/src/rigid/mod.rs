@@ -0,0 +1,445 @@
+use crate::error::SolverError;
+use crate::lie::Attitude;
+use nalgebra::{DVector, Matrix3, Quaternion, UnitQuaternion, Vector3};
+
+type Torque<'a> = Box<dyn Fn(f64, &Attitude<DVector<f64>>) -> Vector3<f64> + 'a>;
+type WheelTorques<'a> = Box<dyn Fn(f64, &Attitude<DVector<f64>>) -> DVector<f64> + 'a>;
+type Position<'a> = Box<dyn Fn(f64) -> Vector3<f64> + 'a>;
+
+/// Euler's equations `dw = I^-1 (torque - w x I w)` for a body with inertia
+/// tensor `inertia`, all in the body frame. The inverse is passed in so it
+/// is computed once rather than at every evaluation.
+pub fn euler_equations(
+    inertia: &Matrix3<f64>,
+    inertia_inverse: &Matrix3<f64>,
+    w: &Vector3<f64>,
+    torque: &Vector3<f64>,
+) -> Vector3<f64> {
+    let h = inertia * w;
+    inertia_inverse * (torque - w.cross(&h))
+}
+
+/// `dq = q (0, w) / 2` for the rotation `q` from the body frame to the
+/// reference frame and the body angular velocity `w`
+pub fn quaternion_rate(q: &Quaternion<f64>, w: &Vector3<f64>) -> Quaternion<f64> {
+    q * Quaternion::from_imag(*w) * 0.5
+}
+
+/// Rate of the modified Rodrigues parameters `sigma` for the body angular
+/// velocity `w`
+pub fn mrp_rate(sigma: &Vector3<f64>, w: &Vector3<f64>) -> Vector3<f64> {
+    let s2 = sigma.norm_squared();
+    ((1.0 - s2) * w + 2.0 * sigma.cross(w) + 2.0 * sigma.dot(w) * sigma) / 4.0
+}
+
+/// The modified Rodrigues parameters of the same rotation with norm at most
+/// one. Switching to the shadow set when the norm exceeds one keeps the
+/// parameters away from their singularity at a full turn.
+pub fn mrp_shadow(sigma: &Vector3<f64>) -> Vector3<f64> {
+    let s2 = sigma.norm_squared();
+    if s2 > 1.0 {
+        -sigma / s2
+    } else {
+        *sigma
+    }
+}
+
+/// Modified Rodrigues parameters `e tan(angle / 4)` of a rotation, with norm
+/// at most one
+pub fn quaternion_to_mrp(q: &UnitQuaternion<f64>) -> Vector3<f64> {
+    let q = if q.w < 0.0 {
+        -q.into_inner()
+    } else {
+        q.into_inner()
+    };
+    q.imag() / (1.0 + q.w)
+}
+
+pub fn mrp_to_quaternion(sigma: &Vector3<f64>) -> UnitQuaternion<f64> {
+    let s2 = sigma.norm_squared();
+    let w = (1.0 - s2) / (1.0 + s2);
+    let v = 2.0 * sigma / (1.0 + s2);
+    UnitQuaternion::new_normalize(Quaternion::new(w, v.x, v.y, v.z))
+}
+
+/// Gravity-gradient torque `3 mu / |r|^5 r x I r` on a body at `r` from a
+/// central body, with `r` and the inertia in the body frame
+pub fn gravity_gradient_torque(mu: f64, r: &Vector3<f64>, inertia: &Matrix3<f64>) -> Vector3<f64> {
+    3.0 * mu / r.norm().powi(5) * r.cross(&(inertia * r))
+}
+
+/// The inverse of a positive definite matrix
+fn inverse(m: &Matrix3<f64>) -> Option<Matrix3<f64>> {
+    if !m.iter().all(|v| v.is_finite()) {
+        return None;
+    }
+    m.cholesky().map(|c| c.inverse())
+}
+
+struct Wheel {
+    axis: Vector3<f64>,
+    inertia: f64,
+}
+
+/// A rigid body with optional reaction wheels and torques, for `solve_lie`
+/// with the rest of the state `x = [w, h]`: the body angular velocity and the
+/// axial angular momentum of each wheel
+pub struct RigidBody<'a> {
+    inertia: Matrix3<f64>,
+    /// Inertia without the axial inertia of the wheels, which is carried by
+    /// their momenta, and its inverse
+    transverse: Matrix3<f64>,
+    transverse_inverse: Matrix3<f64>,
+    wheels: Vec<Wheel>,
+    torques: Vec<Torque<'a>>,
+    wheel_torques: Option<WheelTorques<'a>>,
+    gravity: Option<(f64, Position<'a>)>,
+}
+
+impl<'a> RigidBody<'a> {
+    /// A body with the given inertia tensor in its body frame, including the
+    /// wheels, which must be symmetric and positive definite
+    pub fn new(inertia: Matrix3<f64>) -> Result<Self, SolverError> {
+        let symmetric = (inertia - inertia.transpose()).amax() <= 1e-12 * inertia.amax();
+        let Some(transverse_inverse) = inverse(&inertia).filter(|_| symmetric) else {
+            return Err(SolverError::InvalidInput(
+                "inertia tensor must be symmetric and positive definite".to_string(),
+            ));
+        };
+        Ok(Self {
+            inertia,
+            transverse: inertia,
+            transverse_inverse,
+            wheels: vec![],
+            torques: vec![],
+            wheel_torques: None,
+            gravity: None,
+        })
+    }
+
+    /// A reaction wheel spinning about `axis` in the body frame, with axial
+    /// inertia `inertia`, which must be positive and leave the rest of the
+    /// body a positive definite inertia
+    pub fn wheel(mut self, axis: Vector3<f64>, inertia: f64) -> Result<Self, SolverError> {
+        let invalid = |msg: &str| Err(SolverError::InvalidInput(msg.to_string()));
+        let Some(axis) = axis
+            .try_normalize(0.0)
+            .filter(|a| a.iter().all(|v| v.is_finite()))
+        else {
+            return invalid("wheel axis must be nonzero and finite");
+        };
+        if !(inertia > 0.0 && inertia.is_finite()) {
+            return invalid("wheel inertia must be positive and finite");
+        }
+        let transverse = self.transverse - inertia * axis * axis.transpose();
+        let Some(transverse_inverse) = inverse(&transverse) else {
+            return invalid("wheel inertia exceeds the inertia of the body about its axis");
+        };
+        self.transverse = transverse;
+        self.transverse_inverse = transverse_inverse;
+        self.wheels.push(Wheel { axis, inertia });
+        Ok(self)
+    }
+
+    /// An external torque in the body frame, such as from thrusters or
+    /// magnetorquers
+    pub fn torque(
+        mut self,
+        torque: impl Fn(f64, &Attitude<DVector<f64>>) -> Vector3<f64> + 'a,
+    ) -> Self {
+        self.torques.push(Box::new(torque));
+        self
+    }
+
+    /// Motor torques spinning up each wheel, in the order they were added.
+    /// `dynamics` panics if they are not one per wheel.
+    pub fn wheel_torques(
+        mut self,
+        torques: impl Fn(f64, &Attitude<DVector<f64>>) -> DVector<f64> + 'a,
+    ) -> Self {
+        self.wheel_torques = Some(Box::new(torques));
+        self
+    }
+
+    /// Gravity-gradient torque from a central body with gravitational
+    /// parameter `mu` at `-position(t)`, the position of the body in the
+    /// reference frame
+    pub fn gravity_gradient(
+        mut self,
+        mu: f64,
+        position: impl Fn(f64) -> Vector3<f64> + 'a,
+    ) -> Self {
+        self.gravity = Some((mu, Box::new(position)));
+        self
+    }
+
+    /// The state `[w, h]` for body angular velocity `w` and wheel speeds
+    /// relative to the body. Panics if there is not one speed per wheel.
+    pub fn state(&self, w: Vector3<f64>, wheel_speeds: &[f64]) -> DVector<f64> {
+        assert_eq!(wheel_speeds.len(), self.wheels.len(), "one speed per wheel");
+        let mut x = DVector::zeros(3 + self.wheels.len());
+        x.fixed_rows_mut::<3>(0).copy_from(&w);
+        for (k, (wheel, speed)) in self.wheels.iter().zip(wheel_speeds).enumerate() {
+            x[3 + k] = wheel.inertia * (wheel.axis.dot(&w) + speed);
+        }
+        x
+    }
+
+    /// Wheel speeds relative to the body
+    pub fn wheel_speeds(&self, x: &DVector<f64>) -> Vec<f64> {
+        let w: Vector3<f64> = x.fixed_rows::<3>(0).into();
+        self.wheels
+            .iter()
+            .enumerate()
+            .map(|(k, wheel)| x[3 + k] / wheel.inertia - wheel.axis.dot(&w))
+            .collect()
+    }
+
+    /// Total angular momentum in the reference frame
+    pub fn angular_momentum(&self, y: &Attitude<DVector<f64>>) -> Vector3<f64> {
+        y.q * self.body_momentum(&y.x)
+    }
+
+    fn body_momentum(&self, x: &DVector<f64>) -> Vector3<f64> {
+        let w: Vector3<f64> = x.fixed_rows::<3>(0).into();
+        let wheels = self
+            .wheels
+            .iter()
+            .enumerate()
+            .fold(Vector3::zeros(), |h, (k, wheel)| h + x[3 + k] * wheel.axis);
+        self.transverse * w + wheels
+    }
+
+    /// Right-hand side for `solve_lie`
+    pub fn dynamics(
+        &self,
+        w: &mut Vector3<f64>,
+        dx: &mut DVector<f64>,
+        y: &Attitude<DVector<f64>>,
+        t: f64,
+    ) {
+        let omega: Vector3<f64> = y.x.fixed_rows::<3>(0).into();
+        let mut torque = Vector3::zeros();
+        for external in &self.torques {
+            torque += external(t, y);
+        }
+        if let Some((mu, position)) = &self.gravity {
+            let r = y.q.inverse_transform_vector(&position(t));
+            torque += gravity_gradient_torque(*mu, &r, &self.inertia);
+        }
+        let motor = match &self.wheel_torques {
+            Some(torques) => torques(t, y),
+            None => DVector::zeros(self.wheels.len()),
+        };
+        assert_eq!(motor.len(), self.wheels.len(), "one motor torque per wheel");
+        for (k, wheel) in self.wheels.iter().enumerate() {
+            torque -= motor[k] * wheel.axis;
+            dx[3 + k] = motor[k];
+        }
+        let h = self.body_momentum(&y.x);
+        let domega = self.transverse_inverse * (torque - omega.cross(&h));
+        dx.fixed_rows_mut::<3>(0).copy_from(&domega);
+        *w = omega;
+    }
+}
+
+/// `RigidBody::dynamics` as a right-hand side for `solve_lie` with the body
+/// as the parameter
+pub fn rigid_body_dynamics(
+    w: &mut Vector3<f64>,
+    dx: &mut DVector<f64>,
+    y: &Attitude<DVector<f64>>,
+    t: f64,
+    body: &RigidBody,
+) {
+    body.dynamics(w, dx, y, t);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::butcher::Tableau;
+    use crate::lie::{solve_lie, LieMethod};
+    use crate::runge_kutta;
+    use crate::solver::SolverOptions;
+    use nalgebra::{SVector, Vector6};
+
+    fn options() -> SolverOptions {
+        SolverOptions::new(Tableau::DoPri45)
+            .dt(0.01)
+            .tolerance(1e-11, 1e-13)
+    }
+
+    #[test]
+    fn test_torque_free_precession() {
+        // an axisymmetric body: the transverse rate turns at
+        // lambda = (Ia - It) / It w3 in the body, and the symmetry axis cones
+        // about the momentum at |H| / It
+        let (it, ia) = (2.0, 3.0);
+        let model = || RigidBody::new(Matrix3::from_diagonal(&Vector3::new(it, it, ia))).unwrap();
+        let body = model();
+        let (wt, w3) = (0.4, 1.5);
+        let y0 = Attitude::new(
+            UnitQuaternion::identity(),
+            body.state(Vector3::new(wt, 0.0, w3), &[]),
+        );
+        let t1 = 1.2;
+        let sol = solve_lie(
+            rigid_body_dynamics,
+            y0.clone(),
+            0.0,
+            model(),
+            t1,
+            LieMethod::Rkmk,
+            options(),
+        )
+        .unwrap();
+        let y1 = sol.last().unwrap().1;
+
+        let lambda = (ia - it) / it * w3;
+        let expected = Vector3::new(wt * (lambda * t1).cos(), wt * (lambda * t1).sin(), w3);
+        assert!((y1.x.fixed_rows::<3>(0) - expected).norm() < 1e-8);
+
+        let h = body.angular_momentum(&y0);
+        assert!((body.angular_momentum(y1) - h).norm() < 1e-8);
+        let (z0, z1) = (y0.q * Vector3::z(), y1.q * Vector3::z());
+        let nutation = |z: &Vector3<f64>| z.angle(&h);
+        assert!((nutation(&z1) - nutation(&z0)).abs() < 1e-8);
+        let h_hat = h.normalize();
+        let across = |z: &Vector3<f64>| z - z.dot(&h_hat) * h_hat;
+        let (p0, p1) = (across(&z0), across(&z1));
+        let precession = p0.cross(&p1).dot(&h_hat).atan2(p0.dot(&p1));
+        assert!((precession - h.norm() / it * t1).abs() < 1e-8);
+    }
+
+    #[test]
+    fn test_mrp_kinematics() {
+        // MRPs integrated with plain Runge–Kutta track the quaternion of the
+        // same tumbling body
+        let inertia = Matrix3::from_diagonal(&Vector3::new(1.0, 2.0, 3.0));
+        let inertia_inverse = inertia.try_inverse().unwrap();
+        let w = Vector3::new(0.2, 0.1, 0.4);
+        let f = |dy: &mut Vector6<f64>, y: &Vector6<f64>, _t: f64, _p: &()| {
+            let (sigma, w) = (y.fixed_rows::<3>(0).into(), y.fixed_rows::<3>(3).into());
+            let dw = euler_equations(&inertia, &inertia_inverse, &w, &Vector3::zeros());
+            dy.fixed_rows_mut::<3>(0).copy_from(&mrp_rate(&sigma, &w));
+            dy.fixed_rows_mut::<3>(3).copy_from(&dw);
+        };
+        let options = SolverOptions::default().tolerance(1e-12, 1e-14);
+        let y0 = Vector6::new(0.0, 0.0, 0.0, w.x, w.y, 0.0);
+        let sol = runge_kutta(f, y0, 0.0, (), 3.0, options.clone()).unwrap();
+        let y1 = sol.last().unwrap().1;
+        let q = mrp_to_quaternion(&y1.fixed_rows::<3>(0).into());
+
+        // the same motion with quaternion kinematics
+        let g = |dy: &mut SVector<f64, 7>, y: &SVector<f64, 7>, _t: f64, _p: &()| {
+            let q = Quaternion::new(y[0], y[1], y[2], y[3]);
+            let w = y.fixed_rows::<3>(4).into();
+            let dq = quaternion_rate(&q, &w);
+            let dw = euler_equations(&inertia, &inertia_inverse, &w, &Vector3::zeros());
+            *dy = SVector::<f64, 7>::from_column_slice(&[dq.w, dq.i, dq.j, dq.k, dw.x, dw.y, dw.z]);
+        };
+        let z0 = SVector::<f64, 7>::from_column_slice(&[1.0, 0.0, 0.0, 0.0, w.x, w.y, 0.0]);
+        let sol = runge_kutta(g, z0, 0.0, (), 3.0, options).unwrap();
+        let z1 = sol.last().unwrap().1;
+        let q_ref = UnitQuaternion::new_normalize(Quaternion::new(z1[0], z1[1], z1[2], z1[3]));
+        assert!(q.angle_to(&q_ref) < 1e-9);
+
+        let sigma = quaternion_to_mrp(&q_ref);
+        assert!(sigma.norm() <= 1.0);
+        assert!(mrp_to_quaternion(&sigma).angle_to(&q_ref) < 1e-12);
+        let far = Vector3::new(1.5, 0.0, 0.0);
+        assert!(mrp_to_quaternion(&mrp_shadow(&far)).angle_to(&mrp_to_quaternion(&far)) < 1e-12);
+        assert!(mrp_shadow(&far).norm() < 1.0);
+    }
+
+    #[test]
+    fn test_reaction_wheel_exchange() {
+        // a motor torque spins the wheel up and the body the other way, with
+        // the total momentum unchanged
+        let inertia = Matrix3::from_diagonal(&Vector3::new(10.0, 12.0, 8.0));
+        let (jw, tau) = (0.05, 0.01);
+        let body = RigidBody::new(inertia)
+            .unwrap()
+            .wheel(Vector3::z(), jw)
+            .unwrap()
+            .wheel_torques(|_t, _y| DVector::from_element(1, tau));
+        let y0 = Attitude::new(
+            UnitQuaternion::identity(),
+            body.state(Vector3::zeros(), &[0.0]),
+        );
+        let t1 = 20.0;
+        let f = |w: &mut Vector3<f64>,
+                 dx: &mut DVector<f64>,
+                 y: &Attitude<DVector<f64>>,
+                 t,
+                 _p: &()| { body.dynamics(w, dx, y, t) };
+        let sol = solve_lie(f, y0, 0.0, (), t1, LieMethod::Rkmk, options()).unwrap();
+        let y1 = sol.last().unwrap().1;
+        assert!(body.angular_momentum(y1).norm() < 1e-10);
+        let wz = -tau * t1 / (8.0 - jw);
+        assert!((y1.x[2] - wz).abs() < 1e-9);
+        assert!((body.wheel_speeds(&y1.x)[0] - (tau * t1 / jw - wz)).abs() < 1e-6);
+        assert!(y1.q.angle() > 0.0);
+    }
+
+    #[test]
+    fn test_invalid_body() {
+        let inertia = Matrix3::from_diagonal(&Vector3::new(10.0, 12.0, 8.0));
+        assert!(RigidBody::new(-inertia).is_err());
+        let mut skew = inertia;
+        skew[(0, 1)] = 1.0;
+        assert!(RigidBody::new(skew).is_err());
+        let body = || RigidBody::new(inertia).unwrap();
+        assert!(body().wheel(Vector3::zeros(), 0.05).is_err());
+        assert!(body().wheel(Vector3::z(), 0.0).is_err());
+        assert!(body().wheel(Vector3::z(), 8.0).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "one motor torque per wheel")]
+    fn test_wheel_torques_length() {
+        let inertia = Matrix3::from_diagonal(&Vector3::new(10.0, 12.0, 8.0));
+        let body = RigidBody::new(inertia)
+            .unwrap()
+            .wheel(Vector3::x(), 0.05)
+            .unwrap()
+            .wheel(Vector3::y(), 0.05)
+            .unwrap()
+            .wheel_torques(|_t, _y| DVector::zeros(1));
+        let y = Attitude::new(
+            UnitQuaternion::identity(),
+            body.state(Vector3::zeros(), &[0.0, 0.0]),
+        );
+        let (mut w, mut dx) = (Vector3::zeros(), DVector::zeros(5));
+        body.dynamics(&mut w, &mut dx, &y, 0.0);
+    }
+
+    #[test]
+    fn test_gravity_gradient() {
+        let inertia = Matrix3::from_diagonal(&Vector3::new(5.0, 6.0, 9.0));
+        let (mu, radius) = (3.986e14, 7.0e6);
+        let s = std::f64::consts::FRAC_1_SQRT_2;
+        let torque = gravity_gradient_torque(mu, &(radius * Vector3::new(0.0, s, s)), &inertia);
+        let expected = 1.5 * mu / radius.powi(3) * (9.0 - 6.0);
+        assert!((torque - Vector3::new(expected, 0.0, 0.0)).norm() < 1e-12 * expected);
+        // no torque along a principal axis
+        assert_eq!(
+            gravity_gradient_torque(mu, &(radius * Vector3::z()), &inertia),
+            Vector3::zeros()
+        );
+
+        // the model rotates the position into the body frame
+        let body = RigidBody::new(inertia)
+            .unwrap()
+            .gravity_gradient(mu, |_t| radius * Vector3::new(0.0, s, s))
+            .torque(|_t, _y| Vector3::new(-expected, 0.0, 0.0));
+        let y = Attitude::new(
+            UnitQuaternion::identity(),
+            body.state(Vector3::zeros(), &[]),
+        );
+        let (mut w, mut dx) = (Vector3::zeros(), DVector::zeros(3));
+        body.dynamics(&mut w, &mut dx, &y, 0.0);
+        assert!(dx.norm() < 1e-15);
+    }
+}
@@ -0,0 +1,446 @@
+use crate::brownian::BrownianPath;
+use crate::error::SolverError;
+use crate::integrator::{clamp_step, error_norm, initial_step, Stats, Stops};
+use crate::output::{Saver, Step};
+use crate::solution::Solution;
+use crate::solver::Solver;
+use crate::state::{from_slice, to_dvector, OdeState};
+use nalgebra::{DMatrix, DVector};
+
+type DiagonalDiffusion<'a, Ty, Tp> = Box<dyn Fn(&mut Ty, &Ty, f64, &Tp) + 'a>;
+type GeneralDiffusion<'a, Ty, Tp> = Box<dyn Fn(&mut DMatrix<f64>, &Ty, f64, &Tp) + 'a>;
+
+/// The noise term `g(y, t, p) dW` of an SDE `dy = f dt + g dW`
+pub enum Diffusion<'a, Ty, Tp> {
+    /// `g` has the shape of the state and each component is driven by its own
+    /// Wiener process. Component `i` of `g` may depend on `t` and `y[i]` only.
+    Diagonal(DiagonalDiffusion<'a, Ty, Tp>),
+    /// `g` is an `n x m` matrix driven by `m` Wiener processes
+    General(usize, GeneralDiffusion<'a, Ty, Tp>),
+}
+
+impl<'a, Ty: OdeState, Tp> Diffusion<'a, Ty, Tp> {
+    pub fn diagonal(g: impl Fn(&mut Ty, &Ty, f64, &Tp) + 'a) -> Self {
+        Diffusion::Diagonal(Box::new(g))
+    }
+
+    pub fn general(noise_dim: usize, g: impl Fn(&mut DMatrix<f64>, &Ty, f64, &Tp) + 'a) -> Self {
+        Diffusion::General(noise_dim, Box::new(g))
+    }
+
+    /// Number of Wiener processes for a state like `y`
+    pub fn noise_dim(&self, y: &Ty) -> usize {
+        match self {
+            Diffusion::Diagonal(_) => y.dim(),
+            Diffusion::General(m, _) => *m,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SdeMethod {
+    /// Strong order 0.5, any noise
+    #[default]
+    EulerMaruyama,
+    /// Derivative-free Milstein, strong order 1, diagonal noise
+    Milstein,
+    /// Kloeden–Platen explicit order 1.5 strong scheme, diagonal noise
+    Srk15,
+}
+
+impl SdeMethod {
+    pub fn strong_order(&self) -> f64 {
+        match self {
+            SdeMethod::EulerMaruyama => 0.5,
+            SdeMethod::Milstein => 1.0,
+            SdeMethod::Srk15 => 1.5,
+        }
+    }
+}
+
+/// The diffusion, method and Brownian path set by `Solver::noise`
+pub(crate) struct Noise<'a, Ty, Tp> {
+    pub(crate) diffusion: Diffusion<'a, Ty, Tp>,
+    pub(crate) method: SdeMethod,
+    pub(crate) path: &'a mut BrownianPath,
+}
+
+/// Evaluates the drift and diffusion on component vectors
+struct Sde<'s, Ty, Tp, F> {
+    f: F,
+    g: &'s Diffusion<'s, Ty, Tp>,
+    p: &'s Tp,
+    template: Ty,
+    fevals: usize,
+}
+
+impl<'s, Ty: OdeState, Tp, F: Fn(&mut Ty, &Ty, f64, &Tp)> Sde<'s, Ty, Tp, F> {
+    fn drift(&mut self, y: &DVector<f64>, t: f64) -> DVector<f64> {
+        self.fevals += 1;
+        let y = from_slice(&self.template, y.as_slice());
+        let mut dy = self.template.clone();
+        (self.f)(&mut dy, &y, t, self.p);
+        to_dvector(&dy)
+    }
+
+    fn diagonal(&self, y: &DVector<f64>, t: f64) -> DVector<f64> {
+        let Diffusion::Diagonal(g) = self.g else {
+            unreachable!("diagonal noise checked before stepping")
+        };
+        let y = from_slice(&self.template, y.as_slice());
+        let mut gy = self.template.clone();
+        g(&mut gy, &y, t, self.p);
+        to_dvector(&gy)
+    }
+
+    /// `g(y, t) dW`
+    fn noise(&self, y: &DVector<f64>, t: f64, dw: &DVector<f64>) -> DVector<f64> {
+        match self.g {
+            Diffusion::Diagonal(_) => self.diagonal(y, t).component_mul(dw),
+            Diffusion::General(m, g) => {
+                let mut gy = DMatrix::zeros(y.len(), *m);
+                g(
+                    &mut gy,
+                    &from_slice(&self.template, y.as_slice()),
+                    t,
+                    self.p,
+                );
+                gy * dw
+            }
+        }
+    }
+
+    /// Takes a step of `h` from `(t, y)` with drift `a` there. Returns the new
+    /// state, its drift when already evaluated, and the local error estimate.
+    #[allow(clippy::too_many_arguments)]
+    fn step(
+        &mut self,
+        method: SdeMethod,
+        t: f64,
+        y: &DVector<f64>,
+        a: &DVector<f64>,
+        h: f64,
+        dw: &DVector<f64>,
+        dz: &DVector<f64>,
+        adaptive: bool,
+    ) -> (DVector<f64>, Option<DVector<f64>>, DVector<f64>) {
+        if method == SdeMethod::EulerMaruyama {
+            let gdw = self.noise(y, t, dw);
+            let y_new = y + a * h + &gdw;
+            if !adaptive {
+                return (y_new, None, DVector::zeros(y.len()));
+            }
+            // trapezoidal corrections of the drift and diffusion
+            let a_new = self.drift(&y_new, t + h);
+            let err = (&a_new - a) * (0.5 * h) + (self.noise(&y_new, t + h, dw) - gdw) * 0.5;
+            return (y_new, Some(a_new), err);
+        }
+
+        let n = y.len();
+        let sh = h.sqrt();
+        let g = self.diagonal(y, t);
+        let up = y + a * h + &g * sh;
+        let gp = self.diagonal(&up, t + h);
+        let dw2 = dw.component_mul(dw);
+        let i11 = dw2.add_scalar(-h);
+        let y_mil = y + a * h + g.component_mul(dw) + (&gp - &g).component_mul(&i11) / (2.0 * sh);
+        if method == SdeMethod::Milstein && !adaptive {
+            return (y_mil, None, DVector::zeros(n));
+        }
+
+        let um = y + a * h - &g * sh;
+        let gm = self.diagonal(&um, t + h);
+        let phip = &up + &gp * sh;
+        let phim = &up - &gp * sh;
+        let gphip = self.diagonal(&phip, t + h);
+        let gphim = self.diagonal(&phim, t + h);
+
+        // drift terms, perturbing one noise direction at a time
+        let hn = h / n as f64;
+        let mut drift = a * h;
+        for j in 0..n {
+            let mut yp = y + a * hn;
+            let mut ym = yp.clone();
+            yp[j] += g[j] * sh;
+            ym[j] -= g[j] * sh;
+            let ap = self.drift(&yp, t + hn);
+            let am = self.drift(&ym, t + hn);
+            drift += (&ap - &am) * (dz[j] / (2.0 * sh)) + (ap - a * 2.0 + am) * (h / 4.0);
+        }
+        let y_srk = DVector::from_fn(n, |i, _| {
+            y[i] + drift[i]
+                + g[i] * dw[i]
+                + (gp[i] - gm[i]) / (4.0 * sh) * i11[i]
+                + (gp[i] - 2.0 * g[i] + gm[i]) / (2.0 * h) * (dw[i] * h - dz[i])
+                + (gphip[i] - gphim[i] - gp[i] + gm[i]) / (4.0 * h) * (dw2[i] / 3.0 - h) * dw[i]
+        });
+        let err = &y_srk - &y_mil;
+        match method {
+            SdeMethod::Milstein => (y_mil, None, err),
+            _ => (y_srk, None, err),
+        }
+    }
+}
+
+/// Integrates the Itô SDE `dy = f(y, t, p) dt + g(y, t, p) dW` forward from
+/// `t0` to `t_end` with the noise set by `Solver::noise`.
+///
+/// Steps are fixed at `options.dt` unless a tolerance is set, in which case
+/// the local error is estimated from the difference to a scheme one half
+/// order away, and the first step is `dt` or selected from the drift when
+/// `None`. Euler–Maruyama steps are never rejected, only the next step is
+/// resized. The derivative stored in the `Solution` is the drift, which is
+/// not suitable for dense output, so saved times are stepped onto exactly.
+pub(crate) fn solve_sde<'a, Ty: OdeState, Tp>(
+    solver: Solver<'a, Ty, Tp>,
+    f: impl Fn(&mut Ty, &Ty, f64, &Tp),
+    y0: Ty,
+    t0: f64,
+    p: Tp,
+    t_end: f64,
+) -> Result<Solution<Ty>, SolverError> {
+    let invalid = |msg: &str| Err(SolverError::InvalidInput(msg.to_string()));
+    solver.observers_only()?;
+    let Solver {
+        options,
+        mut observers,
+        noise,
+        ..
+    } = solver;
+    let Some(Noise {
+        diffusion: g,
+        method,
+        path,
+    }) = noise
+    else {
+        return invalid("SDE integration requires noise");
+    };
+    let g = &g;
+    options.validate_steps()?;
+    if t_end < t0 {
+        return invalid("SDEs are integrated forward in time");
+    }
+    if method != SdeMethod::EulerMaruyama && matches!(g, Diffusion::General(..)) {
+        return invalid("Milstein and order 1.5 schemes require diagonal noise");
+    }
+    if path.dim() != g.noise_dim(&y0) || path.start() > t0 {
+        return invalid("Brownian path does not match the noise");
+    }
+
+    let mut sde = Sde {
+        f,
+        g,
+        p: &p,
+        template: y0.zeros_like(),
+        fevals: 0,
+    };
+    let as_state = |y: &DVector<f64>| from_slice(&y0, y.as_slice());
+    let mut sol = Solution::default();
+    let mut saver = Saver::new(options.save_at.clone(), t0, t_end);
+    let stops = Stops::new(
+        options.tstops.iter().chain(&saver.times).copied(),
+        t0,
+        t_end,
+    );
+
+    let mut t = t0;
+    let mut y = to_dvector(&y0);
+    let mut a = sde.drift(&y, t);
+    saver.start(&mut sol, t, &y0, &as_state(&a));
+    let mut h = match options.dt {
+        Some(dt) => dt.abs(),
+        None => initial_step(&y, &a, t, t_end, method.strong_order(), &options, |y, t| {
+            sde.drift(y, t)
+        }),
+    };
+    let mut stats = Stats::default();
+    let q = 1.0 / (method.strong_order() + 0.5);
+
+    while t < t_end {
+        if stats.accepted + stats.rejected >= options.max_steps {
+            return Err(SolverError::MaxStepsExceeded { t });
+        }
+        let stop = stops.next(t);
+        let (h_try, hits_stop) = clamp_step(t, h, stop, &options)?;
+
+        let (dw, dz) = path.increment(t, t + h_try);
+        let (y_new, a_new, err) =
+            sde.step(method, t, &y, &a, h_try, &dw, &dz, options.tol.is_some());
+        if !y_new.iter().all(|v| v.is_finite()) {
+            return Err(SolverError::NonFiniteState { t });
+        }
+        if let Some(tol) = options.tol {
+            let norm = error_norm(&err, &y, &y_new, &tol);
+            // Rejecting Euler-Maruyama steps would favour increments with small
+            // dW^2 and bias the solution, so its steps are only resized
+            if norm > 1.0 && method != SdeMethod::EulerMaruyama {
+                stats.rejected += 1;
+                h = h_try * (0.9 * norm.powf(-q)).max(0.2);
+                continue;
+            }
+            let fac = if norm == 0.0 {
+                5.0
+            } else {
+                0.9 * norm.powf(-q)
+            };
+            h = h_try * fac.clamp(0.2, 5.0);
+        }
+
+        let t_new = if hits_stop { stop } else { t + h_try };
+        let a_new = a_new.unwrap_or_else(|| sde.drift(&y_new, t_new));
+        stats.accepted += 1;
+        let (y0_state, dy0_state) = (as_state(&y), as_state(&a));
+        let (y1_state, dy1_state) = (as_state(&y_new), as_state(&a_new));
+        saver.after_step(
+            &mut sol,
+            Step {
+                t0: t,
+                y0: &y0_state,
+                dy0: &dy0_state,
+                t1: t_new,
+                y1: &y1_state,
+                dy1: &dy1_state,
+            },
+        );
+        for observer in observers.iter_mut() {
+            observer(t_new, &y1_state);
+        }
+        t = t_new;
+        y = y_new;
+        a = a_new;
+    }
+    saver.finish(&mut sol, t, &as_state(&y), &as_state(&a));
+    stats.fevals = sde.fevals;
+    sol.stats = stats;
+    Ok(sol)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::SaveAt;
+    use crate::solver::SolverOptions;
+    use nalgebra::Vector2;
+
+    const MU: f64 = 0.5;
+    const SIGMA: f64 = 0.8;
+
+    // geometric Brownian motion, X(t) = X0 exp((mu - sigma^2 / 2) t + sigma W(t))
+    fn drift(dy: &mut f64, y: &f64, _t: f64, _p: &()) {
+        *dy = MU * y;
+    }
+
+    fn gbm_noise() -> Diffusion<'static, f64, ()> {
+        Diffusion::diagonal(|dy: &mut f64, y: &f64, _t: f64, _p: &()| *dy = SIGMA * y)
+    }
+
+    fn gbm_error(method: SdeMethod, dt: f64, seeds: u64) -> f64 {
+        let mut total = 0.0;
+        for seed in 0..seeds {
+            let mut path = BrownianPath::new(1, 0.0, seed);
+            let options = SolverOptions::default().fixed_dt(dt).save_at(SaveAt::Final);
+            let sol = Solver::new(options)
+                .noise(gbm_noise(), method, &mut path)
+                .solve(drift, 1.0, 0.0, (), 1.0)
+                .unwrap();
+            let w = path.value(1.0)[0];
+            let exact = ((MU - 0.5 * SIGMA * SIGMA) + SIGMA * w).exp();
+            total += (sol.last().unwrap().1 - exact).abs();
+        }
+        total / seeds as f64
+    }
+
+    #[test]
+    fn test_strong_convergence() {
+        for (method, ratio) in [
+            (SdeMethod::EulerMaruyama, 1.5),
+            (SdeMethod::Milstein, 3.0),
+            (SdeMethod::Srk15, 5.0),
+        ] {
+            let coarse = gbm_error(method, 1.0 / 16.0, 40);
+            let fine = gbm_error(method, 1.0 / 64.0, 40);
+            assert!(coarse / fine > ratio, "{:?}: {} / {}", method, coarse, fine);
+        }
+        assert!(
+            gbm_error(SdeMethod::Srk15, 1.0 / 64.0, 40)
+                < gbm_error(SdeMethod::Milstein, 1.0 / 64.0, 40)
+        );
+    }
+
+    fn adaptive_error(method: SdeMethod, tol: f64, seeds: u64) -> f64 {
+        let mut total = 0.0;
+        for seed in 0..seeds {
+            let mut path = BrownianPath::new(1, 0.0, seed);
+            let options = SolverOptions::default()
+                .tolerance(tol, 1e-2 * tol)
+                .save_at(SaveAt::Times(vec![0.25, 0.5, 1.0]));
+            let sol = Solver::new(options)
+                .noise(gbm_noise(), method, &mut path)
+                .solve(drift, 1.0, 0.0, (), 1.0)
+                .unwrap();
+            assert_eq!(sol.t, vec![0.25, 0.5, 1.0]);
+            let w = path.value(1.0)[0];
+            let exact = ((MU - 0.5 * SIGMA * SIGMA) + SIGMA * w).exp();
+            total += (sol.y[2] - exact).abs();
+        }
+        total / seeds as f64
+    }
+
+    #[test]
+    fn test_adaptive() {
+        for method in [
+            SdeMethod::EulerMaruyama,
+            SdeMethod::Milstein,
+            SdeMethod::Srk15,
+        ] {
+            let loose = adaptive_error(method, 1e-2, 40);
+            let tight = adaptive_error(method, 1e-4, 40);
+            assert!(tight < 0.7 * loose, "{:?}: {} / {}", method, loose, tight);
+        }
+        assert!(adaptive_error(SdeMethod::Srk15, 1e-4, 40) < 5e-3);
+    }
+
+    #[test]
+    fn test_general_noise() {
+        // one Wiener process drives both components, so y1 - 2 y0 stays zero
+        let f = |dy: &mut Vector2<f64>, _y: &Vector2<f64>, _t: f64, _p: &()| *dy = Vector2::zeros();
+        let g = || {
+            Diffusion::general(
+                1,
+                |g: &mut DMatrix<f64>, _y: &Vector2<f64>, _t: f64, _p: &()| {
+                    g[(0, 0)] = 1.0;
+                    g[(1, 0)] = 2.0;
+                },
+            )
+        };
+        let mut path = BrownianPath::new(1, 0.0, 5);
+        let options = SolverOptions::default().fixed_dt(0.01);
+        let sol = Solver::new(options.clone())
+            .noise(g(), SdeMethod::EulerMaruyama, &mut path)
+            .solve(f, Vector2::zeros(), 0.0, (), 1.0)
+            .unwrap();
+        let (_, y) = sol.last().unwrap();
+        assert!((y[1] - 2.0 * y[0]).abs() < 1e-12);
+        assert!((y[0] - path.value(1.0)[0]).abs() < 1e-12);
+
+        let result = Solver::new(options)
+            .noise(g(), SdeMethod::Milstein, &mut path)
+            .solve(f, Vector2::zeros(), 0.0, (), 1.0);
+        assert!(matches!(result, Err(SolverError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_seeded_paths_are_reproducible() {
+        let options = SolverOptions::default().fixed_dt(0.01);
+        let run = |seed| {
+            let mut path = BrownianPath::new(1, 0.0, seed);
+            Solver::new(options.clone())
+                .noise(gbm_noise(), SdeMethod::Srk15, &mut path)
+                .solve(drift, 1.0, 0.0, (), 1.0)
+                .unwrap()
+                .y
+        };
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+    }
+}
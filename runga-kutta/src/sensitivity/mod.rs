@@ -0,0 +1,199 @@
+use crate::error::SolverError;
+use crate::events::EventRecord;
+use crate::jacobian::{param_jacobian, state_jacobian};
+use crate::runge_kutta;
+use crate::solution::Solution;
+use crate::solver::SolverOptions;
+use crate::state::{from_slice, OdeState};
+use nalgebra::{DMatrix, DVector};
+
+/// A `Solution` together with the derivatives of the trajectory with respect
+/// to the initial state and the parameters at each saved time
+#[derive(Debug, Clone)]
+pub struct SensitivitySolution<Ty> {
+    pub solution: Solution<Ty>,
+    /// State transition matrix `dy(t)/dy0`
+    pub stm: Vec<DMatrix<f64>>,
+    /// Parameter sensitivities `dy(t)/dp`
+    pub sensitivity: Vec<DMatrix<f64>>,
+}
+
+/// Layout of the augmented state `[y, vec(stm), vec(sensitivity)]`, with the
+/// matrices stored column-major
+#[derive(Debug, Clone, Copy)]
+struct Augmented {
+    n: usize,
+    m: usize,
+}
+
+impl Augmented {
+    fn len(&self) -> usize {
+        self.n * (1 + self.n + self.m)
+    }
+
+    fn split<'z>(&self, z: &'z [f64]) -> (&'z [f64], DMatrix<f64>, DMatrix<f64>) {
+        let (n, m) = (self.n, self.m);
+        let stm = DMatrix::from_column_slice(n, n, &z[n..n + n * n]);
+        let sens = DMatrix::from_column_slice(n, m, &z[n + n * n..]);
+        (&z[..n], stm, sens)
+    }
+}
+
+/// Integrates `dy = f(y, t, p)` together with its variational equations
+/// `dStm = J_y Stm` and `dS = J_y S + J_p`, with the Jacobians taken by central
+/// differences. Step size control covers the sensitivities as well as the
+/// state. Events are not supported.
+pub fn forward_sensitivity<Ty: OdeState, Tp: OdeState>(
+    f: impl Fn(&mut Ty, &Ty, f64, &Tp),
+    y0: Ty,
+    t0: f64,
+    p: Tp,
+    t_end: f64,
+    options: SolverOptions,
+) -> Result<SensitivitySolution<Ty>, SolverError> {
+    let m = p.dim();
+    variational(
+        &f,
+        |y, t, p| param_jacobian(&f, y, t, p),
+        m,
+        y0,
+        t0,
+        p,
+        t_end,
+        options,
+    )
+}
+
+/// Integrates `dy = f(y, t, p)` together with its state transition matrix
+/// only, for parameters that are not an `OdeState`. The `sensitivity`
+/// matrices of the result have no columns.
+pub fn state_transition<Ty: OdeState, Tp>(
+    f: impl Fn(&mut Ty, &Ty, f64, &Tp),
+    y0: Ty,
+    t0: f64,
+    p: Tp,
+    t_end: f64,
+    options: SolverOptions,
+) -> Result<SensitivitySolution<Ty>, SolverError> {
+    let n = y0.dim();
+    variational(
+        &f,
+        |_, _, _| DMatrix::zeros(n, 0),
+        0,
+        y0,
+        t0,
+        p,
+        t_end,
+        options,
+    )
+}
+
+/// Integrates the state with its variational equations, given the parameter
+/// Jacobian `jp` with `m` columns
+#[allow(clippy::too_many_arguments)]
+fn variational<Ty: OdeState, Tp>(
+    f: impl Fn(&mut Ty, &Ty, f64, &Tp),
+    jp: impl Fn(&Ty, f64, &Tp) -> DMatrix<f64>,
+    m: usize,
+    y0: Ty,
+    t0: f64,
+    p: Tp,
+    t_end: f64,
+    options: SolverOptions,
+) -> Result<SensitivitySolution<Ty>, SolverError> {
+    let layout = Augmented { n: y0.dim(), m };
+    let n = layout.n;
+
+    let mut z0 = DVector::zeros(layout.len());
+    for i in 0..n {
+        z0[i] = y0.component(i);
+        z0[n + i * n + i] = 1.0;
+    }
+
+    let template = y0.zeros_like();
+    let augmented = |dz: &mut DVector<f64>, z: &DVector<f64>, t: f64, p: &Tp| {
+        let (y, stm, sens) = layout.split(z.as_slice());
+        let y = from_slice(&template, y);
+        let mut dy = template.clone();
+        f(&mut dy, &y, t, p);
+        let jy = state_jacobian(&f, &y, t, p);
+        let dstm = &jy * stm;
+        let dsens = &jy * sens + jp(&y, t, p);
+        for i in 0..n {
+            dz[i] = dy.component(i);
+        }
+        dz.rows_mut(n, n * n).copy_from_slice(dstm.as_slice());
+        dz.rows_mut(n + n * n, dsens.len())
+            .copy_from_slice(dsens.as_slice());
+    };
+    let sol = runge_kutta(augmented, z0, t0, p, t_end, options)?;
+
+    let state = |z: &DVector<f64>| from_slice(&template, &z.as_slice()[..n]);
+    let mut out = SensitivitySolution {
+        solution: Solution {
+            t: sol.t.clone(),
+            y: sol.y.iter().map(state).collect(),
+            dy: sol.dy.iter().map(state).collect(),
+            events: sol
+                .events
+                .iter()
+                .map(|e| EventRecord {
+                    index: e.index,
+                    t: e.t,
+                    y: state(&e.y),
+                })
+                .collect(),
+            stats: sol.stats,
+        },
+        stm: vec![],
+        sensitivity: vec![],
+    };
+    for z in &sol.y {
+        let (_, stm, sens) = layout.split(z.as_slice());
+        out.stm.push(stm);
+        out.sensitivity.push(sens);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::butcher::Tableau;
+    use nalgebra::{Matrix2, Vector2};
+
+    #[test]
+    fn test_decay_sensitivity() {
+        // y = y0 exp(-k t)
+        let f = |dy: &mut f64, y: &f64, _t: f64, k: &f64| *dy = -k * y;
+        let (y0, k) = (2.0, 0.7);
+        let options = SolverOptions::default().tolerance(1e-10, 1e-12);
+        let sol = forward_sensitivity(f, y0, 0.0, k, 3.0, options).unwrap();
+        for ((t, stm), sens) in sol.solution.t.iter().zip(&sol.stm).zip(&sol.sensitivity) {
+            let e = (-k * t).exp();
+            assert!((stm[(0, 0)] - e).abs() < 1e-8);
+            assert!((sens[(0, 0)] + t * y0 * e).abs() < 1e-8);
+        }
+    }
+
+    #[test]
+    fn test_oscillator_stm() {
+        let f = |dy: &mut Vector2<f64>, y: &Vector2<f64>, _t: f64, w: &f64| {
+            *dy = Vector2::new(y[1], -w * w * y[0]);
+        };
+        let w = 2.0;
+        let t1 = 1.3;
+        let options = SolverOptions::new(Tableau::Tsit5).tolerance(1e-10, 1e-12);
+        let sol = forward_sensitivity(f, Vector2::new(1.0, 0.0), 0.0, w, t1, options).unwrap();
+        let (c, s) = ((w * t1).cos(), (w * t1).sin());
+        let expected = Matrix2::new(c, s / w, -w * s, c);
+        assert!((sol.stm.last().unwrap() - expected).abs().max() < 1e-7);
+
+        // x = cos(w t), v = -w sin(w t)
+        let sens = sol.sensitivity.last().unwrap();
+        assert!((sens[(0, 0)] + t1 * s).abs() < 1e-7);
+        assert!((sens[(1, 0)] + s + w * t1 * c).abs() < 1e-7);
+        let (_, y1) = sol.solution.last().unwrap();
+        assert!((y1[0] - c).abs() < 1e-8);
+    }
+}
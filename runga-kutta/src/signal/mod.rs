@@ -0,0 +1,181 @@
+use crate::table::Table1;
+use std::f64::consts::TAU;
+
+/// An external input `u(t)` for the right-hand side of a model
+#[derive(Debug, Clone, PartialEq)]
+pub enum Signal {
+    Constant(f64),
+    /// `before` until `time`, `after` from then on
+    Step {
+        time: f64,
+        before: f64,
+        after: f64,
+    },
+    /// `initial` until `start`, then changing at `slope`
+    Ramp {
+        start: f64,
+        slope: f64,
+        initial: f64,
+    },
+    /// `offset + amplitude sin(2 pi frequency t + phase)`
+    Sine {
+        amplitude: f64,
+        frequency: f64,
+        phase: f64,
+        offset: f64,
+    },
+    /// Interpolated from a table over time, such as test data
+    Table(Table1),
+    /// The sum of several signals
+    Sum(Vec<Signal>),
+}
+
+impl Signal {
+    /// A unit step at `time`
+    pub fn step(time: f64) -> Self {
+        Signal::Step {
+            time,
+            before: 0.0,
+            after: 1.0,
+        }
+    }
+
+    /// A ramp from zero starting at `start`
+    pub fn ramp(start: f64, slope: f64) -> Self {
+        Signal::Ramp {
+            start,
+            slope,
+            initial: 0.0,
+        }
+    }
+
+    pub fn sine(amplitude: f64, frequency: f64, phase: f64) -> Self {
+        Signal::Sine {
+            amplitude,
+            frequency,
+            phase,
+            offset: 0.0,
+        }
+    }
+
+    pub fn value(&self, t: f64) -> f64 {
+        match self {
+            Signal::Constant(c) => *c,
+            Signal::Step {
+                time,
+                before,
+                after,
+            } => {
+                if t < *time {
+                    *before
+                } else {
+                    *after
+                }
+            }
+            Signal::Ramp {
+                start,
+                slope,
+                initial,
+            } => initial + slope * (t - start).max(0.0),
+            Signal::Sine {
+                amplitude,
+                frequency,
+                phase,
+                offset,
+            } => offset + amplitude * (TAU * frequency * t + phase).sin(),
+            Signal::Table(table) => table.eval(t),
+            Signal::Sum(signals) => signals.iter().map(|s| s.value(t)).sum(),
+        }
+    }
+
+    /// Times at which the signal or one of its derivatives jumps. The solver
+    /// cannot see signals used inside a right-hand side, so stopping on them
+    /// is opt-in: attach the signal with `Solver::input`, or pass them to
+    /// `SolverOptions::add_tstops`, to keep adaptive steps from straddling
+    /// them. `System::solve` does this for the external inputs of a block
+    /// diagram.
+    pub fn breakpoints(&self) -> Vec<f64> {
+        match self {
+            Signal::Constant(_) | Signal::Sine { .. } => vec![],
+            Signal::Step { time, .. } => vec![*time],
+            Signal::Ramp { start, .. } => vec![*start],
+            Signal::Table(table) => table.breakpoints().to_vec(),
+            Signal::Sum(signals) => signals.iter().flat_map(Signal::breakpoints).collect(),
+        }
+    }
+}
+
+impl std::ops::Add for Signal {
+    type Output = Signal;
+
+    fn add(self, other: Signal) -> Signal {
+        match self {
+            Signal::Sum(mut signals) => {
+                signals.push(other);
+                Signal::Sum(signals)
+            }
+            _ => Signal::Sum(vec![self, other]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runge_kutta;
+    use crate::solver::{Solver, SolverOptions};
+    use crate::table::Interpolation;
+
+    #[test]
+    fn test_signal_values() {
+        let u = Signal::step(1.0) + Signal::ramp(2.0, 0.5) + Signal::sine(2.0, 0.25, 0.0);
+        assert_eq!(u.value(0.0), 0.0);
+        assert_eq!(u.value(1.0), 3.0);
+        assert!((u.value(4.0) - 2.0).abs() < 1e-12);
+        assert_eq!(u.breakpoints(), vec![1.0, 2.0]);
+
+        let data = Table1::new(
+            vec![0.0, 1.0, 3.0],
+            vec![0.0, 2.0, 1.0],
+            Interpolation::Linear,
+        )
+        .unwrap();
+        let u = Signal::Table(data);
+        assert_eq!(u.value(2.0), 1.5);
+        assert_eq!(u.value(5.0), 1.0);
+        assert_eq!(u.breakpoints(), vec![0.0, 1.0, 3.0]);
+    }
+
+    #[test]
+    fn test_breakpoints_as_tstops() {
+        // y' = u(t) with a piecewise-linear input integrates exactly when
+        // steps end on the breakpoints
+        let data = Table1::new(
+            vec![0.0, 0.37, 1.2, 2.0],
+            vec![1.0, -2.0, 3.0, 0.0],
+            Interpolation::Linear,
+        )
+        .unwrap();
+        let u = Signal::Table(data) + Signal::ramp(1.5, 2.0);
+        let f = |dy: &mut f64, _y: &f64, t: f64, u: &Signal| *dy = u.value(t);
+        let exact = 0.37 * (1.0 - 2.0) / 2.0 + 0.83 * (-2.0 + 3.0) / 2.0 + 0.8 * 1.5 + 0.25;
+        let options = SolverOptions::default().add_tstops(u.breakpoints());
+        let sol = runge_kutta(f, 0.0, 0.0, u, 2.0, options).unwrap();
+        assert!((sol.last().unwrap().1 - exact).abs() < 1e-12);
+        assert!(sol.t.contains(&0.37) && sol.t.contains(&1.5));
+    }
+
+    #[test]
+    fn test_solver_input_stops_on_breakpoints() {
+        // the kinks of the ramps are stopped on without add_tstops
+        let u = Signal::ramp(0.37, -2.0) + Signal::ramp(1.5, 2.0);
+        let f = |dy: &mut f64, _y: &f64, t: f64, u: &Signal| *dy = u.value(t);
+        let exact = -1.63 * 1.63 + 0.25;
+        let sol = Solver::default()
+            .input(&u)
+            .solve(f, 0.0, 0.0, u.clone(), 2.0)
+            .unwrap();
+        assert!((sol.last().unwrap().1 - exact).abs() < 1e-12);
+        assert!(sol.t.contains(&0.37) && sol.t.contains(&1.5));
+    }
+}
@@ -0,0 +1,208 @@
+use crate::state::OdeState;
+use std::fmt;
+use std::ops::{Add, Mul, Sub};
+use units::{Angle, Length};
+
+/// The unit a `SimVal` is expressed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Units {
+    Dimensionless,
+    Length(Length),
+    Angle(Angle),
+}
+
+impl Units {
+    /// Factor that converts a value in these units to `other`, or `None` if
+    /// the two measure different quantities
+    pub fn factor_to(&self, other: &Units) -> Option<f64> {
+        if self == other {
+            return Some(1.0);
+        }
+        match (self, other) {
+            (Units::Length(from), Units::Length(to)) => Some(Length::convert(1.0, *from, *to)),
+            (Units::Angle(from), Units::Angle(to)) => Some(Angle::convert(1.0, *from, *to)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnitError {
+    pub from: Units,
+    pub to: Units,
+}
+
+impl fmt::Display for UnitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot convert {:?} to {:?}", self.from, self.to)
+    }
+}
+
+impl std::error::Error for UnitError {}
+
+/// A simulation value paired with its units. Adding or subtracting values in
+/// different units of the same quantity converts the right-hand side to the
+/// units of the left; mixing quantities panics, or returns an error from the
+/// `checked_` variants. As an ODE state the derivative carries the units of
+/// the state per unit time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimVal<T> {
+    pub value: T,
+    pub units: Units,
+}
+
+impl<T> SimVal<T> {
+    pub fn new(value: T, units: Units) -> Self {
+        Self { value, units }
+    }
+}
+
+impl<T: Mul<f64, Output = T>> SimVal<T> {
+    /// The same value expressed in `units`
+    pub fn to(self, units: Units) -> Result<Self, UnitError> {
+        let factor = self.units.factor_to(&units).ok_or(UnitError {
+            from: self.units,
+            to: units,
+        })?;
+        if factor == 1.0 {
+            return Ok(Self::new(self.value, units));
+        }
+        Ok(Self::new(self.value * factor, units))
+    }
+}
+
+impl<T: Add<Output = T> + Mul<f64, Output = T>> SimVal<T> {
+    pub fn checked_add(self, other: Self) -> Result<Self, UnitError> {
+        let other = other.to(self.units)?;
+        Ok(Self::new(self.value + other.value, self.units))
+    }
+}
+
+impl<T: Sub<Output = T> + Mul<f64, Output = T>> SimVal<T> {
+    pub fn checked_sub(self, other: Self) -> Result<Self, UnitError> {
+        let other = other.to(self.units)?;
+        Ok(Self::new(self.value - other.value, self.units))
+    }
+}
+
+impl<T: Add<Output = T> + Mul<f64, Output = T>> Add for SimVal<T> {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        self.checked_add(other).unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+impl<T: Sub<Output = T> + Mul<f64, Output = T>> Sub for SimVal<T> {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self.checked_sub(other).unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+impl<T: Mul<f64, Output = T>> Mul<f64> for SimVal<T> {
+    type Output = Self;
+
+    fn mul(self, scale: f64) -> Self {
+        Self::new(self.value * scale, self.units)
+    }
+}
+
+impl<T: OdeState> OdeState for SimVal<T> {
+    fn dim(&self) -> usize {
+        self.value.dim()
+    }
+
+    fn component(&self, i: usize) -> f64 {
+        self.value.component(i)
+    }
+
+    fn set_component(&mut self, i: usize, value: f64) {
+        self.value.set_component(i, value);
+    }
+
+    fn zeros_like(&self) -> Self {
+        Self::new(self.value.zeros_like(), self.units)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::butcher::Tableau;
+    use crate::runge_kutta;
+    use crate::solver::SolverOptions;
+    use nalgebra::Vector2;
+
+    #[test]
+    fn test_conversion() {
+        let x = SimVal::new(1.0, Units::Length(Length::Kilometers));
+        let y = x.to(Units::Length(Length::Meters)).unwrap();
+        assert_eq!(y.value, 1000.0);
+        assert!(x.to(Units::Angle(Angle::Degrees)).is_err());
+        assert!(x.to(Units::Dimensionless).is_err());
+    }
+
+    #[test]
+    fn test_arithmetic_converts_units() {
+        let a = SimVal::new(1.0, Units::Length(Length::Meters));
+        let b = SimVal::new(1.0, Units::Length(Length::Kilometers));
+        let sum = a + b;
+        assert_eq!(sum.value, 1001.0);
+        assert_eq!(sum.units, Units::Length(Length::Meters));
+        assert_eq!((b - a).value, 0.999);
+        assert_eq!((a * 2.0).value, 2.0);
+
+        let angle = SimVal::new(90.0, Units::Angle(Angle::Degrees));
+        assert!(a.checked_add(angle).is_err());
+        assert!(a.checked_sub(angle).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot convert")]
+    fn test_mixed_quantities_panic() {
+        let a = SimVal::new(1.0, Units::Length(Length::Meters));
+        let b = SimVal::new(1.0, Units::Angle(Angle::Radians));
+        let _ = a + b;
+    }
+
+    #[test]
+    fn test_state_in_feet_with_derivative_in_meters() {
+        // the model returns a velocity in meters per second for a position
+        // integrated in feet
+        let f = |dy: &mut SimVal<f64>, _y: &SimVal<f64>, _t: f64, _p: &()| {
+            *dy = SimVal::new(1.0, Units::Length(Length::Meters));
+        };
+        let y0 = SimVal::new(0.0, Units::Length(Length::Feet));
+        let sol =
+            runge_kutta(f, y0, 0.0, (), 1.0, SolverOptions::fixed(Tableau::RK4, 0.1)).unwrap();
+        let (_, y1) = sol.last().unwrap();
+        assert_eq!(y1.units, Units::Length(Length::Feet));
+        assert!((y1.value - 1.0 / 0.3048).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_vector_state() {
+        let f = |dy: &mut SimVal<Vector2<f64>>, y: &SimVal<Vector2<f64>>, _t: f64, _p: &()| {
+            *dy = SimVal::new(Vector2::new(y.value[1], -y.value[0]), y.units);
+        };
+        let y0 = SimVal::new(Vector2::new(1.0, 0.0), Units::Angle(Angle::Radians));
+        let options = SolverOptions::default().tolerance(1e-10, 1e-12);
+        let sol = runge_kutta(f, y0, 0.0, (), 1.0, options).unwrap();
+        let (_, y1) = sol.last().unwrap();
+        assert!((y1.value[0] - 1.0_f64.cos()).abs() < 1e-8);
+        let degrees = y1.to(Units::Angle(Angle::Degrees)).unwrap();
+        assert!((degrees.value[0] - 1.0_f64.cos().to_degrees()).abs() < 1e-6);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot convert")]
+    fn test_model_unit_mistake_is_caught() {
+        let f = |dy: &mut SimVal<f64>, _y: &SimVal<f64>, _t: f64, _p: &()| {
+            *dy = SimVal::new(1.0, Units::Angle(Angle::Degrees));
+        };
+        let y0 = SimVal::new(0.0, Units::Length(Length::Meters));
+        let _ = runge_kutta(f, y0, 0.0, (), 1.0, SolverOptions::fixed(Tableau::RK4, 0.1));
+    }
+}
@@ -0,0 +1,185 @@
+use crate::events::EventRecord;
+use crate::integrator::Stats;
+use crate::state::OdeState;
+use serde::{Deserialize, Serialize};
+
+/// Time history produced by an integration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Solution<Ty> {
+    pub t: Vec<f64>,
+    pub y: Vec<Ty>,
+    /// Derivative at each saved point, used for dense output
+    pub dy: Vec<Ty>,
+    pub events: Vec<EventRecord<Ty>>,
+    pub stats: Stats,
+}
+
+impl<Ty> Default for Solution<Ty> {
+    fn default() -> Self {
+        Self {
+            t: vec![],
+            y: vec![],
+            dy: vec![],
+            events: vec![],
+            stats: Stats::default(),
+        }
+    }
+}
+
+impl<Ty: OdeState> Solution<Ty> {
+    pub(crate) fn push(&mut self, t: f64, y: Ty, dy: Ty) {
+        self.t.push(t);
+        self.y.push(y);
+        self.dy.push(dy);
+    }
+
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    /// +1 for forward integration, -1 for backward
+    pub fn direction(&self) -> f64 {
+        match (self.t.first(), self.t.last()) {
+            (Some(t0), Some(t1)) if t1 < t0 => -1.0,
+            _ => 1.0,
+        }
+    }
+
+    pub fn last(&self) -> Option<(f64, &Ty)> {
+        Some((*self.t.last()?, self.y.last()?))
+    }
+
+    /// Cubic Hermite interpolation between saved points. Returns `None` outside
+    /// the integrated interval. Works for forward and backward solutions.
+    pub fn interpolate(&self, t: f64) -> Option<Ty> {
+        let n = self.t.len();
+        if n == 0 {
+            return None;
+        }
+        let dir = self.direction();
+        let (t_first, t_last) = (self.t[0], self.t[n - 1]);
+        if dir * (t - t_first) < 0.0 || dir * (t - t_last) > 0.0 {
+            return None;
+        }
+        if n == 1 || t == t_last {
+            return Some(self.y[n - 1].clone());
+        }
+        // first index whose time lies beyond t in the integration direction
+        let i = self
+            .t
+            .partition_point(|&ti| dir * (ti - t) <= 0.0)
+            .clamp(1, n - 1);
+        Some(hermite(
+            self.t[i - 1],
+            &self.y[i - 1],
+            &self.dy[i - 1],
+            self.t[i],
+            &self.y[i],
+            &self.dy[i],
+            t,
+        ))
+    }
+
+    /// Derivative of the dense output at `t`
+    pub fn interpolate_derivative(&self, t: f64) -> Option<Ty> {
+        let n = self.t.len();
+        if n < 2 {
+            return self.dy.first().cloned();
+        }
+        let dir = self.direction();
+        if dir * (t - self.t[0]) < 0.0 || dir * (t - self.t[n - 1]) > 0.0 {
+            return None;
+        }
+        let i = self
+            .t
+            .partition_point(|&ti| dir * (ti - t) <= 0.0)
+            .clamp(1, n - 1);
+        Some(hermite_derivative(
+            self.t[i - 1],
+            &self.y[i - 1],
+            &self.dy[i - 1],
+            self.t[i],
+            &self.y[i],
+            &self.dy[i],
+            t,
+        ))
+    }
+}
+
+/// Cubic Hermite interpolant through `(t0, y0, dy0)` and `(t1, y1, dy1)`
+pub fn hermite<Ty: OdeState>(t0: f64, y0: &Ty, dy0: &Ty, t1: f64, y1: &Ty, dy1: &Ty, t: f64) -> Ty {
+    let h = t1 - t0;
+    if h == 0.0 {
+        return y1.clone();
+    }
+    let s = (t - t0) / h;
+    let h00 = (1.0 + 2.0 * s) * (1.0 - s) * (1.0 - s);
+    let h10 = s * (1.0 - s) * (1.0 - s);
+    let h01 = s * s * (3.0 - 2.0 * s);
+    let h11 = s * s * (s - 1.0);
+    y0.clone() * h00 + dy0.clone() * (h10 * h) + y1.clone() * h01 + dy1.clone() * (h11 * h)
+}
+
+pub fn hermite_derivative<Ty: OdeState>(
+    t0: f64,
+    y0: &Ty,
+    dy0: &Ty,
+    t1: f64,
+    y1: &Ty,
+    dy1: &Ty,
+    t: f64,
+) -> Ty {
+    let h = t1 - t0;
+    if h == 0.0 {
+        return dy1.clone();
+    }
+    let s = (t - t0) / h;
+    let d00 = 6.0 * s * (s - 1.0) / h;
+    let d10 = (1.0 - s) * (1.0 - 3.0 * s);
+    let d01 = -d00;
+    let d11 = s * (3.0 * s - 2.0);
+    y0.clone() * d00 + dy0.clone() * d10 + y1.clone() * d01 + dy1.clone() * d11
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cubic_solution(ts: &[f64]) -> Solution<f64> {
+        // y = t^3 - t is reproduced exactly by cubic Hermite interpolation
+        let mut sol = Solution::default();
+        for &t in ts {
+            sol.push(t, t * t * t - t, 3.0 * t * t - 1.0);
+        }
+        sol
+    }
+
+    #[test]
+    fn test_interpolate_forward() {
+        let sol = cubic_solution(&[0.0, 0.5, 1.5, 2.0]);
+        for t in [0.0, 0.2, 0.5, 1.0, 1.7, 2.0] {
+            let y = sol.interpolate(t).unwrap();
+            assert!((y - (t * t * t - t)).abs() < 1e-12);
+            let dy = sol.interpolate_derivative(t).unwrap();
+            assert!((dy - (3.0 * t * t - 1.0)).abs() < 1e-12);
+        }
+        assert!(sol.interpolate(-0.1).is_none());
+        assert!(sol.interpolate(2.1).is_none());
+    }
+
+    #[test]
+    fn test_interpolate_backward() {
+        let sol = cubic_solution(&[2.0, 1.5, 0.5, 0.0]);
+        assert_eq!(sol.direction(), -1.0);
+        for t in [0.0, 0.2, 0.5, 1.0, 1.7, 2.0] {
+            let y = sol.interpolate(t).unwrap();
+            assert!((y - (t * t * t - t)).abs() < 1e-12);
+        }
+        assert!(sol.interpolate(-0.1).is_none());
+        assert!(sol.interpolate(2.1).is_none());
+    }
+}
@@ -0,0 +1,429 @@
+use crate::brownian::BrownianPath;
+use crate::butcher::Tableau;
+use crate::checkpoint::Checkpoint;
+use crate::dae::solve_dae;
+use crate::error::SolverError;
+use crate::events::Event;
+use crate::hybrid::{Hybrid, HybridSolver};
+use crate::integrator::{Integrator, Tolerance};
+use crate::output::{Observer, SaveAt};
+use crate::projection::Projection;
+use crate::sde::{solve_sde, Diffusion, Noise, SdeMethod};
+use crate::signal::Signal;
+use crate::solution::Solution;
+use crate::state::OdeState;
+use nalgebra::DMatrix;
+use serde::{Deserialize, Serialize};
+
+/// Method, tolerances, step limits and output options for an integration.
+/// The default is adaptive `DoPri45` with the default `Tolerance`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SolverOptions {
+    pub tableau: Tableau,
+    /// Adaptive stepping when set, fixed steps of `dt` otherwise
+    pub tol: Option<Tolerance>,
+    /// Fixed step size, or the first adaptive step. Selected automatically
+    /// for adaptive integration when `None`.
+    pub dt: Option<f64>,
+    pub dtmin: f64,
+    pub dtmax: f64,
+    pub max_steps: usize,
+    pub save_at: SaveAt,
+    /// Times the integrator steps onto exactly, such as known discontinuities
+    #[serde(default)]
+    pub tstops: Vec<f64>,
+}
+
+impl Default for SolverOptions {
+    fn default() -> Self {
+        Self {
+            tableau: Tableau::default(),
+            tol: Some(Tolerance::default()),
+            dt: None,
+            dtmin: 0.0,
+            dtmax: f64::MAX,
+            max_steps: 1_000_000,
+            save_at: SaveAt::default(),
+            tstops: vec![],
+        }
+    }
+}
+
+impl SolverOptions {
+    /// Adaptive integration with `tableau` and the default tolerances
+    pub fn new(tableau: Tableau) -> Self {
+        Self {
+            tableau,
+            ..Self::default()
+        }
+    }
+
+    /// Fixed steps of `dt` with `tableau`
+    pub fn fixed(tableau: Tableau, dt: f64) -> Self {
+        Self {
+            tableau,
+            tol: None,
+            dt: Some(dt),
+            ..Self::default()
+        }
+    }
+
+    pub fn tableau(mut self, tableau: Tableau) -> Self {
+        self.tableau = tableau;
+        self
+    }
+
+    pub fn tolerance(mut self, rtol: f64, atol: f64) -> Self {
+        self.tol = Some(Tolerance::new(rtol, atol));
+        self
+    }
+
+    /// Fixed steps of `dt`, for solvers whose method does not come from
+    /// `tableau`
+    pub fn fixed_dt(mut self, dt: f64) -> Self {
+        self.tol = None;
+        self.dt = Some(dt);
+        self
+    }
+
+    pub fn dt(mut self, dt: f64) -> Self {
+        self.dt = Some(dt);
+        self
+    }
+
+    pub fn dtmin(mut self, dtmin: f64) -> Self {
+        self.dtmin = dtmin;
+        self
+    }
+
+    pub fn dtmax(mut self, dtmax: f64) -> Self {
+        self.dtmax = dtmax;
+        self
+    }
+
+    pub fn max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    pub fn save_at(mut self, save_at: SaveAt) -> Self {
+        self.save_at = save_at;
+        self
+    }
+
+    pub fn tstops(mut self, tstops: Vec<f64>) -> Self {
+        self.tstops = tstops;
+        self
+    }
+
+    /// Adds to the stop times, for example the breakpoints of input signals
+    pub fn add_tstops(mut self, tstops: impl IntoIterator<Item = f64>) -> Self {
+        self.tstops.extend(tstops);
+        self
+    }
+
+    /// Order of the chosen method
+    pub fn order(&self) -> usize {
+        self.tableau.tableau().order
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), SolverError> {
+        self.validate_steps()?;
+        if self.tol.is_some() && !self.tableau.tableau().is_adaptive() {
+            return Err(SolverError::InvalidInput(
+                "adaptive stepping requires a tableau with an embedded method".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the step control and output options, for solvers whose method
+    /// does not come from `tableau`
+    pub(crate) fn validate_steps(&self) -> Result<(), SolverError> {
+        let invalid = |msg: &str| Err(SolverError::InvalidInput(msg.to_string()));
+        if self.tol.is_none() && self.dt.is_none() {
+            return invalid("fixed-step integration requires dt");
+        }
+        if let Some(dt) = self.dt {
+            if dt == 0.0 || !dt.is_finite() {
+                return invalid("dt must be finite and nonzero");
+            }
+        }
+        if let Some(tol) = self.tol {
+            if tol.rtol < 0.0 || tol.atol < 0.0 || tol.rtol + tol.atol <= 0.0 {
+                return invalid("tolerances must be nonnegative and not both zero");
+            }
+        }
+        if self.dtmin < 0.0 || self.dtmax <= 0.0 || self.dtmin > self.dtmax {
+            return invalid("step limits must satisfy 0 <= dtmin <= dtmax");
+        }
+        if self.save_at == SaveAt::EveryNth(0) {
+            return invalid("SaveAt::EveryNth needs a positive step count");
+        }
+        Ok(())
+    }
+}
+
+/// Bundles `SolverOptions` with events and observers so an integration can be
+/// set up once and solved for any right-hand side and initial condition
+pub struct Solver<'a, Ty, Tp> {
+    pub options: SolverOptions,
+    pub(crate) events: Vec<Event<'a, Ty, Tp>>,
+    pub(crate) observers: Vec<Observer<'a, Ty>>,
+    pub(crate) projections: Vec<Projection<'a, Ty, Tp>>,
+    pub(crate) mass: Option<DMatrix<f64>>,
+    pub(crate) noise: Option<Noise<'a, Ty, Tp>>,
+}
+
+impl<'a, Ty: OdeState, Tp> Default for Solver<'a, Ty, Tp> {
+    fn default() -> Self {
+        Self::new(SolverOptions::default())
+    }
+}
+
+impl<'a, Ty: OdeState, Tp> Solver<'a, Ty, Tp> {
+    pub fn new(options: SolverOptions) -> Self {
+        Self {
+            options,
+            events: vec![],
+            observers: vec![],
+            projections: vec![],
+            mass: None,
+            noise: None,
+        }
+    }
+
+    pub fn event(mut self, event: Event<'a, Ty, Tp>) -> Self {
+        self.events.push(event);
+        self
+    }
+
+    /// Adds a callback invoked with `(t, y)` after each accepted step
+    pub fn observer(mut self, observer: impl FnMut(f64, &Ty) + 'a) -> Self {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    /// Registers an input signal used by the right-hand side, so that steps
+    /// stop on its breakpoints
+    pub fn input(mut self, signal: &Signal) -> Self {
+        self.options.tstops.extend(signal.breakpoints());
+        self
+    }
+
+    /// Adds a projection applied to the state after each accepted step
+    pub fn projection(mut self, projection: Projection<'a, Ty, Tp>) -> Self {
+        self.projections.push(projection);
+        self
+    }
+
+    /// Solves `mass * dy = f(y, t, p)` with a constant mass matrix. When it is
+    /// singular this is a differential-algebraic equation: components whose
+    /// rows of `mass` vanish are algebraic, and the equation must be of index
+    /// 1, meaning they are determined by the differential components through
+    /// `f`. `solve` uses a linearly implicit Rosenbrock method instead of
+    /// `options.tableau`, which also suits stiff ODEs, and integrates backward
+    /// in time when `t_end < t0`. Events and projections are not supported.
+    pub fn mass_matrix(mut self, mass: DMatrix<f64>) -> Self {
+        self.mass = Some(mass);
+        self
+    }
+
+    /// Adds the noise `g(y, t, p) dW` of an Itô SDE `dy = f dt + g dW`, which
+    /// `solve` integrates forward in time with `method` along `path`. The path
+    /// must have one component per Wiener process and start no later than the
+    /// initial time; reusing it with a different step size gives a solution
+    /// on the same realization. `options.tableau` is not used, and events and
+    /// projections are not supported.
+    pub fn noise(
+        mut self,
+        diffusion: Diffusion<'a, Ty, Tp>,
+        method: SdeMethod,
+        path: &'a mut BrownianPath,
+    ) -> Self {
+        self.noise = Some(Noise {
+            diffusion,
+            method,
+            path,
+        });
+        self
+    }
+
+    /// Adds discrete-time updates that drive the plant through a discrete
+    /// state, for `HybridSolver::solve`. Events, observers and projections
+    /// apply to every segment between samples; a terminal event ends the
+    /// whole integration.
+    pub fn hybrid<Td>(self, hybrid: Hybrid<'a, Ty, Td, Tp>) -> HybridSolver<'a, Ty, Td, Tp> {
+        HybridSolver {
+            solver: self,
+            hybrid,
+        }
+    }
+
+    /// Sets up an integrator for stepping manually
+    pub fn integrator(
+        self,
+        f: impl Fn(&mut Ty, &Ty, f64, &Tp) + 'a,
+        y0: Ty,
+        t0: f64,
+        p: Tp,
+        t_end: f64,
+    ) -> Result<Integrator<'a, Ty, Tp>, SolverError> {
+        self.explicit_only()?;
+        let integrator = Integrator::new(f, y0, t0, p, t_end, self.options.clone())?;
+        Ok(self.attach(integrator))
+    }
+
+    /// Resumes an integration from a checkpoint with these events, observers
+    /// and projections. The options stored in the checkpoint are used, not
+    /// `self.options`.
+    pub fn restore(
+        self,
+        f: impl Fn(&mut Ty, &Ty, f64, &Tp) + 'a,
+        p: Tp,
+        checkpoint: Checkpoint<Ty>,
+    ) -> Result<Integrator<'a, Ty, Tp>, SolverError> {
+        self.explicit_only()?;
+        let integrator = Integrator::restore(f, p, checkpoint)?;
+        Ok(self.attach(integrator))
+    }
+
+    fn explicit_only(&self) -> Result<(), SolverError> {
+        if self.mass.is_some() || self.noise.is_some() {
+            return Err(SolverError::InvalidInput(
+                "problems with a mass matrix or noise are only integrated by Solver::solve"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that only observers are attached, for the solvers that do not
+    /// use `Integrator`
+    pub(crate) fn observers_only(&self) -> Result<(), SolverError> {
+        let invalid = |msg: &str| Err(SolverError::InvalidInput(msg.to_string()));
+        if self.mass.is_some() && self.noise.is_some() {
+            return invalid("a mass matrix cannot be combined with noise");
+        }
+        if !self.events.is_empty() || !self.projections.is_empty() {
+            return invalid("events and projections are not supported with a mass matrix or noise");
+        }
+        Ok(())
+    }
+
+    fn attach(self, mut integrator: Integrator<'a, Ty, Tp>) -> Integrator<'a, Ty, Tp> {
+        for event in self.events {
+            integrator.add_event(event);
+        }
+        for observer in self.observers {
+            integrator.add_boxed_observer(observer);
+        }
+        for projection in self.projections {
+            integrator.add_projection(projection);
+        }
+        integrator
+    }
+
+    pub fn solve(
+        self,
+        f: impl Fn(&mut Ty, &Ty, f64, &Tp) + 'a,
+        y0: Ty,
+        t0: f64,
+        p: Tp,
+        t_end: f64,
+    ) -> Result<Solution<Ty>, SolverError> {
+        if self.noise.is_some() {
+            return solve_sde(self, f, y0, t0, p, t_end);
+        }
+        if self.mass.is_some() {
+            return solve_dae(self, f, y0, t0, p, t_end);
+        }
+        self.integrator(f, y0, t0, p, t_end)?.solve()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Crossing;
+    use nalgebra::Vector2;
+
+    fn oscillator(dy: &mut Vector2<f64>, y: &Vector2<f64>, _t: f64, w: &f64) {
+        *dy = Vector2::new(y[1], -w * w * y[0]);
+    }
+
+    #[test]
+    fn test_defaults() {
+        let options = SolverOptions::default();
+        assert_eq!(options.tableau, Tableau::DoPri45);
+        assert_eq!(options.order(), 5);
+        assert!(options.validate().is_ok());
+        assert_eq!(SolverOptions::new(Tableau::BS3).order(), 3);
+        assert_eq!(SolverOptions::fixed(Tableau::RK4, 0.1).order(), 4);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(SolverOptions::fixed(Tableau::RK4, 0.0).validate().is_err());
+        assert!(SolverOptions::new(Tableau::RK4).validate().is_err());
+        assert!(SolverOptions::default()
+            .dtmin(1.0)
+            .dtmax(0.5)
+            .validate()
+            .is_err());
+        assert!(SolverOptions::default()
+            .save_at(SaveAt::EveryNth(0))
+            .validate()
+            .is_err());
+        let mut options = SolverOptions::fixed(Tableau::RK4, 0.1);
+        options.dt = None;
+        assert!(options.validate().is_err());
+    }
+
+    #[test]
+    fn test_solver_with_callbacks() {
+        let mut steps = 0;
+        let sol = Solver::new(SolverOptions::default().tolerance(1e-10, 1e-12))
+            .event(
+                Event::new(|_t, y: &Vector2<f64>, _w: &f64| y[0])
+                    .crossing(Crossing::Falling)
+                    .terminal(true),
+            )
+            .observer(|_t, _y| steps += 1)
+            .solve(oscillator, Vector2::new(1.0, 0.0), 0.0, 2.0, 10.0)
+            .unwrap();
+        let te = sol.events[0].t;
+        assert!((te - std::f64::consts::FRAC_PI_4).abs() < 1e-8);
+        assert_eq!(steps, sol.stats.accepted);
+    }
+
+    #[test]
+    fn test_step_limits() {
+        let sol = Solver::new(SolverOptions::default().dtmax(0.1))
+            .solve(oscillator, Vector2::new(1.0, 0.0), 0.0, 1.0, 10.0)
+            .unwrap();
+        assert!(sol.t.windows(2).all(|w| w[1] - w[0] <= 0.1 + 1e-12));
+
+        let result = Solver::new(SolverOptions::default().max_steps(10)).solve(
+            oscillator,
+            Vector2::new(1.0, 0.0),
+            0.0,
+            1.0,
+            100.0,
+        );
+        assert!(matches!(result, Err(SolverError::MaxStepsExceeded { .. })));
+
+        // y = 1 / (1 - t) blows up at t = 1, so the controller keeps shrinking
+        // the step until it falls below dtmin
+        let blowup = |dy: &mut f64, y: &f64, _t: f64, _p: &()| *dy = y * y;
+        let result =
+            Solver::new(SolverOptions::default().dtmin(1e-4)).solve(blowup, 1.0, 0.0, (), 2.0);
+        match result {
+            Err(SolverError::StepSizeTooSmall { t, dt }) => {
+                assert!(t > 0.9 && t < 1.0);
+                assert!(dt < 1e-4);
+            }
+            _ => panic!("expected StepSizeTooSmall"),
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use nalgebra::{DVector, SVector};
+use std::ops::{Add, Mul, Sub};
+
+/// A value that can be integrated as the state of an ODE
+pub trait OdeState:
+    Clone + Add<Output = Self> + Sub<Output = Self> + Mul<f64, Output = Self>
+{
+    /// Number of scalar components
+    fn dim(&self) -> usize;
+    fn component(&self, i: usize) -> f64;
+    fn set_component(&mut self, i: usize, value: f64);
+    /// A state of the same shape with every component zero
+    fn zeros_like(&self) -> Self;
+}
+
+impl OdeState for f64 {
+    fn dim(&self) -> usize {
+        1
+    }
+
+    fn component(&self, _i: usize) -> f64 {
+        *self
+    }
+
+    fn set_component(&mut self, _i: usize, value: f64) {
+        *self = value;
+    }
+
+    fn zeros_like(&self) -> Self {
+        0.0
+    }
+}
+
+impl<const N: usize> OdeState for SVector<f64, N> {
+    fn dim(&self) -> usize {
+        N
+    }
+
+    fn component(&self, i: usize) -> f64 {
+        self[i]
+    }
+
+    fn set_component(&mut self, i: usize, value: f64) {
+        self[i] = value;
+    }
+
+    fn zeros_like(&self) -> Self {
+        Self::zeros()
+    }
+}
+
+impl OdeState for DVector<f64> {
+    fn dim(&self) -> usize {
+        self.len()
+    }
+
+    fn component(&self, i: usize) -> f64 {
+        self[i]
+    }
+
+    fn set_component(&mut self, i: usize, value: f64) {
+        self[i] = value;
+    }
+
+    fn zeros_like(&self) -> Self {
+        DVector::zeros(self.len())
+    }
+}
+
+/// Returns `y + x * a`
+pub(crate) fn axpy<Ty: OdeState>(y: &Ty, a: f64, x: &Ty) -> Ty {
+    y.clone() + x.clone() * a
+}
+
+pub(crate) fn is_finite<Ty: OdeState>(y: &Ty) -> bool {
+    (0..y.dim()).all(|i| y.component(i).is_finite())
+}
+
+/// Components of `y` as a column vector
+pub(crate) fn to_dvector<Ty: OdeState>(y: &Ty) -> DVector<f64> {
+    DVector::from_fn(y.dim(), |i, _| y.component(i))
+}
+
+/// A state shaped like `template` holding `values`
+pub(crate) fn from_slice<Ty: OdeState>(template: &Ty, values: &[f64]) -> Ty {
+    let mut y = template.clone();
+    for (i, &v) in values.iter().enumerate().take(y.dim()) {
+        y.set_component(i, v);
+    }
+    y
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector3;
+
+    #[test]
+    fn test_components() {
+        let mut y = Vector3::new(1.0, 2.0, 3.0);
+        assert_eq!(y.dim(), 3);
+        y.set_component(1, 5.0);
+        assert_eq!(y.component(1), 5.0);
+        assert_eq!(y.zeros_like(), Vector3::zeros());
+
+        let mut x = 2.0;
+        x.set_component(0, 4.0);
+        assert_eq!(x.component(0), 4.0);
+        assert_eq!(x.dim(), 1);
+
+        let d = DVector::from_vec(vec![1.0, 2.0]);
+        assert_eq!(d.zeros_like().dim(), 2);
+    }
+
+    #[test]
+    fn test_axpy() {
+        let y = Vector3::new(1.0, 1.0, 1.0);
+        let x = Vector3::new(1.0, 2.0, 3.0);
+        assert_eq!(axpy(&y, 2.0, &x), Vector3::new(3.0, 5.0, 7.0));
+        assert!(is_finite(&y));
+        assert!(!is_finite(&f64::NAN));
+    }
+
+    #[test]
+    fn test_dvector_round_trip() {
+        let y = Vector3::new(1.0, 2.0, 3.0);
+        let v = to_dvector(&y);
+        assert_eq!(v.as_slice(), &[1.0, 2.0, 3.0]);
+        assert_eq!(from_slice(&Vector3::zeros(), v.as_slice()), y);
+    }
+}
@@ -0,0 +1,379 @@
+use crate::error::SolverError;
+use nalgebra::{DMatrix, DVector};
+
+/// How a lookup table interpolates between its breakpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    /// Natural cubic spline, twice continuously differentiable
+    CubicSpline,
+    /// Akima's piecewise cubic, which follows the data without the overshoot
+    /// of a spline near abrupt changes
+    Akima,
+}
+
+fn check_axis(x: &[f64]) -> Result<(), SolverError> {
+    if x.len() < 2 {
+        return Err(SolverError::InvalidInput(
+            "a table axis needs at least two breakpoints".to_string(),
+        ));
+    }
+    if x.windows(2).any(|w| w[1] <= w[0]) || x.iter().any(|v| !v.is_finite()) {
+        return Err(SolverError::InvalidInput(
+            "table breakpoints must be finite and strictly increasing".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Slopes at the breakpoints of the cubic interpolants
+fn slopes(x: &[f64], y: &[f64], method: Interpolation) -> Vec<f64> {
+    let n = x.len();
+    let secant: Vec<f64> = (0..n - 1)
+        .map(|i| (y[i + 1] - y[i]) / (x[i + 1] - x[i]))
+        .collect();
+    match method {
+        Interpolation::Linear => vec![],
+        Interpolation::CubicSpline => {
+            // second derivatives from the tridiagonal system, zero at the ends
+            let mut m = vec![0.0; n];
+            if n > 2 {
+                let h: Vec<f64> = (0..n - 1).map(|i| x[i + 1] - x[i]).collect();
+                let mut diag = vec![0.0; n];
+                let mut rhs = vec![0.0; n];
+                for i in 1..n - 1 {
+                    diag[i] = 2.0 * (h[i - 1] + h[i]);
+                    rhs[i] = 6.0 * (secant[i] - secant[i - 1]);
+                }
+                // forward elimination and back substitution (Thomas algorithm)
+                for i in 2..n - 1 {
+                    let w = h[i - 1] / diag[i - 1];
+                    diag[i] -= w * h[i - 1];
+                    rhs[i] -= w * rhs[i - 1];
+                }
+                for i in (1..n - 1).rev() {
+                    m[i] = (rhs[i] - h[i] * m[i + 1]) / diag[i];
+                }
+            }
+            (0..n)
+                .map(|i| {
+                    if i < n - 1 {
+                        let h = x[i + 1] - x[i];
+                        secant[i] - h * (2.0 * m[i] + m[i + 1]) / 6.0
+                    } else {
+                        let h = x[i] - x[i - 1];
+                        secant[i - 1] + h * (m[i - 1] + 2.0 * m[i]) / 6.0
+                    }
+                })
+                .collect()
+        }
+        Interpolation::Akima => {
+            // secants extended by two on each side
+            let k = secant.len();
+            let mut m = vec![0.0; k + 4];
+            m[2..k + 2].copy_from_slice(&secant);
+            m[1] = 2.0 * m[2] - m[3.min(k + 1)];
+            m[0] = 2.0 * m[1] - m[2];
+            m[k + 2] = 2.0 * m[k + 1] - m[k];
+            m[k + 3] = 2.0 * m[k + 2] - m[k + 1];
+            (0..n)
+                .map(|i| {
+                    let (m0, m1, m2, m3) = (m[i], m[i + 1], m[i + 2], m[i + 3]);
+                    let (w1, w2) = ((m3 - m2).abs(), (m1 - m0).abs());
+                    if w1 + w2 == 0.0 {
+                        0.5 * (m1 + m2)
+                    } else {
+                        (w1 * m1 + w2 * m2) / (w1 + w2)
+                    }
+                })
+                .collect()
+        }
+    }
+}
+
+/// Evaluates the interpolant through `(x, y)` with the given slopes, holding
+/// the end values outside the breakpoints
+fn evaluate(x: &[f64], y: &[f64], slopes: &[f64], xq: f64) -> f64 {
+    let (i, [[w0, w1], [d0, d1]]) = weights(x, xq, !slopes.is_empty());
+    let mut v = w0 * y[i] + w1 * y[i + 1];
+    if !slopes.is_empty() {
+        v += d0 * slopes[i] + d1 * slopes[i + 1];
+    }
+    v
+}
+
+/// The interval `[x[i], x[i + 1]]` holding `xq`, clamped to the breakpoints,
+/// with the weights of the values and the slopes at its ends
+fn weights(x: &[f64], xq: f64, cubic: bool) -> (usize, [[f64; 2]; 2]) {
+    let n = x.len();
+    let xq = xq.clamp(x[0], x[n - 1]);
+    let i = x.partition_point(|&v| v <= xq).clamp(1, n - 1) - 1;
+    let h = x[i + 1] - x[i];
+    let s = (xq - x[i]) / h;
+    if !cubic {
+        return (i, [[1.0 - s, s], [0.0, 0.0]]);
+    }
+    let h00 = (1.0 + 2.0 * s) * (1.0 - s) * (1.0 - s);
+    let h10 = s * (1.0 - s) * (1.0 - s);
+    let h01 = s * s * (3.0 - 2.0 * s);
+    let h11 = s * s * (s - 1.0);
+    (i, [[h00, h01], [h10 * h, h11 * h]])
+}
+
+/// A function of one variable tabulated at breakpoints. Outside the
+/// breakpoints the end values are held.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table1 {
+    x: Vec<f64>,
+    y: Vec<f64>,
+    slopes: Vec<f64>,
+    method: Interpolation,
+}
+
+impl Table1 {
+    pub fn new(x: Vec<f64>, y: Vec<f64>, method: Interpolation) -> Result<Self, SolverError> {
+        check_axis(&x)?;
+        if y.len() != x.len() {
+            return Err(SolverError::InvalidInput(
+                "table values must match the breakpoints".to_string(),
+            ));
+        }
+        let slopes = slopes(&x, &y, method);
+        Ok(Self {
+            x,
+            y,
+            slopes,
+            method,
+        })
+    }
+
+    pub fn method(&self) -> Interpolation {
+        self.method
+    }
+
+    pub fn breakpoints(&self) -> &[f64] {
+        &self.x
+    }
+
+    pub fn eval(&self, x: f64) -> f64 {
+        evaluate(&self.x, &self.y, &self.slopes, x)
+    }
+}
+
+/// Slopes along `axis` of `values` on the grid spanned by `axes`
+fn slopes_along(axes: &[Vec<f64>], axis: usize, values: &[f64], method: Interpolation) -> Vec<f64> {
+    let x = &axes[axis];
+    let stride: usize = axes[axis + 1..].iter().map(Vec::len).product();
+    let mut along = vec![0.0; values.len()];
+    for start in 0..values.len() {
+        if !(start / stride).is_multiple_of(x.len()) {
+            continue;
+        }
+        let line: Vec<f64> = (0..x.len()).map(|i| values[start + i * stride]).collect();
+        for (i, slope) in slopes(x, &line, method).into_iter().enumerate() {
+            along[start + i * stride] = slope;
+        }
+    }
+    along
+}
+
+/// A function of several variables tabulated on a rectangular grid, evaluated
+/// by interpolating along each axis in turn. Values are stored with the last
+/// axis varying fastest. Outside the grid the values on its boundary are held.
+/// The slopes of the cubic methods are computed once, when the table is built.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableNd {
+    axes: Vec<Vec<f64>>,
+    /// The values, then for the cubic methods their slopes along each subset
+    /// of the axes, indexed by a bit mask of the axes
+    derivatives: Vec<Vec<f64>>,
+    method: Interpolation,
+}
+
+impl TableNd {
+    pub fn new(
+        axes: Vec<Vec<f64>>,
+        values: Vec<f64>,
+        method: Interpolation,
+    ) -> Result<Self, SolverError> {
+        if axes.is_empty() {
+            return Err(SolverError::InvalidInput(
+                "a table needs at least one axis".to_string(),
+            ));
+        }
+        for axis in &axes {
+            check_axis(axis)?;
+        }
+        if values.len() != axes.iter().map(Vec::len).product::<usize>() {
+            return Err(SolverError::InvalidInput(
+                "table values must match the grid".to_string(),
+            ));
+        }
+        let mut derivatives = vec![values];
+        if method != Interpolation::Linear {
+            for mask in 1..1usize << axes.len() {
+                let axis = mask.trailing_zeros() as usize;
+                let along = slopes_along(&axes, axis, &derivatives[mask & !(1 << axis)], method);
+                derivatives.push(along);
+            }
+        }
+        Ok(Self {
+            axes,
+            derivatives,
+            method,
+        })
+    }
+
+    /// A table of two variables with `values[(i, j)]` at `(x[i], y[j])`
+    pub fn grid2(
+        x: Vec<f64>,
+        y: Vec<f64>,
+        values: &DMatrix<f64>,
+        method: Interpolation,
+    ) -> Result<Self, SolverError> {
+        let values = values.transpose().as_slice().to_vec();
+        Self::new(vec![x, y], values, method)
+    }
+
+    pub fn axes(&self) -> &[Vec<f64>] {
+        &self.axes
+    }
+
+    /// Panics if `point` does not have one coordinate per axis
+    pub fn eval(&self, point: &[f64]) -> f64 {
+        assert_eq!(
+            point.len(),
+            self.axes.len(),
+            "one coordinate per table axis"
+        );
+        let cubic = self.method != Interpolation::Linear;
+        let brackets: Vec<_> = self
+            .axes
+            .iter()
+            .zip(point)
+            .map(|(x, &xq)| weights(x, xq, cubic))
+            .collect();
+        // tensor product of the one-dimensional interpolants, summed over the
+        // corners of the grid cell and the derivatives stored at them
+        let mut v = 0.0;
+        for (mask, derivative) in self.derivatives.iter().enumerate() {
+            for corner in 0..1usize << self.axes.len() {
+                let mut w = 1.0;
+                let mut index = 0;
+                for (k, (x, (i, weights))) in self.axes.iter().zip(&brackets).enumerate() {
+                    let c = (corner >> k) & 1;
+                    w *= weights[(mask >> k) & 1][c];
+                    index = index * x.len() + i + c;
+                }
+                v += w * derivative[index];
+            }
+        }
+        v
+    }
+
+    /// `eval` for a point held in a vector
+    pub fn eval_vector(&self, point: &DVector<f64>) -> f64 {
+        self.eval(point.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table1_methods() {
+        let x: Vec<f64> = (0..21).map(|i| 0.3 * i as f64).collect();
+        let y: Vec<f64> = x.iter().map(|v| v.sin()).collect();
+        for (method, tol) in [
+            (Interpolation::Linear, 2e-2),
+            (Interpolation::CubicSpline, 5e-3),
+            (Interpolation::Akima, 5e-3),
+        ] {
+            let table = Table1::new(x.clone(), y.clone(), method).unwrap();
+            for i in 0..100 {
+                let v = 0.06 * i as f64;
+                assert!(
+                    (table.eval(v) - v.sin()).abs() < tol,
+                    "{:?} at {}",
+                    method,
+                    v
+                );
+            }
+            assert_eq!(table.eval(x[7]), y[7]);
+            assert_eq!(table.eval(-1.0), y[0]);
+            assert_eq!(table.eval(10.0), y[20]);
+        }
+    }
+
+    #[test]
+    fn test_akima_does_not_overshoot() {
+        let x: Vec<f64> = (0..8).map(|i| i as f64).collect();
+        let y = vec![0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0];
+        let akima = Table1::new(x.clone(), y.clone(), Interpolation::Akima).unwrap();
+        let spline = Table1::new(x, y, Interpolation::CubicSpline).unwrap();
+        let samples = (0..71).map(|i| 0.1 * i as f64);
+        assert!(samples
+            .clone()
+            .all(|v| (0.0..=1.0).contains(&akima.eval(v))));
+        assert!(samples.clone().any(|v| spline.eval(v) > 1.0));
+        assert!(Table1::new(vec![0.0, 0.0], vec![1.0, 2.0], Interpolation::Linear).is_err());
+    }
+
+    #[test]
+    fn test_table_nd() {
+        let axes = vec![vec![0.0, 1.0, 2.0], vec![-1.0, 0.5, 1.0], vec![0.0, 2.0]];
+        let f = |p: &[f64]| 1.0 + p[0] - 2.0 * p[1] + 0.5 * p[2];
+        let mut values = vec![];
+        for &a in &axes[0] {
+            for &b in &axes[1] {
+                for &c in &axes[2] {
+                    values.push(f(&[a, b, c]));
+                }
+            }
+        }
+        let point = [1.3, 0.1, 0.7];
+        for method in [
+            Interpolation::Linear,
+            Interpolation::CubicSpline,
+            Interpolation::Akima,
+        ] {
+            let table = TableNd::new(axes.clone(), values.clone(), method).unwrap();
+            assert!((table.eval(&point) - f(&point)).abs() < 1e-12);
+        }
+
+        // x y is bilinear, so linear interpolation is exact on a 2D grid
+        let (x, y) = (vec![0.0, 1.0, 3.0], vec![0.0, 2.0]);
+        let z = DMatrix::from_fn(3, 2, |i, j| x[i] * y[j]);
+        let table = TableNd::grid2(x, y, &z, Interpolation::Linear).unwrap();
+        assert!((table.eval(&[2.0, 0.5]) - 1.0).abs() < 1e-12);
+        assert!((table.eval_vector(&DVector::from_vec(vec![5.0, 1.0])) - 3.0).abs() < 1e-12);
+        assert!(TableNd::new(vec![vec![0.0, 1.0]], vec![1.0], Interpolation::Linear).is_err());
+    }
+
+    #[test]
+    fn test_table_nd_spline_matches_nested() {
+        // a spline through splines along the last axis
+        let x: Vec<f64> = vec![0.0, 0.5, 1.5, 2.0];
+        let y: Vec<f64> = vec![-1.0, 0.0, 0.5, 2.0, 3.0];
+        let z = DMatrix::from_fn(4, 5, |i, j| (x[i] * y[j]).sin() + y[j]);
+        let table = TableNd::grid2(x.clone(), y.clone(), &z, Interpolation::CubicSpline).unwrap();
+        let nested = |p: [f64; 2]| {
+            let line: Vec<f64> = (0..x.len())
+                .map(|i| {
+                    let row = z.row(i).iter().copied().collect();
+                    Table1::new(y.clone(), row, Interpolation::CubicSpline)
+                        .unwrap()
+                        .eval(p[1])
+                })
+                .collect();
+            Table1::new(x.clone(), line, Interpolation::CubicSpline)
+                .unwrap()
+                .eval(p[0])
+        };
+        for p in [[0.3, -0.4], [1.9, 2.7], [1.0, 0.5], [-1.0, 4.0]] {
+            assert!((table.eval(&p) - nested(p)).abs() < 1e-12);
+        }
+    }
+}
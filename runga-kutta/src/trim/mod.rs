@@ -0,0 +1,270 @@
+use crate::error::SolverError;
+use crate::jacobian::central_difference;
+use crate::least_squares::{levenberg_marquardt, LeastSquaresOptions};
+use crate::linearize::OperatingPoint;
+use crate::state::{to_dvector, OdeState};
+use nalgebra::{DMatrix, DVector};
+
+/// What a trim solve holds fixed and which derivatives it drives to their
+/// targets. Derivatives target zero unless given a target or ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trim {
+    fixed_states: Vec<usize>,
+    fixed_inputs: Vec<usize>,
+    targets: Vec<(usize, f64)>,
+    ignored: Vec<usize>,
+    /// Largest derivative error accepted as trimmed
+    pub tol: f64,
+    pub options: LeastSquaresOptions,
+}
+
+impl Default for Trim {
+    fn default() -> Self {
+        Self {
+            fixed_states: vec![],
+            fixed_inputs: vec![],
+            targets: vec![],
+            ignored: vec![],
+            tol: 1e-8,
+            options: LeastSquaresOptions::default(),
+        }
+    }
+}
+
+impl Trim {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Holds state component `i` at its initial guess
+    pub fn fix_state(mut self, i: usize) -> Self {
+        self.fixed_states.push(i);
+        self
+    }
+
+    /// Holds input component `i` at its initial guess
+    pub fn fix_input(mut self, i: usize) -> Self {
+        self.fixed_inputs.push(i);
+        self
+    }
+
+    /// Drives derivative `i` to `value` instead of zero, as for a steady climb
+    pub fn target(mut self, i: usize, value: f64) -> Self {
+        self.targets.retain(|&(j, _)| j != i);
+        self.targets.push((i, value));
+        self
+    }
+
+    /// Leaves derivative `i` free, as for a position that grows in cruise
+    pub fn ignore(mut self, i: usize) -> Self {
+        self.ignored.push(i);
+        self
+    }
+
+    pub fn tolerance(mut self, tol: f64) -> Self {
+        self.tol = tol;
+        self
+    }
+
+    pub fn options(mut self, options: LeastSquaresOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+/// A trimmed operating point with diagnostics of the solve
+#[derive(Debug, Clone)]
+pub struct TrimPoint<Tx, Tu> {
+    /// The trimmed states and inputs, ready for `linearize`
+    pub point: OperatingPoint<Tx, Tu>,
+    /// Errors of the constrained derivatives from their targets
+    pub residuals: DVector<f64>,
+    /// Jacobian of the residuals with respect to the free states, then the
+    /// free inputs
+    pub jacobian: DMatrix<f64>,
+    pub iterations: usize,
+    /// Whether every residual is within the tolerance
+    pub converged: bool,
+}
+
+impl<Tx, Tu> TrimPoint<Tx, Tu> {
+    /// Largest residual magnitude
+    pub fn residual(&self) -> f64 {
+        self.residuals.amax()
+    }
+
+    /// Ratio of the largest to the smallest singular value of the Jacobian.
+    /// Large values mean the free variables are poorly determined, so the
+    /// trim point is not unique; it is infinite when there are more free
+    /// variables than constrained derivatives.
+    pub fn condition_number(&self) -> f64 {
+        if self.jacobian.ncols() > self.jacobian.nrows() {
+            return f64::INFINITY;
+        }
+        let sv = self.jacobian.singular_values();
+        let smallest = sv.min();
+        if smallest == 0.0 {
+            f64::INFINITY
+        } else {
+            sv.max() / smallest
+        }
+    }
+}
+
+/// Finds states `x` and inputs `u` with `f(x, u, t, p)` at its targets,
+/// starting from `start`, by Levenberg–Marquardt on the free components; the
+/// time is kept. Trim problems without an exact solution return the least
+/// squares point with `converged` false.
+pub fn trim<Tx: OdeState, Tu: OdeState, Tp>(
+    f: impl Fn(&mut Tx, &Tx, &Tu, f64, &Tp),
+    start: &OperatingPoint<Tx, Tu>,
+    p: &Tp,
+    trim: &Trim,
+) -> Result<TrimPoint<Tx, Tu>, SolverError> {
+    let OperatingPoint { x: x0, u: u0, t } = start;
+    let t = *t;
+    let (n, m) = (x0.dim(), u0.dim());
+    let invalid = |msg: &str| Err(SolverError::InvalidInput(msg.to_string()));
+    if trim.fixed_states.iter().any(|&i| i >= n)
+        || trim.ignored.iter().any(|&i| i >= n)
+        || trim.targets.iter().any(|&(i, _)| i >= n)
+    {
+        return invalid("trim state index out of range");
+    }
+    if trim.fixed_inputs.iter().any(|&i| i >= m) {
+        return invalid("trim input index out of range");
+    }
+    if trim.targets.iter().any(|(i, _)| trim.ignored.contains(i)) {
+        return invalid("trim targets an ignored derivative");
+    }
+    let free_states: Vec<usize> = (0..n).filter(|i| !trim.fixed_states.contains(i)).collect();
+    let free_inputs: Vec<usize> = (0..m).filter(|i| !trim.fixed_inputs.contains(i)).collect();
+    let constrained: Vec<usize> = (0..n).filter(|i| !trim.ignored.contains(i)).collect();
+    if free_states.is_empty() && free_inputs.is_empty() {
+        return invalid("trim needs at least one free variable");
+    }
+    let mut target = DVector::zeros(constrained.len());
+    for &(i, value) in &trim.targets {
+        if let Some(k) = constrained.iter().position(|&c| c == i) {
+            target[k] = value;
+        }
+    }
+
+    let unpack = |z: &DVector<f64>| {
+        let (mut x, mut u) = (x0.clone(), u0.clone());
+        for (k, &i) in free_states.iter().enumerate() {
+            x.set_component(i, z[k]);
+        }
+        for (k, &i) in free_inputs.iter().enumerate() {
+            u.set_component(i, z[free_states.len() + k]);
+        }
+        (x, u)
+    };
+    let mut dx = x0.zeros_like();
+    let mut residuals = |z: &DVector<f64>| {
+        let (x, u) = unpack(z);
+        f(&mut dx, &x, &u, t, p);
+        let dx = to_dvector(&dx);
+        DVector::from_iterator(constrained.len(), constrained.iter().map(|&i| dx[i])) - &target
+    };
+    let z0 = DVector::from_iterator(
+        free_states.len() + free_inputs.len(),
+        free_states
+            .iter()
+            .map(|&i| x0.component(i))
+            .chain(free_inputs.iter().map(|&i| u0.component(i))),
+    );
+    let fit = levenberg_marquardt(
+        |z| {
+            let r = residuals(z);
+            if !r.iter().all(|v| v.is_finite()) {
+                return Err(SolverError::NonFiniteState { t });
+            }
+            let jac = central_difference(z, r.len(), &mut residuals);
+            Ok((r, jac))
+        },
+        z0,
+        &trim.options,
+    )?;
+    let (x, u) = unpack(&fit.x);
+    Ok(TrimPoint {
+        point: OperatingPoint::new(x, u, t),
+        converged: fit.residuals.amax() <= trim.tol,
+        residuals: fit.residuals,
+        jacobian: fit.jacobian,
+        iterations: fit.iterations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linearize::linearize;
+    use nalgebra::Vector2;
+
+    /// Damped pendulum driven by a torque, `p = (g / l, damping)`
+    fn pendulum(dx: &mut Vector2<f64>, x: &Vector2<f64>, u: &f64, _t: f64, p: &Vector2<f64>) {
+        *dx = Vector2::new(x[1], -p[0] * x[0].sin() - p[1] * x[1] + u);
+    }
+
+    #[test]
+    fn test_hold_angle() {
+        // the torque that holds the pendulum at a given angle
+        let p = Vector2::new(9.81, 0.5);
+        let start = OperatingPoint::new(Vector2::new(0.5, 0.3), 0.0, 0.0);
+        let trimmed = trim(pendulum, &start, &p, &Trim::new().fix_state(0)).unwrap();
+        assert!(trimmed.converged);
+        let point = &trimmed.point;
+        assert_eq!(point.x[0], 0.5);
+        assert!(point.x[1].abs() < 1e-10);
+        assert!((point.u - 9.81 * 0.5_f64.sin()).abs() < 1e-8);
+        assert!(trimmed.residual() < 1e-8);
+        assert!(trimmed.condition_number().is_finite());
+
+        // the trimmed point linearizes directly
+        let ss = linearize(pendulum, point, &p);
+        assert!((ss.a[(1, 0)] + 9.81 * 0.5_f64.cos()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_derivative_targets() {
+        // steady rotation at unit rate through a fixed angle, with the angle
+        // derivative left free but its rate set by the target
+        let p = Vector2::new(9.81, 0.5);
+        let start = OperatingPoint::new(Vector2::new(0.2, 0.0), 0.0, 0.0);
+        let spec = Trim::new().fix_state(0).target(0, 1.0);
+        let trimmed = trim(pendulum, &start, &p, &spec).unwrap();
+        assert!(trimmed.converged);
+        assert!((trimmed.point.x[1] - 1.0).abs() < 1e-8);
+        assert!((trimmed.point.u - 9.81 * 0.2_f64.sin() - 0.5).abs() < 1e-8);
+
+        // ignoring the angle derivative leaves the rate undetermined
+        let spec = Trim::new().fix_input(0).ignore(0);
+        let trimmed = trim(pendulum, &start, &p, &spec).unwrap();
+        assert!(trimmed.converged);
+        let x = trimmed.point.x;
+        assert!((p[0] * x[0].sin() + p[1] * x[1]).abs() < 1e-8);
+        assert_eq!(trimmed.condition_number(), f64::INFINITY);
+        let origin = OperatingPoint::new(Vector2::zeros(), 0.0, 0.0);
+        assert!(trim(pendulum, &origin, &p, &Trim::new().fix_state(2)).is_err());
+        let spec = Trim::new().ignore(0).target(0, 1.0);
+        let Err(SolverError::InvalidInput(_)) = trim(pendulum, &origin, &p, &spec) else {
+            panic!("expected a target on an ignored derivative to be rejected");
+        };
+    }
+
+    #[test]
+    fn test_no_equilibrium() {
+        // the derivatives cannot both vanish; the least squares point splits
+        // the difference
+        let f = |dx: &mut Vector2<f64>, x: &Vector2<f64>, u: &f64, _t: f64, _p: &()| {
+            *dx = Vector2::new(x[0] + x[1] - 1.0, x[0] + x[1] - 3.0 + u);
+        };
+        let spec = Trim::new().fix_input(0);
+        let start = OperatingPoint::new(Vector2::zeros(), 0.0, 0.0);
+        let trimmed = trim(f, &start, &(), &spec).unwrap();
+        assert!(!trimmed.converged);
+        assert!((trimmed.residual() - 1.0).abs() < 1e-8);
+        assert!((trimmed.point.x.sum() - 2.0).abs() < 1e-8);
+    }
+}
@@ -0,0 +1,52 @@
+use crate::butcher::{ButcherTableau, MAX_STAGES};
+
+/// Tsitouras 5(4) pair
+pub fn tableau() -> ButcherTableau {
+    let mut a = [[0.0; MAX_STAGES]; MAX_STAGES];
+    a[1][..1].copy_from_slice(&[0.161]);
+    a[2][..2].copy_from_slice(&[-0.008480655492356989, 0.335480655492357]);
+    a[3][..3].copy_from_slice(&[2.897153057105493, -6.359448489975075, 4.3622954328695815]);
+    a[4][..4].copy_from_slice(&[
+        5.325864828439257,
+        -11.748883564062828,
+        7.4955393428898365,
+        -0.09249506636175525,
+    ]);
+    a[5][..5].copy_from_slice(&[
+        5.86145544294642,
+        -12.92096931784711,
+        8.159367898576159,
+        -0.071584973281401,
+        -0.028269050394068383,
+    ]);
+    a[6][..6].copy_from_slice(&[
+        0.09646076681806523,
+        0.01,
+        0.4798896504144996,
+        1.379008574103742,
+        -3.290069515436081,
+        2.324710524099774,
+    ]);
+
+    let mut b = [0.0; MAX_STAGES];
+    b[..7].copy_from_slice(&a[6][..7]);
+
+    let btilde = [
+        0.001780011052226,
+        0.000816434459657,
+        -0.007880878010262,
+        0.144711007173263,
+        -0.582357165452555,
+        0.458082105929187,
+        -1.0 / 66.0,
+    ];
+    let mut b_hat = [0.0; MAX_STAGES];
+    for i in 0..7 {
+        b_hat[i] = b[i] - btilde[i];
+    }
+
+    let mut c = [0.0; MAX_STAGES];
+    c[..7].copy_from_slice(&[0.0, 0.161, 0.327, 0.9, 0.9800255409045097, 1.0, 1.0]);
+
+    ButcherTableau::new(a, b, c, 7, 5).with_embedded(b_hat, 4)
+}
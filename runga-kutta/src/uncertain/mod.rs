@@ -0,0 +1,137 @@
+use crate::error::SolverError;
+use crate::sensitivity::state_transition;
+use crate::solution::Solution;
+use crate::solver::SolverOptions;
+use crate::state::{from_slice, OdeState};
+use nalgebra::{DMatrix, DVector};
+use uncertainty::UncertainValue;
+
+/// A state with the covariance of its components
+#[derive(Debug, Clone, PartialEq)]
+pub struct UncertainState<Ty> {
+    pub mean: Ty,
+    pub covariance: DMatrix<f64>,
+}
+
+impl<Ty: OdeState> UncertainState<Ty> {
+    pub fn new(mean: Ty, covariance: DMatrix<f64>) -> Self {
+        Self { mean, covariance }
+    }
+
+    /// Independent components whose uncertainties are standard deviations.
+    /// `shape` gives the type and size of the state.
+    pub fn from_values(values: &[UncertainValue<f64>], shape: &Ty) -> Self {
+        let mean: Vec<f64> = values.iter().map(|v| v.value).collect();
+        let variance = values.iter().map(|v| v.uncertainty * v.uncertainty);
+        Self {
+            mean: from_slice(shape, &mean),
+            covariance: DMatrix::from_diagonal(&DVector::from_iterator(values.len(), variance)),
+        }
+    }
+
+    /// Each component with its standard deviation as uncertainty
+    pub fn values(&self) -> Vec<UncertainValue<f64>> {
+        (0..self.mean.dim())
+            .map(|i| UncertainValue::new(self.mean.component(i), self.covariance[(i, i)].sqrt()))
+            .collect()
+    }
+}
+
+/// A `Solution` with the state covariance at each saved time
+#[derive(Debug, Clone)]
+pub struct UncertainSolution<Ty> {
+    pub solution: Solution<Ty>,
+    pub covariance: Vec<DMatrix<f64>>,
+}
+
+impl<Ty: OdeState> UncertainSolution<Ty> {
+    pub fn state(&self, i: usize) -> UncertainState<Ty> {
+        UncertainState::new(self.solution.y[i].clone(), self.covariance[i].clone())
+    }
+
+    pub fn last(&self) -> Option<UncertainState<Ty>> {
+        (!self.solution.is_empty()).then(|| self.state(self.solution.len() - 1))
+    }
+}
+
+/// Integrates the mean of an uncertain initial state and propagates its
+/// covariance to first order, `P(t) = Stm(t) P0 Stm(t)'`. Unlike applying
+/// `UncertainValue` arithmetic inside the model, this accounts for
+/// correlations between components, so uncertainties can shrink as well as
+/// grow.
+pub fn propagate_uncertainty<Ty: OdeState, Tp>(
+    f: impl Fn(&mut Ty, &Ty, f64, &Tp),
+    y0: UncertainState<Ty>,
+    t0: f64,
+    p: Tp,
+    t_end: f64,
+    options: SolverOptions,
+) -> Result<UncertainSolution<Ty>, SolverError> {
+    let n = y0.mean.dim();
+    if y0.covariance.shape() != (n, n) {
+        return Err(SolverError::InvalidInput(
+            "covariance must be square with the dimension of the state".to_string(),
+        ));
+    }
+    let sol = state_transition(f, y0.mean, t0, p, t_end, options)?;
+    let covariance = sol
+        .stm
+        .iter()
+        .map(|stm| stm * &y0.covariance * stm.transpose())
+        .collect();
+    Ok(UncertainSolution {
+        solution: sol.solution,
+        covariance,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Matrix2, Vector2};
+
+    #[test]
+    fn test_decay_uncertainty() {
+        let f = |dy: &mut f64, y: &f64, _t: f64, k: &f64| *dy = -k * y;
+        let y0 = UncertainState::from_values(&[UncertainValue::new(2.0, 0.1)], &0.0);
+        let options = SolverOptions::default().tolerance(1e-10, 1e-12);
+        let sol = propagate_uncertainty(f, y0, 0.0, 0.5, 2.0, options).unwrap();
+        let y1 = sol.last().unwrap().values()[0];
+        let e = (-1.0_f64).exp();
+        assert!((y1.value - 2.0 * e).abs() < 1e-8);
+        assert!((y1.uncertainty - 0.1 * e).abs() < 1e-8);
+    }
+
+    #[test]
+    fn test_correlated_oscillator() {
+        // a quarter turn keeps an isotropic covariance isotropic and swaps
+        // the variances of an anisotropic one
+        let f = |dy: &mut Vector2<f64>, y: &Vector2<f64>, _t: f64, _p: &()| {
+            *dy = Vector2::new(y[1], -y[0]);
+        };
+        let options = SolverOptions::default().tolerance(1e-10, 1e-12);
+        let values = [UncertainValue::new(1.0, 0.2), UncertainValue::new(0.0, 0.2)];
+        let y0 = UncertainState::from_values(&values, &Vector2::zeros());
+        let t1 = std::f64::consts::FRAC_PI_2;
+        let sol = propagate_uncertainty(f, y0, 0.0, (), t1, options.clone()).unwrap();
+        let p1 = &sol.last().unwrap().covariance;
+        assert!((p1 - Matrix2::identity() * 0.04).abs().max() < 1e-8);
+
+        let p0 = Matrix2::new(0.04, 0.0, 0.0, 0.0001);
+        let y0 = UncertainState::new(
+            Vector2::new(1.0, 0.0),
+            DMatrix::from_iterator(2, 2, p0.iter().copied()),
+        );
+        let sol = propagate_uncertainty(f, y0, 0.0, (), t1, options).unwrap();
+        let values = sol.last().unwrap().values();
+        assert!((values[0].uncertainty - 0.01).abs() < 1e-8);
+        assert!((values[1].uncertainty - 0.2).abs() < 1e-8);
+    }
+
+    #[test]
+    fn test_covariance_shape_checked() {
+        let f = |dy: &mut f64, y: &f64, _t: f64, _p: &()| *dy = -y;
+        let y0 = UncertainState::new(1.0, DMatrix::zeros(2, 2));
+        assert!(propagate_uncertainty(f, y0, 0.0, (), 1.0, SolverOptions::default()).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Angle {
     Degrees,
     Radians,
@@ -5,7 +6,7 @@ pub enum Angle {
 }
 
 impl Angle {
-    fn to_radians(&self, value: f64) -> f64 {
+    fn to_radians(self, value: f64) -> f64 {
         match self {
             Angle::Degrees => value.to_radians(),
             Angle::Radians => value,
@@ -13,6 +14,7 @@ impl Angle {
         }
     }
 
+    #[allow(clippy::wrong_self_convention)]
     fn from_radians(&self, value: f64) -> f64 {
         match self {
             Angle::Degrees => value.to_degrees(),
@@ -1,3 +1,4 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Length {
     Au,
     Centimeters,
@@ -16,7 +17,7 @@ pub enum Length {
 }
 
 impl Length {
-    fn to_meters(&self, value: f64) -> f64 {
+    fn to_meters(self, value: f64) -> f64 {
         match self {
             Length::Au => value * 1.495978707e+11,
             Length::Feet => value * 0.3048,
@@ -35,6 +36,7 @@ impl Length {
         }
     }
 
+    #[allow(clippy::wrong_self_convention)]
     fn from_meters(&self, value: f64) -> f64 {
         match self {
             Length::Au => value / 1.495978707e+11,
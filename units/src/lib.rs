@@ -1,2 +1,5 @@
-mod angle;
-mod length;
\ No newline at end of file
+pub mod angle;
+pub mod length;
+
+pub use angle::Angle;
+pub use length::Length;